@@ -1,3 +1,35 @@
+use std::path::Path;
+
+// RNNoise models from GregorR/rnnoise-models, checked in under models/rnnoise and bundled as
+// resources. Keep in sync with BUNDLED_MODELS in src/noise.rs.
+const RNNOISE_MODELS: [&str; 3] = ["cb.rnnn", "bd.rnnn", "sh.rnnn"];
+const RNNOISE_DIR: &str = "models/rnnoise";
+
+// A checkout without the model files would ship without the default noise model, so release
+// builds stop; other builds warn
+fn check_rnnoise_models() {
+  println!("cargo:rerun-if-changed={}", RNNOISE_DIR);
+  let missing: Vec<&str> = RNNOISE_MODELS
+    .into_iter()
+    .filter(|file| !Path::new(RNNOISE_DIR).join(file).is_file())
+    .collect();
+  if missing.is_empty() {
+    return;
+  }
+
+  let message = format!(
+    "RNNoise models missing from {}: {} (see {}/README.md)",
+    RNNOISE_DIR,
+    missing.join(", "),
+    RNNOISE_DIR
+  );
+  if std::env::var("PROFILE").is_ok_and(|profile| profile == "release") {
+    panic!("{}", message);
+  }
+  println!("cargo:warning={}", message);
+}
+
 fn main() {
+  check_rnnoise_models();
   tauri_build::build()
 }
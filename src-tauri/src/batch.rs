@@ -0,0 +1,381 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use serde::{Deserialize, Serialize};
+use tauri::{State, Window};
+
+use crate::capabilities::CapabilityCache;
+use crate::jobs::{self, JobRegistry};
+use crate::media_type::{self, MediaType};
+use crate::presets::{PresetStore, ResolvedSettings};
+use crate::scanner::{self, ScanOptions};
+use crate::{AudioConversionSettings, ConversionSettings};
+
+pub const PROGRESS_EVENT: &str = "batch-convert-progress";
+const DEFAULT_SUFFIX: &str = "_converted";
+
+// What to do when a file already exists where a converted file would go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingPolicy {
+    #[default]
+    Skip,
+    Overwrite,
+    // Write "<name> (2).<ext>" and so on instead
+    Rename,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRequest {
+    #[serde(default)]
+    pub files: Vec<String>,
+    // Every file under this folder whose relative path matches `pattern` is converted too; every
+    // audio and video file when there is no pattern. Earlier batch outputs are left out.
+    pub folder: Option<String>,
+    pub pattern: Option<String>,
+    // How the folder is walked, like a library scan; hidden files are left out when not set.
+    // .yeyoignore files apply either way.
+    pub scan_options: Option<ScanOptions>,
+    // Taken from the preset when not given
+    pub output_format: Option<String>,
+    // Exactly one of these is applied to every file: inline video or audio settings, or a saved preset
+    pub video_settings: Option<ConversionSettings>,
+    pub audio_settings: Option<AudioConversionSettings>,
+    pub preset: Option<String>,
+    // Where outputs go; next to each input when not set
+    pub output_root: Option<String>,
+    // Recreate the subfolders below the source folder inside the output root
+    #[serde(default)]
+    pub mirror_structure: bool,
+    #[serde(default)]
+    pub existing: ExistingPolicy,
+    // Appended to the file stem; "_converted" when not set
+    pub suffix: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Converted,
+    Skipped,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchFileResult {
+    pub input: String,
+    pub output: Option<String>,
+    pub status: BatchStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchProgress {
+    pub job_id: String,
+    // 1-based position of the file that just finished
+    pub index: usize,
+    pub total: usize,
+    pub result: BatchFileResult,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchReport {
+    pub job_id: String,
+    pub total: usize,
+    pub converted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub cancelled: bool,
+    pub files: Vec<BatchFileResult>,
+}
+
+// Files under `folder` whose path relative to it matches `pattern`, in a stable order. The walk is
+// the library scanner's, so ignore files, the depth limit and the symlink policy apply here too.
+fn folder_files(folder: &Path, pattern: Option<&str>, options: Option<&ScanOptions>) -> Result<Vec<PathBuf>, String> {
+    let mut options = options.cloned().unwrap_or(ScanOptions { include_hidden: false, ..Default::default() });
+    options.include.extend(pattern.map(str::to_string));
+    let mut files = Vec::new();
+    let report = scanner::walk_files(folder, &options, &AtomicBool::new(false), |path| {
+        files.push(path.to_path_buf());
+        true
+    })?;
+    if let Some(error) = report.errors.iter().find(|error| Path::new(&error.path) == folder) {
+        return Err(format!("Failed to read {}: {}", folder.display(), error.message));
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Whether `path` looks like something a batch with `suffix` wrote: "<stem><suffix>.<ext>", or one
+// renamed to "<stem><suffix> (n).<ext>"
+fn is_batch_output(path: &Path, suffix: &str) -> bool {
+    let Some(stem) = path.file_stem().map(|stem| stem.to_string_lossy().to_string()) else {
+        return false;
+    };
+    let stem = match stem.strip_suffix(')').and_then(|rest| rest.rsplit_once(" (")) {
+        Some((base, n)) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => base.to_string(),
+        _ => stem,
+    };
+    !suffix.is_empty() && stem.ends_with(suffix)
+}
+
+fn is_media(path: &Path) -> bool {
+    let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+    matches!(media_type::type_for_extension(&ext), MediaType::Video | MediaType::Audio)
+}
+
+// Deepest folder containing all of `paths`, used to mirror a hand-picked list of files
+fn common_parent(paths: &[PathBuf]) -> Option<PathBuf> {
+    let mut common = paths.first()?.parent()?.to_path_buf();
+    for path in &paths[1..] {
+        while !path.starts_with(&common) {
+            common = common.parent()?.to_path_buf();
+        }
+    }
+    Some(common)
+}
+
+// The first "<stem> (n).<ext>" that is neither on disk nor already taken by this batch
+fn renamed(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|candidate| !candidate.exists() && !taken.contains(candidate))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+// Where the converted `input` goes, and whether it is skipped because that file already exists
+fn plan_output(
+    input: &Path,
+    request: &BatchRequest,
+    output_format: &str,
+    output_root: Option<&Path>,
+    base: Option<&Path>,
+    taken: &HashSet<PathBuf>,
+) -> Result<(PathBuf, bool), String> {
+    let stem = input.file_stem().ok_or("Invalid input file")?.to_string_lossy();
+    let parent = input.parent().ok_or("Cannot determine output directory")?;
+    let dir = match (output_root, base) {
+        (Some(root), Some(base)) if request.mirror_structure => root.join(parent.strip_prefix(base).unwrap_or(Path::new(""))),
+        (Some(root), _) => root.to_path_buf(),
+        (None, _) => parent.to_path_buf(),
+    };
+    let suffix = request.suffix.as_deref().unwrap_or(DEFAULT_SUFFIX);
+    let output = dir.join(format!("{}{}.{}", stem, suffix, output_format));
+
+    // Two inputs of this batch landing on the same name always get renamed
+    let output = if taken.contains(&output) {
+        renamed(&output, taken)
+    } else if output.exists() {
+        match request.existing {
+            ExistingPolicy::Skip => return Ok((output, true)),
+            ExistingPolicy::Overwrite => output,
+            ExistingPolicy::Rename => renamed(&output, taken),
+        }
+    } else {
+        output
+    };
+    if output == input {
+        return Err("The output would overwrite the input".to_string());
+    }
+    // Only now that a conversion will run, so skipped files leave no empty folders behind
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create output directory: {}", e))?;
+    Ok((output, false))
+}
+
+// Converts many files with one preset, one after another, and reports how each one went
+#[tauri::command]
+pub async fn batch_convert(
+    request: BatchRequest,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+    presets: State<'_, PresetStore>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<BatchReport, String> {
+    let job_id = job_id.unwrap_or_else(crate::new_job_id);
+    let job = jobs.reserve(&job_id)?;
+    let settings = presets.resolve(
+        request.video_settings.clone(),
+        request.audio_settings.clone(),
+        request.preset.as_deref(),
+        request.output_format.clone(),
+    )?;
+    capabilities.validate(&settings).into_result()?;
+
+    let mut inputs: Vec<PathBuf> = request.files.iter().map(PathBuf::from).collect();
+    let folder = request.folder.as_deref().filter(|folder| !folder.is_empty()).map(PathBuf::from);
+    if let Some(folder) = &folder {
+        let suffix = request.suffix.as_deref().unwrap_or(DEFAULT_SUFFIX);
+        let found = folder_files(folder, request.pattern.as_deref(), request.scan_options.as_ref())?
+            .into_iter()
+            // Without a pattern only audio and video files are picked up, not the .nfo or .jpg next to them
+            .filter(|path| request.pattern.is_some() || is_media(path))
+            // A rerun must not pick up the outputs of the last one as new inputs
+            .filter(|path| !is_batch_output(path, suffix))
+            .filter(|path| !request.files.iter().any(|file| Path::new(file) == path));
+        inputs.extend(found);
+    }
+
+    let output_root = request.output_root.as_deref().filter(|root| !root.is_empty()).map(PathBuf::from);
+    let base = folder.clone().or_else(|| common_parent(&inputs));
+    // Nor anything else in an output root inside the source folder
+    if let (Some(root), Some(base)) = (&output_root, &base) {
+        if root != base && root.starts_with(base) {
+            inputs.retain(|input| !input.starts_with(root));
+        }
+    }
+    if inputs.is_empty() {
+        return Err("No files to convert".to_string());
+    }
+
+    let output_format = match &settings {
+        ResolvedSettings::Video(_, format) | ResolvedSettings::Audio(_, format) => format.as_str(),
+    };
+    let total = inputs.len();
+    let mut taken: HashSet<PathBuf> = HashSet::new();
+    let mut results: Vec<BatchFileResult> = Vec::new();
+    let mut cancelled = false;
+
+    for (i, input) in inputs.iter().enumerate() {
+        let input_str = input.to_string_lossy().to_string();
+        // Also catches a cancel that came in while no FFmpeg process was running
+        cancelled = cancelled || job.is_cancelled();
+        let result = if cancelled {
+            BatchFileResult { input: input_str, output: None, status: BatchStatus::Cancelled, error: None }
+        } else {
+            match plan_output(input, &request, output_format, output_root.as_deref(), base.as_deref(), &taken) {
+                Ok((output, true)) => BatchFileResult {
+                    input: input_str,
+                    output: Some(output.to_string_lossy().to_string()),
+                    status: BatchStatus::Skipped,
+                    error: Some("Output already exists".to_string()),
+                },
+                Err(e) => BatchFileResult { input: input_str, output: None, status: BatchStatus::Failed, error: Some(e) },
+                Ok((output, false)) => {
+                    taken.insert(output.clone());
+                    let run = match &settings {
+                        ResolvedSettings::Video(video, _) => crate::run_video_conversion(&input_str, &output, video, &window, &jobs, &job_id),
+                        ResolvedSettings::Audio(audio, _) => crate::run_audio_conversion(&input_str, &output, audio, &window, &jobs, &job_id),
+                    };
+                    let output = Some(output.to_string_lossy().to_string());
+                    match run {
+                        Ok(()) => BatchFileResult { input: input_str, output, status: BatchStatus::Converted, error: None },
+                        Err(e) if e == jobs::cancelled_error(&job_id) => {
+                            cancelled = true;
+                            BatchFileResult { input: input_str, output: None, status: BatchStatus::Cancelled, error: None }
+                        }
+                        Err(e) => BatchFileResult { input: input_str, output, status: BatchStatus::Failed, error: Some(e) },
+                    }
+                }
+            }
+        };
+
+        let _ = window.emit(
+            PROGRESS_EVENT,
+            BatchProgress { job_id: job_id.clone(), index: i + 1, total, result: result.clone() },
+        );
+        results.push(result);
+    }
+
+    let count = |status: BatchStatus| results.iter().filter(|result| result.status == status).count();
+    Ok(BatchReport {
+        job_id,
+        total,
+        converted: count(BatchStatus::Converted),
+        skipped: count(BatchStatus::Skipped),
+        failed: count(BatchStatus::Failed),
+        cancelled,
+        files: results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earlier_outputs_are_recognised() {
+        assert!(is_batch_output(Path::new("/v/clip_converted.mp4"), "_converted"));
+        assert!(is_batch_output(Path::new("/v/clip_converted (2).mp4"), "_converted"));
+        assert!(!is_batch_output(Path::new("/v/clip.mp4"), "_converted"));
+        assert!(!is_batch_output(Path::new("/v/clip (2).mp4"), "_converted"));
+        assert!(!is_batch_output(Path::new("/v/clip.mp4"), ""));
+    }
+
+    #[test]
+    fn only_audio_and_video_count_as_media() {
+        assert!(is_media(Path::new("a.MKV")));
+        assert!(is_media(Path::new("a.flac")));
+        assert!(!is_media(Path::new("a.nfo")));
+        assert!(!is_media(Path::new("a.jpg")));
+        assert!(!is_media(Path::new("README")));
+    }
+
+    #[test]
+    fn common_parent_of_a_file_list() {
+        let paths = [PathBuf::from("/m/a/b/x.mp4"), PathBuf::from("/m/a/c/y.mp4"), PathBuf::from("/m/a/z.mp4")];
+        assert_eq!(common_parent(&paths), Some(PathBuf::from("/m/a")));
+        assert_eq!(common_parent(&[]), None);
+    }
+
+    fn touch(root: &Path, relative: &str) {
+        let path = root.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"x").unwrap();
+    }
+
+    fn request(json: &str) -> BatchRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn folder_walk_follows_ignore_files_and_scan_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in ["a.mp4", "sub/b.mp4", "sub/deeper/c.mp4", "skip/d.mp4", ".hidden.mp4", "notes.txt"] {
+            touch(root, file);
+        }
+        std::fs::write(root.join(scanner::IGNORE_FILE), "skip/\n").unwrap();
+        let relative = |files: Vec<PathBuf>| -> Vec<String> {
+            files.iter().map(|file| file.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/")).collect()
+        };
+
+        let files = folder_files(root, Some("**/*.mp4"), None).unwrap();
+        assert_eq!(relative(files), ["a.mp4", "sub/b.mp4", "sub/deeper/c.mp4"]);
+
+        let shallow = ScanOptions { max_depth: Some(1), ..Default::default() };
+        let files = folder_files(root, Some("**/*.mp4"), Some(&shallow)).unwrap();
+        assert_eq!(relative(files), [".hidden.mp4", "a.mp4", "sub/b.mp4"]);
+
+        assert!(folder_files(&root.join("missing"), None, None).is_err());
+    }
+
+    #[test]
+    fn output_folder_is_created_only_for_a_conversion() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("clip.mp4");
+        let taken = HashSet::new();
+
+        touch(dir.path(), "clip_converted.mkv");
+        let (output, skipped) = plan_output(&input, &request("{}"), "mkv", None, None, &taken).unwrap();
+        assert!(skipped);
+        assert_eq!(output, dir.path().join("clip_converted.mkv"));
+
+        let no_suffix = request(r#"{ "suffix": "", "existing": "overwrite" }"#);
+        assert!(plan_output(&input, &no_suffix, "mp4", None, None, &taken).is_err());
+
+        let root = dir.path().join("out");
+        let (output, skipped) = plan_output(&input, &request("{}"), "webm", Some(&root), None, &taken).unwrap();
+        assert!(!skipped);
+        assert_eq!(output, root.join("clip_converted.webm"));
+        assert!(root.is_dir());
+    }
+}
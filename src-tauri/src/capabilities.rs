@@ -0,0 +1,693 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::State;
+
+use crate::create_hidden_command;
+use crate::presets::{PresetStore, ResolvedSettings};
+use crate::target_size::TargetSizeSettings;
+use crate::{AudioConversionSettings, ConversionSettings};
+
+// How long a failed FFmpeg query is remembered before conversions try again
+const RETRY_AFTER: Duration = Duration::from_secs(60);
+const X26X_PRESETS: &[&str] = &[
+    "ultrafast", "superfast", "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow", "placebo",
+];
+const NVENC_PRESETS: &[&str] = &[
+    "p1", "p2", "p3", "p4", "p5", "p6", "p7", "default", "slow", "medium", "fast", "hp", "hq", "bd", "ll", "llhq",
+    "llhp", "lossless", "losslesshp",
+];
+const QSV_PRESETS: &[&str] = &["veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow"];
+const SVT_AV1_PRESETS: &[&str] = &["0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13"];
+// Sample rates the MPEG audio and Opus encoders accept
+const MP3_SAMPLE_RATES: &[u32] = &[8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+const OPUS_SAMPLE_RATES: &[u32] = &[8000, 12000, 16000, 24000, 48000];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Codec {
+    pub name: String,
+    // "video", "audio", "subtitle" or "data"
+    pub kind: String,
+    // The codec this encoder or decoder handles, e.g. h264 for libx264
+    pub codec: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedEntry {
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FfmpegCapabilities {
+    pub version: Option<String>,
+    pub encoders: Vec<Codec>,
+    pub decoders: Vec<Codec>,
+    pub muxers: Vec<NamedEntry>,
+    pub filters: Vec<NamedEntry>,
+    pub pixel_formats: Vec<String>,
+}
+
+impl FfmpegCapabilities {
+    pub fn has_encoder(&self, name: &str) -> bool {
+        self.encoders.iter().any(|codec| codec.name == name)
+    }
+
+    // FFmpeg also takes a codec name for -c and picks the first encoder for it, e.g. mp3 for libmp3lame
+    fn encoder(&self, name: &str) -> Option<&Codec> {
+        self.encoders
+            .iter()
+            .find(|codec| codec.name == name)
+            .or_else(|| self.encoders.iter().find(|codec| codec.codec == name))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    pub valid: bool,
+    // Problems that would make FFmpeg fail
+    pub errors: Vec<String>,
+    // Settings that will be ignored or are unusual
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    fn finish(mut self) -> Self {
+        self.valid = self.errors.is_empty();
+        self
+    }
+
+    // Turns the errors into one message for commands that refuse to run invalid settings
+    pub fn into_result(self) -> Result<(), String> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Invalid conversion settings: {}", self.errors.join("; ")))
+        }
+    }
+}
+
+// What an encoder accepts for the generic quality options
+pub struct EncoderRules {
+    pub crf: Option<(u32, u32)>,
+    pub presets: &'static [&'static str],
+    // libvpx only does constant quality with -crf when the bitrate is set to 0
+    pub zero_bitrate_for_crf: bool,
+}
+
+pub fn encoder_rules(encoder: &str) -> EncoderRules {
+    let (crf, presets, zero_bitrate_for_crf) = match encoder {
+        "libx264" | "libx264rgb" | "libx265" => (Some((0, 51)), X26X_PRESETS, false),
+        "libvpx" => (Some((4, 63)), &[][..], true),
+        "libvpx-vp9" => (Some((0, 63)), &[][..], true),
+        "libaom-av1" => (Some((0, 63)), &[][..], false),
+        "libsvtav1" => (Some((0, 63)), SVT_AV1_PRESETS, false),
+        _ if encoder.ends_with("_nvenc") => (None, NVENC_PRESETS, false),
+        _ if encoder.ends_with("_qsv") => (None, QSV_PRESETS, false),
+        _ => (None, &[][..], false),
+    };
+    EncoderRules { crf, presets, zero_bitrate_for_crf }
+}
+
+// Encoder FFmpeg picks for an output extension when no video codec is given; empty when unknown
+pub fn default_video_encoder(format: &str) -> &'static str {
+    match format {
+        "webm" => "libvpx-vp9",
+        "ogv" => "libtheora",
+        "mp4" | "m4v" | "mkv" | "mov" | "flv" | "ts" => "libx264",
+        _ => "",
+    }
+}
+
+// Codec produced by an encoder, from FFmpeg's own listing when it is known
+fn codec_of(capabilities: Option<&FfmpegCapabilities>, encoder: &str) -> String {
+    if let Some(codec) = capabilities.and_then(|caps| caps.encoder(encoder)) {
+        return codec.codec.clone();
+    }
+    match encoder {
+        "libx264" | "libx264rgb" | "libopenh264" => "h264",
+        "libx265" => "hevc",
+        "libvpx" => "vp8",
+        "libvpx-vp9" => "vp9",
+        "libaom-av1" | "libsvtav1" | "librav1e" => "av1",
+        "libxvid" => "mpeg4",
+        "libmp3lame" | "libshine" => "mp3",
+        "libopus" => "opus",
+        "libvorbis" => "vorbis",
+        "libfdk_aac" => "aac",
+        // Hardware encoders are named <codec>_<api>
+        _ => encoder.split('_').next().unwrap_or(encoder),
+    }
+    .to_string()
+}
+
+// Muxer FFmpeg picks for an output extension
+fn muxer_for(format: &str) -> &str {
+    match format {
+        "mkv" | "mka" => "matroska",
+        "m4a" | "m4b" => "ipod",
+        "aac" => "adts",
+        "ts" | "m2ts" => "mpegts",
+        "wma" | "wmv" => "asf",
+        "oga" => "ogg",
+        other => other,
+    }
+}
+
+// (video codecs, audio codecs) a container can hold; None for containers that take anything.
+// A trailing '*' matches a prefix.
+fn container_codecs(format: &str) -> Option<(&'static [&'static str], &'static [&'static str])> {
+    Some(match format {
+        "mp4" | "m4v" => (
+            &["h264", "hevc", "av1", "vp9", "mpeg4", "mpeg2video"],
+            &["aac", "mp3", "ac3", "eac3", "opus", "flac", "alac"],
+        ),
+        "mov" => (
+            &["h264", "hevc", "prores", "mpeg4", "mjpeg", "av1"],
+            &["aac", "alac", "mp3", "ac3", "pcm_*"],
+        ),
+        "webm" => (&["vp8", "vp9", "av1"], &["opus", "vorbis"]),
+        "avi" => (&["mpeg4", "h264", "mjpeg", "msmpeg4v3"], &["mp3", "ac3", "aac", "pcm_*"]),
+        "flv" => (&["h264", "flv1"], &["aac", "mp3"]),
+        "ts" | "m2ts" => (&["h264", "hevc", "mpeg2video"], &["aac", "mp3", "mp2", "ac3"]),
+        "gif" => (&["gif"], &[]),
+        "mp3" => (&[], &["mp3"]),
+        "m4a" | "m4b" => (&[], &["aac", "alac"]),
+        "aac" => (&[], &["aac"]),
+        "flac" => (&[], &["flac"]),
+        "wav" => (&[], &["pcm_*"]),
+        "ogg" | "oga" => (&[], &["vorbis", "opus", "flac"]),
+        "opus" => (&[], &["opus"]),
+        "wma" => (&[], &["wmav1", "wmav2"]),
+        _ => return None,
+    })
+}
+
+fn allows(list: &[&str], codec: &str) -> bool {
+    list.iter().any(|allowed| match allowed.strip_suffix('*') {
+        Some(prefix) => codec.starts_with(prefix),
+        None => *allowed == codec,
+    })
+}
+
+// "128k", "2.5M", "800000"
+fn valid_bitrate(bitrate: &str) -> bool {
+    let number = bitrate.strip_suffix(['k', 'K', 'm', 'M']).unwrap_or(bitrate);
+    number.parse::<f64>().is_ok_and(|value| value > 0.0)
+}
+
+fn check_container(report: &mut ValidationReport, capabilities: Option<&FfmpegCapabilities>, format: &str) {
+    let Some(caps) = capabilities else { return };
+    let muxer = muxer_for(format);
+    // Other extensions may map to muxers of a different name (png to image2, …), so leave them to FFmpeg
+    if muxer == format && container_codecs(format).is_none() {
+        return;
+    }
+    if !caps.muxers.iter().any(|entry| entry.name == muxer) {
+        report.errors.push(format!("This FFmpeg can't write {} files", format));
+    }
+}
+
+fn check_encoder(
+    report: &mut ValidationReport,
+    capabilities: Option<&FfmpegCapabilities>,
+    encoder: &str,
+    kind: &str,
+    format: &str,
+) {
+    if let Some(caps) = capabilities {
+        match caps.encoder(encoder) {
+            None => {
+                report.errors.push(format!("Encoder {} is not available in this FFmpeg", encoder));
+                return;
+            }
+            Some(codec) if codec.kind != kind => {
+                report.errors.push(format!("{} is a {} encoder, not a {} encoder", encoder, codec.kind, kind));
+                return;
+            }
+            Some(_) => {}
+        }
+    }
+
+    let codec = codec_of(capabilities, encoder);
+    if let Some((video, audio)) = container_codecs(format) {
+        let allowed = if kind == "video" { video } else { audio };
+        if allowed.is_empty() {
+            report.errors.push(format!("{} files can't contain {}", format, kind));
+        } else if !allows(allowed, &codec) {
+            report.errors.push(format!(
+                "{} ({}) can't be stored in {} files; use one of {}",
+                encoder,
+                codec,
+                format,
+                allowed.join(", ")
+            ));
+        }
+    }
+}
+
+pub fn validate_video(
+    capabilities: Option<&FfmpegCapabilities>,
+    settings: &ConversionSettings,
+    output_format: &str,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let format = output_format.to_lowercase();
+    check_container(&mut report, capabilities, &format);
+
+    // Stream copy keeps whatever the input has, which can't be checked from the settings
+    if settings.fast_mode.unwrap_or(false) || settings.video_codec == "copy" {
+        return report.finish();
+    }
+
+    check_encoder(&mut report, capabilities, &settings.video_codec, "video", &format);
+    if settings.audio_codec != "copy" {
+        check_encoder(&mut report, capabilities, &settings.audio_codec, "audio", &format);
+    }
+
+    let rules = encoder_rules(&settings.video_codec);
+    if let Some(crf) = settings.crf {
+        match rules.crf {
+            Some((min, max)) if crf < min || crf > max => {
+                report.errors.push(format!("CRF for {} must be between {} and {}", settings.video_codec, min, max));
+            }
+            Some(_) => {}
+            None => report.warnings.push(format!("{} has no CRF mode; the CRF is ignored", settings.video_codec)),
+        }
+    }
+    if let Some(preset) = &settings.preset {
+        if rules.presets.is_empty() {
+            report.warnings.push(format!("{} has no presets; {} is ignored", settings.video_codec, preset));
+        } else if !rules.presets.contains(&preset.as_str()) {
+            report.errors.push(format!(
+                "Unknown preset {} for {}; use one of {}",
+                preset,
+                settings.video_codec,
+                rules.presets.join(", ")
+            ));
+        }
+    }
+    if let Some(bitrate) = settings.bitrate.as_deref().filter(|bitrate| !bitrate.is_empty()) {
+        if !valid_bitrate(bitrate) {
+            report.errors.push(format!("Invalid video bitrate {}", bitrate));
+        }
+    }
+    if settings.max_height == Some(0) {
+        report.errors.push("Maximum height must be greater than zero".to_string());
+    }
+    if let Some(target) = settings.target_size_mb {
+        if target.is_nan() || target <= 0.0 {
+            report.errors.push("Target size must be greater than zero".to_string());
+        }
+        if settings.crf.is_some() || settings.bitrate.as_deref().is_some_and(|b| !b.is_empty()) {
+            report.warnings.push("A target size overrides the CRF and bitrate".to_string());
+        }
+        if !crate::target_size::supports_two_pass(&settings.video_codec) {
+            report.errors.push(format!(
+                "{} doesn't support two-pass encoding, which a target size needs",
+                settings.video_codec
+            ));
+        }
+        if settings.fast_mode.unwrap_or(false) {
+            report.errors.push("Fast mode copies the streams and can't be combined with a target size".to_string());
+        }
+    } else if settings.fast_mode.unwrap_or(false) && settings.max_height.is_some() {
+        report.warnings.push("Fast mode copies the streams, so the maximum height is ignored".to_string());
+    }
+    report.finish()
+}
+
+// Target size encodes take the same checks as a conversion with a target size
+pub fn validate_target_size(
+    capabilities: Option<&FfmpegCapabilities>,
+    settings: &TargetSizeSettings,
+    output_format: &str,
+) -> ValidationReport {
+    let conversion = ConversionSettings {
+        video_codec: settings.video_codec.clone(),
+        audio_codec: settings.audio_codec.clone(),
+        crf: None,
+        preset: settings.preset.clone(),
+        bitrate: None,
+        fast_mode: None,
+        target_size_mb: Some(settings.target_size_mb),
+        max_height: settings.max_height,
+    };
+    validate_video(capabilities, &conversion, output_format)
+}
+
+pub fn validate_audio(
+    capabilities: Option<&FfmpegCapabilities>,
+    settings: &AudioConversionSettings,
+    output_format: &str,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let format = output_format.to_lowercase();
+    check_container(&mut report, capabilities, &format);
+    if settings.codec == "copy" {
+        return report.finish();
+    }
+    check_encoder(&mut report, capabilities, &settings.codec, "audio", &format);
+
+    let codec = codec_of(capabilities, &settings.codec);
+    if let Some(bitrate) = settings.bitrate.as_deref().filter(|bitrate| !bitrate.is_empty()) {
+        if !valid_bitrate(bitrate) {
+            report.errors.push(format!("Invalid audio bitrate {}", bitrate));
+        } else if codec == "flac" || codec.starts_with("pcm_") {
+            report.warnings.push(format!("{} is lossless; the bitrate is ignored", settings.codec));
+        }
+    }
+    if let Some(rate) = settings.sample_rate {
+        let allowed = match codec.as_str() {
+            "mp3" => Some(MP3_SAMPLE_RATES),
+            "opus" => Some(OPUS_SAMPLE_RATES),
+            _ => None,
+        };
+        if allowed.is_some_and(|rates| !rates.contains(&rate)) || rate == 0 {
+            report.errors.push(format!("{} can't encode at {} Hz", settings.codec, rate));
+        }
+    }
+    if let Some(channels) = settings.channels {
+        let max = if codec == "mp3" { 2 } else { 8 };
+        if channels == 0 || channels > max {
+            report.errors.push(format!("{} supports 1 to {} channels", settings.codec, max));
+        }
+    }
+    if let Some(level) = settings.compression {
+        if codec != "flac" {
+            report.warnings.push("The compression level only applies to FLAC".to_string());
+        } else if level > 12 {
+            report.errors.push("FLAC compression level must be between 0 and 12".to_string());
+        }
+    }
+    report.finish()
+}
+
+fn ffmpeg_output(args: &[&str]) -> Result<String, String> {
+    let output = create_hidden_command("ffmpeg")
+        .arg("-hide_banner")
+        .args(args)
+        .output()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                "FFmpeg not found. Please install FFmpeg and add it to your PATH.".to_string()
+            } else {
+                format!("Failed to execute ffmpeg: {}", e)
+            }
+        })?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg failed: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// Lines of a listing below its "---" separator, split into flags, name and the rest
+fn listing(text: &str) -> Vec<(String, String, String)> {
+    text.lines()
+        .skip_while(|line| !line.trim().starts_with("--"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let flags = parts.next()?.to_string();
+            let name = parts.next()?.to_string();
+            Some((flags, name, parts.collect::<Vec<_>>().join(" ")))
+        })
+        .collect()
+}
+
+fn parse_codecs(text: &str) -> Vec<Codec> {
+    listing(text)
+        .into_iter()
+        .map(|(flags, name, description)| {
+            let kind = match flags.chars().next() {
+                Some('V') => "video",
+                Some('A') => "audio",
+                Some('S') => "subtitle",
+                _ => "data",
+            };
+            // Descriptions end in "(codec h264)" when the encoder's name differs from the codec
+            let codec = description
+                .rsplit_once("(codec ")
+                .and_then(|(_, rest)| rest.strip_suffix(')'))
+                .unwrap_or(&name)
+                .to_string();
+            Codec { name, kind: kind.to_string(), codec, description }
+        })
+        .collect()
+}
+
+fn parse_muxers(text: &str) -> Vec<NamedEntry> {
+    listing(text)
+        .into_iter()
+        .filter(|(flags, _, _)| flags.contains('E'))
+        .flat_map(|(_, names, description)| {
+            names
+                .split(',')
+                .map(|name| NamedEntry { name: name.to_string(), description: description.clone() })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Filter lines look like " TSC adelay  A->A  Delay one or more audio channels."
+fn parse_filters(text: &str) -> Vec<NamedEntry> {
+    text.lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 3 || !parts[2].contains("->") {
+                return None;
+            }
+            Some(NamedEntry { name: parts[1].to_string(), description: parts[3..].join(" ") })
+        })
+        .collect()
+}
+
+fn query() -> Result<FfmpegCapabilities, String> {
+    let version = ffmpeg_output(&["-version"])?
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("ffmpeg version "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(|version| version.to_string());
+    Ok(FfmpegCapabilities {
+        version,
+        encoders: parse_codecs(&ffmpeg_output(&["-encoders"])?),
+        decoders: parse_codecs(&ffmpeg_output(&["-decoders"])?),
+        muxers: parse_muxers(&ffmpeg_output(&["-muxers"])?),
+        filters: parse_filters(&ffmpeg_output(&["-filters"])?),
+        pixel_formats: listing(&ffmpeg_output(&["-pix_fmts"])?).into_iter().map(|(_, name, _)| name).collect(),
+    })
+}
+
+#[derive(Default)]
+struct CacheState {
+    capabilities: Option<FfmpegCapabilities>,
+    // The last failed query; FFmpeg isn't asked again until RETRY_AFTER has passed
+    failure: Option<(String, Instant)>,
+}
+
+// What the installed FFmpeg can do, queried once and kept until a refresh is asked for
+#[derive(Default)]
+pub struct CapabilityCache {
+    state: Mutex<CacheState>,
+}
+
+impl CapabilityCache {
+    pub fn get(&self, refresh: bool) -> Result<FfmpegCapabilities, String> {
+        let mut state = self.state.lock().unwrap();
+        if !refresh {
+            if let Some(capabilities) = &state.capabilities {
+                return Ok(capabilities.clone());
+            }
+            if let Some((error, _)) = state.failure.as_ref().filter(|(_, at)| at.elapsed() < RETRY_AFTER) {
+                return Err(error.clone());
+            }
+        }
+        match query() {
+            Ok(capabilities) => {
+                state.capabilities = Some(capabilities.clone());
+                state.failure = None;
+                Ok(capabilities)
+            }
+            Err(e) => {
+                state.failure = Some((e.clone(), Instant::now()));
+                Err(e)
+            }
+        }
+    }
+
+    // The cached capabilities, if FFmpeg can be queried. Validation without them only applies the
+    // static rules, and the conversion itself reports the missing binary.
+    pub fn current(&self) -> Option<FfmpegCapabilities> {
+        self.get(false).ok()
+    }
+
+    pub fn validate(&self, settings: &ResolvedSettings) -> ValidationReport {
+        let capabilities = self.current();
+        match settings {
+            ResolvedSettings::Video(video, format) => validate_video(capabilities.as_ref(), video, format),
+            ResolvedSettings::Audio(audio, format) => validate_audio(capabilities.as_ref(), audio, format),
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_ffmpeg_capabilities(
+    refresh: Option<bool>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<FfmpegCapabilities, String> {
+    capabilities.get(refresh.unwrap_or(false))
+}
+
+// Checks video or audio settings (inline or a preset name) for an output format without running anything
+#[tauri::command]
+pub async fn validate_conversion_settings(
+    output_format: Option<String>,
+    video_settings: Option<ConversionSettings>,
+    audio_settings: Option<AudioConversionSettings>,
+    preset: Option<String>,
+    presets: State<'_, PresetStore>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<ValidationReport, String> {
+    let settings = presets.resolve(video_settings, audio_settings, preset.as_deref(), output_format)?;
+    Ok(capabilities.validate(&settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10 (codec h264)
+ V....D h264_nvenc           NVIDIA NVENC H.264 encoder (codec h264)
+ A....D aac                  AAC (Advanced Audio Coding)
+ A....D libmp3lame           libmp3lame MP3 (MPEG audio layer 3) (codec mp3)
+ S..... srt                  SubRip subtitle
+";
+
+    fn capabilities() -> FfmpegCapabilities {
+        FfmpegCapabilities {
+            version: Some("7.0".to_string()),
+            encoders: parse_codecs(ENCODERS),
+            decoders: Vec::new(),
+            muxers: parse_muxers(" D. = Demuxing\n E. = Muxing\n --\n  E mp4             MP4 (MPEG-4 Part 14)\n DE matroska,webm  Matroska / WebM\n D  mov,mp4,m4a     QuickTime / MOV\n"),
+            filters: Vec::new(),
+            pixel_formats: Vec::new(),
+        }
+    }
+
+    fn video(codec: &str) -> ConversionSettings {
+        ConversionSettings {
+            video_codec: codec.to_string(),
+            audio_codec: "aac".to_string(),
+            crf: None,
+            preset: None,
+            bitrate: None,
+            fast_mode: None,
+            target_size_mb: None,
+            max_height: None,
+        }
+    }
+
+    #[test]
+    fn codec_listing() {
+        let codecs = parse_codecs(ENCODERS);
+        let names: Vec<(&str, &str, &str)> = codecs
+            .iter()
+            .map(|codec| (codec.name.as_str(), codec.kind.as_str(), codec.codec.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("libx264", "video", "h264"),
+                ("h264_nvenc", "video", "h264"),
+                ("aac", "audio", "aac"),
+                ("libmp3lame", "audio", "mp3"),
+                ("srt", "subtitle", "srt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn muxer_listing_only_keeps_muxers() {
+        let names: Vec<String> = capabilities().muxers.into_iter().map(|entry| entry.name).collect();
+        assert_eq!(names, ["mp4", "matroska", "webm"]);
+    }
+
+    #[test]
+    fn filter_listing() {
+        let text = "Filters:\n  T.. = Timeline support\n  ------\n TSC adelay            A->A       Delay one or more audio channels.\n ... amix              N->A       Audio mixing.\n";
+        let filters = parse_filters(text);
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[0].name, "adelay");
+        assert_eq!(filters[0].description, "Delay one or more audio channels.");
+        assert_eq!(filters[1].name, "amix");
+    }
+
+    #[test]
+    fn encoders_are_found_by_codec_name_too() {
+        let caps = capabilities();
+        assert_eq!(caps.encoder("mp3").map(|codec| codec.name.as_str()), Some("libmp3lame"));
+        assert!(caps.encoder("libvpx-vp9").is_none());
+    }
+
+    #[test]
+    fn conversion_checks() {
+        let caps = capabilities();
+        assert!(validate_video(Some(&caps), &video("libx264"), "mp4").valid);
+
+        let report = validate_video(Some(&caps), &video("libvpx-vp9"), "mp4");
+        assert!(!report.valid);
+
+        let mut settings = video("libx264");
+        settings.crf = Some(60);
+        settings.preset = Some("warp".to_string());
+        assert_eq!(validate_video(Some(&caps), &settings, "mp4").errors.len(), 2);
+
+        settings = video("h264_nvenc");
+        settings.target_size_mb = Some(8.0);
+        assert!(!validate_video(Some(&caps), &settings, "mp4").valid);
+    }
+
+    #[test]
+    fn known_encoders_are_checked_without_a_capability_query() {
+        // The "AVI XviD" preset of the converter
+        let mut settings = video("libxvid");
+        settings.audio_codec = "libmp3lame".to_string();
+        assert!(validate_video(None, &settings, "avi").valid);
+        assert!(!validate_video(None, &settings, "webm").valid);
+    }
+
+    #[test]
+    fn target_size_checks() {
+        let settings = TargetSizeSettings {
+            target_size_mb: 25.0,
+            video_codec: "libx264".to_string(),
+            audio_codec: "aac".to_string(),
+            audio_bitrate_kbps: None,
+            preset: Some("slow".to_string()),
+            max_height: Some(720),
+        };
+        assert!(validate_target_size(None, &settings, "mp4").valid);
+        let broken = TargetSizeSettings { target_size_mb: 0.0, ..settings };
+        assert!(!validate_target_size(None, &broken, "mp4").valid);
+    }
+
+    #[test]
+    fn bitrates() {
+        assert!(valid_bitrate("128k"));
+        assert!(valid_bitrate("2.5M"));
+        assert!(valid_bitrate("800000"));
+        assert!(!valid_bitrate("fast"));
+        assert!(!valid_bitrate("0k"));
+    }
+}
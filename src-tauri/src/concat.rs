@@ -0,0 +1,501 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::{State, Window};
+
+use crate::capabilities::{self, CapabilityCache, FfmpegCapabilities, ValidationReport};
+use crate::ffmpeg_progress;
+use crate::jobs::JobRegistry;
+use crate::probe::{self, MediaProbe};
+use crate::ConversionSettings;
+
+// Frame rates closer than this are treated as the same (29.97 reported as 30000/1001 vs 29.97)
+const FPS_TOLERANCE: f64 = 0.01;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ConcatOptions {
+    // Full output path; defaults to "<first input>_joined.<ext>" in the output directory
+    pub output_path: Option<String>,
+    pub output_directory: Option<String>,
+    // Re-encode even when the inputs could be joined as they are
+    pub force_reencode: bool,
+    // Seconds of video and audio crossfade between clips; 0 for hard cuts
+    pub crossfade: f64,
+    // Output parameters when normalising; taken from the first input when not given
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<f64>,
+    pub sample_rate: u32,
+    pub channels: u32,
+    // Encoders when normalising; without a codec FFmpeg picks the container's default
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub crf: Option<u32>,
+    pub preset: Option<String>,
+}
+
+impl Default for ConcatOptions {
+    fn default() -> Self {
+        ConcatOptions {
+            output_path: None,
+            output_directory: None,
+            force_reencode: false,
+            crossfade: 0.0,
+            width: None,
+            height: None,
+            fps: None,
+            sample_rate: 48000,
+            channels: 2,
+            video_codec: None,
+            audio_codec: None,
+            crf: Some(20),
+            preset: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConcatMethod {
+    // Concat demuxer with stream copy
+    Copy,
+    // Normalised and re-encoded through the concat or xfade filters
+    Filter,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcatResult {
+    pub output: String,
+    pub method: ConcatMethod,
+    // Why the inputs couldn't be stream copied, one line per difference
+    pub mismatches: Vec<String>,
+    pub duration: f64,
+}
+
+// Line for FFmpeg's concat demuxer list; single quotes are closed, escaped and reopened
+pub fn concat_list_entry(path: &Path) -> String {
+    format!("file '{}'\n", path.to_string_lossy().replace('\'', "'\\''"))
+}
+
+// The stream parameters that have to agree for the concat demuxer to produce a valid file
+#[derive(Debug, Clone, PartialEq)]
+struct StreamSignature {
+    video: Option<(String, u32, u32, String)>,
+    fps: Option<f64>,
+    audio: Option<(String, u32, u32)>,
+}
+
+impl StreamSignature {
+    fn of(media: &MediaProbe) -> Self {
+        let video = media.primary_video();
+        let audio = media.primary_audio();
+        StreamSignature {
+            video: video.and_then(|stream| {
+                let info = stream.video.as_ref()?;
+                Some((
+                    stream.codec_name.clone().unwrap_or_default(),
+                    info.width,
+                    info.height,
+                    info.pixel_format.clone().unwrap_or_default(),
+                ))
+            }),
+            fps: video.and_then(|stream| stream.video.as_ref()).and_then(|info| info.avg_frame_rate.or(info.frame_rate)),
+            audio: audio.map(|stream| {
+                let info = stream.audio.as_ref();
+                (
+                    stream.codec_name.clone().unwrap_or_default(),
+                    info.and_then(|a| a.sample_rate).unwrap_or(0),
+                    info.and_then(|a| a.channels).unwrap_or(0),
+                )
+            }),
+        }
+    }
+
+    // Human readable differences from the first input
+    fn differences(&self, first: &StreamSignature, name: &str) -> Vec<String> {
+        let mut differences = Vec::new();
+        match (&first.video, &self.video) {
+            (Some(a), Some(b)) => {
+                if a.0 != b.0 {
+                    differences.push(format!("{}: video codec {} instead of {}", name, b.0, a.0));
+                }
+                if (a.1, a.2) != (b.1, b.2) {
+                    differences.push(format!("{}: resolution {}x{} instead of {}x{}", name, b.1, b.2, a.1, a.2));
+                }
+                if a.3 != b.3 {
+                    differences.push(format!("{}: pixel format {} instead of {}", name, b.3, a.3));
+                }
+            }
+            (Some(_), None) => differences.push(format!("{}: has no video", name)),
+            (None, Some(_)) => differences.push(format!("{}: has video while the first input doesn't", name)),
+            (None, None) => {}
+        }
+        if let (Some(a), Some(b)) = (first.fps, self.fps) {
+            if (a - b).abs() > FPS_TOLERANCE {
+                differences.push(format!("{}: {:.3} fps instead of {:.3}", name, b, a));
+            }
+        }
+        match (&first.audio, &self.audio) {
+            (Some(a), Some(b)) => {
+                if a.0 != b.0 {
+                    differences.push(format!("{}: audio codec {} instead of {}", name, b.0, a.0));
+                }
+                if a.1 != b.1 {
+                    differences.push(format!("{}: {} Hz audio instead of {} Hz", name, b.1, a.1));
+                }
+                if a.2 != b.2 {
+                    differences.push(format!("{}: {} audio channels instead of {}", name, b.2, a.2));
+                }
+            }
+            (Some(_), None) => differences.push(format!("{}: has no audio", name)),
+            (None, Some(_)) => differences.push(format!("{}: has audio while the first input doesn't", name)),
+            (None, None) => {}
+        }
+        differences
+    }
+}
+
+fn channel_layout(channels: u32) -> &'static str {
+    match channels {
+        1 => "mono",
+        6 => "5.1",
+        8 => "7.1",
+        _ => "stereo",
+    }
+}
+
+// Output size and frame rate: the ones asked for, or those of the first input that has video
+pub fn output_params(inputs: &[MediaProbe], options: &ConcatOptions) -> (u32, u32, f64) {
+    let first_video = inputs.iter().find_map(|media| media.primary_video()).and_then(|s| s.video.as_ref());
+    let width = options.width.or(first_video.map(|v| v.width)).unwrap_or(1280);
+    let height = options.height.or(first_video.map(|v| v.height)).unwrap_or(720);
+    // Encoders want even dimensions
+    let (width, height) = (width & !1, height & !1);
+    let fps = options
+        .fps
+        .or(first_video.and_then(|v| v.avg_frame_rate.or(v.frame_rate)))
+        .filter(|fps| *fps > 0.0)
+        .unwrap_or(30.0);
+    (width, height, fps)
+}
+
+// Scales, pads and resamples every input to the same parameters, labelled [v<i>] and [a<i>].
+// Inputs lacking video or audio get black frames or silence so every clip has both.
+pub fn normalize_filters(
+    inputs: &[MediaProbe],
+    durations: &[f64],
+    options: &ConcatOptions,
+    has_video: bool,
+    has_audio: bool,
+) -> String {
+    let (width, height, fps) = output_params(inputs, options);
+    let layout = channel_layout(options.channels);
+
+    let mut filter = String::new();
+    for (i, media) in inputs.iter().enumerate() {
+        if has_video {
+            match media.primary_video() {
+                Some(stream) => filter.push_str(&format!(
+                    "[{}:{}]scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={fps},format=yuv420p,settb=AVTB[v{}];",
+                    i, stream.index, i, w = width, h = height, fps = fps
+                )),
+                None => filter.push_str(&format!(
+                    "color=c=black:s={}x{}:r={}:d={:.3},format=yuv420p,settb=AVTB[v{}];",
+                    width, height, fps, durations[i], i
+                )),
+            }
+        }
+        if has_audio {
+            match media.primary_audio() {
+                Some(stream) => filter.push_str(&format!(
+                    "[{}:{}]aresample={},aformat=sample_fmts=fltp:channel_layouts={}[a{}];",
+                    i, stream.index, options.sample_rate, layout, i
+                )),
+                None => filter.push_str(&format!(
+                    "anullsrc=r={}:cl={},atrim=duration={:.3}[a{}];",
+                    options.sample_rate, layout, durations[i], i
+                )),
+            }
+        }
+    }
+    filter
+}
+
+// Builds the filter graph that normalises the inputs and joins them, with xfade/acrossfade between
+// clips when a crossfade is asked for
+pub fn build_filter(
+    inputs: &[MediaProbe],
+    durations: &[f64],
+    options: &ConcatOptions,
+    has_video: bool,
+    has_audio: bool,
+) -> String {
+    let mut filter = normalize_filters(inputs, durations, options, has_video, has_audio);
+    if options.crossfade > 0.0 {
+        // Each xfade starts `crossfade` seconds before the end of everything joined so far
+        let mut offset = 0.0;
+        let mut video_label = "v0".to_string();
+        let mut audio_label = "a0".to_string();
+        for i in 1..inputs.len() {
+            offset += durations[i - 1] - options.crossfade;
+            let last = i == inputs.len() - 1;
+            if has_video {
+                let out = if last { "v".to_string() } else { format!("vx{}", i) };
+                filter.push_str(&format!(
+                    "[{}][v{}]xfade=transition=fade:duration={:.3}:offset={:.3}[{}];",
+                    video_label, i, options.crossfade, offset, out
+                ));
+                video_label = out;
+            }
+            if has_audio {
+                let out = if last { "a".to_string() } else { format!("ax{}", i) };
+                filter.push_str(&format!("[{}][a{}]acrossfade=d={:.3}[{}];", audio_label, i, options.crossfade, out));
+                audio_label = out;
+            }
+        }
+        filter.pop();
+    } else {
+        for i in 0..inputs.len() {
+            if has_video {
+                filter.push_str(&format!("[v{}]", i));
+            }
+            if has_audio {
+                filter.push_str(&format!("[a{}]", i));
+            }
+        }
+        filter.push_str(&format!(
+            "concat=n={}:v={}:a={}",
+            inputs.len(),
+            has_video as u8,
+            has_audio as u8
+        ));
+        if has_video {
+            filter.push_str("[v]");
+        }
+        if has_audio {
+            filter.push_str("[a]");
+        }
+    }
+    filter
+}
+
+fn encoder_args(options: &ConcatOptions, output: &Path, has_video: bool, has_audio: bool) -> Vec<String> {
+    let format = output.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let mut args = Vec::new();
+    if has_video {
+        if let Some(codec) = &options.video_codec {
+            args.extend(["-c:v".to_string(), codec.clone()]);
+        }
+        // Only pass the quality options the encoder understands, like loop_media does
+        let encoder = options.video_codec.as_deref().unwrap_or_else(|| capabilities::default_video_encoder(&format));
+        let rules = capabilities::encoder_rules(encoder);
+        if let (Some(crf), Some(_)) = (options.crf, rules.crf) {
+            args.extend(["-crf".to_string(), crf.to_string()]);
+            if rules.zero_bitrate_for_crf {
+                args.extend(["-b:v".to_string(), "0".to_string()]);
+            }
+        }
+        if let Some(preset) = options.preset.as_ref().filter(|_| !rules.presets.is_empty()) {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+    }
+    if has_audio {
+        if let Some(codec) = &options.audio_codec {
+            args.extend(["-c:a".to_string(), codec.clone()]);
+        }
+        args.extend(["-b:a".to_string(), "192k".to_string()]);
+    }
+    args
+}
+
+// Checks the encoders of the filter path against the output container. Codecs left to FFmpeg's
+// defaults are only checked where the default is known.
+fn validate_encode(capabilities: Option<&FfmpegCapabilities>, options: &ConcatOptions, output: &Path) -> ValidationReport {
+    let format = output.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let video_codec = options
+        .video_codec
+        .clone()
+        .or_else(|| Some(capabilities::default_video_encoder(&format).to_string()).filter(|codec| !codec.is_empty()))
+        // "copy" skips the encoder checks
+        .unwrap_or_else(|| "copy".to_string());
+    let settings = ConversionSettings {
+        video_codec,
+        audio_codec: options.audio_codec.clone().unwrap_or_else(|| "copy".to_string()),
+        crf: options.crf,
+        preset: options.preset.clone(),
+        bitrate: None,
+        fast_mode: None,
+        target_size_mb: None,
+        max_height: None,
+    };
+    capabilities::validate_video(capabilities, &settings, &format)
+}
+
+// Clips between two others fade in and out, so like looped clips they need to be longer than two
+// crossfades or the fades overlap; the first and last clip only fade on one side
+fn check_crossfade(durations: &[f64], crossfade: f64) -> Result<(), String> {
+    if crossfade <= 0.0 {
+        return Ok(());
+    }
+    let last = durations.len().saturating_sub(1);
+    for (index, duration) in durations.iter().enumerate() {
+        let fades = if index == 0 || index == last { 1.0 } else { 2.0 };
+        if crossfade * fades >= *duration {
+            return Err(format!(
+                "Crossfade of {:.2}s is too long for input {} ({:.2}s); clips in the middle need more than two crossfades",
+                crossfade,
+                index + 1,
+                duration
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Joins the inputs in order into one file. Inputs with matching streams are stream copied through the
+// concat demuxer; anything else is normalised to the first input's parameters and re-encoded.
+#[tauri::command]
+pub async fn concat_media(
+    inputs: Vec<String>,
+    options: Option<ConcatOptions>,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<ConcatResult, String> {
+    let job_id = job_id.unwrap_or_else(crate::new_job_id);
+    let options = options.unwrap_or_default();
+    if inputs.len() < 2 {
+        return Err("At least two inputs are needed to concatenate".to_string());
+    }
+
+    let probes: Vec<MediaProbe> = inputs
+        .iter()
+        .map(|input| probe::probe(input).map_err(|e| format!("{}: {}", input, e)))
+        .collect::<Result<_, _>>()?;
+    let durations: Vec<f64> = probes
+        .iter()
+        .zip(&inputs)
+        .map(|(media, input)| media.duration().ok_or(format!("{}: unknown duration", input)))
+        .collect::<Result<_, _>>()?;
+
+    let first_input = Path::new(&inputs[0]);
+    let output = match &options.output_path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let stem = first_input.file_stem().ok_or("Invalid input file")?.to_string_lossy();
+            let ext = first_input.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or("mkv".to_string());
+            crate::output_dir_for(first_input, options.output_directory.as_deref())?.join(format!("{}_joined.{}", stem, ext))
+        }
+    };
+    if inputs.iter().any(|input| Path::new(input) == output) {
+        return Err("The output would overwrite one of the inputs".to_string());
+    }
+    let output_str = output.to_string_lossy().to_string();
+
+    let first = StreamSignature::of(&probes[0]);
+    let mismatches: Vec<String> = probes
+        .iter()
+        .zip(&inputs)
+        .skip(1)
+        .flat_map(|(media, input)| {
+            let name = Path::new(input).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            StreamSignature::of(media).differences(&first, &name)
+        })
+        .collect();
+
+    if mismatches.is_empty() && options.crossfade <= 0.0 && !options.force_reencode {
+        let duration = durations.iter().sum();
+        let list_path = output.with_file_name(format!(".concat_{}.txt", job_id));
+        let list: String = inputs.iter().map(|input| concat_list_entry(Path::new(input))).collect();
+        fs::write(&list_path, list).map_err(|e| format!("Failed to write concat list: {}", e))?;
+
+        let mut cmd = ffmpeg_progress::command();
+        cmd.args(["-y", "-f", "concat", "-safe", "0", "-i"]);
+        cmd.arg(&list_path);
+        // Only the streams the signatures compared; the demuxer numbers them as in the first input
+        for stream in [probes[0].primary_video(), probes[0].primary_audio()].into_iter().flatten() {
+            cmd.args(["-map", &format!("0:{}", stream.index)]);
+        }
+        cmd.args(["-c", "copy", &output_str]);
+        let result = ffmpeg_progress::run_job(&window, &jobs, &job_id, &mut cmd, vec![output.clone()], Some(duration));
+        let _ = fs::remove_file(&list_path);
+        result?;
+
+        return Ok(ConcatResult {
+            output: output_str,
+            method: ConcatMethod::Copy,
+            mismatches,
+            duration,
+        });
+    }
+
+    check_crossfade(&durations, options.crossfade)?;
+    validate_encode(capabilities.current().as_ref(), &options, &output).into_result()?;
+
+    let has_video = probes.iter().any(|media| media.primary_video().is_some());
+    let has_audio = probes.iter().any(|media| media.primary_audio().is_some());
+    let filter = build_filter(&probes, &durations, &options, has_video, has_audio);
+
+    let mut cmd = ffmpeg_progress::command();
+    cmd.arg("-y");
+    for input in &inputs {
+        cmd.args(["-i", input]);
+    }
+    cmd.args(["-filter_complex", &filter]);
+    if has_video {
+        cmd.args(["-map", "[v]"]);
+    }
+    if has_audio {
+        cmd.args(["-map", "[a]"]);
+    }
+    cmd.args(encoder_args(&options, &output, has_video, has_audio));
+    cmd.arg(&output_str);
+
+    let duration = durations.iter().sum::<f64>() - options.crossfade.max(0.0) * (inputs.len() - 1) as f64;
+    ffmpeg_progress::run_job(&window, &jobs, &job_id, &mut cmd, vec![output], Some(duration))?;
+
+    Ok(ConcatResult {
+        output: output_str,
+        method: ConcatMethod::Filter,
+        mismatches,
+        duration,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn middle_clips_need_room_for_two_crossfades() {
+        assert!(check_crossfade(&[5.0, 5.0], 3.0).is_ok());
+        assert!(check_crossfade(&[5.0, 5.0], 5.0).is_err());
+        assert!(check_crossfade(&[10.0, 5.0, 10.0], 2.0).is_ok());
+        assert!(check_crossfade(&[10.0, 5.0, 10.0], 2.5).is_err());
+        assert!(check_crossfade(&[10.0, 3.0, 10.0], 0.0).is_ok());
+    }
+
+    #[test]
+    fn quality_options_follow_the_encoder() {
+        let options = ConcatOptions { preset: Some("slow".to_string()), ..Default::default() };
+        let args = encoder_args(&options, Path::new("out.mp4"), true, true);
+        assert_eq!(args, ["-crf", "20", "-preset", "slow", "-b:a", "192k"]);
+
+        let vp9 = ConcatOptions { video_codec: Some("libvpx-vp9".to_string()), ..options.clone() };
+        assert_eq!(encoder_args(&vp9, Path::new("out.webm"), true, false), ["-c:v", "libvpx-vp9", "-crf", "20", "-b:v", "0"]);
+
+        let nvenc = ConcatOptions { video_codec: Some("h264_nvenc".to_string()), ..options };
+        assert_eq!(encoder_args(&nvenc, Path::new("out.mkv"), true, false), ["-c:v", "h264_nvenc", "-preset", "slow"]);
+    }
+
+    #[test]
+    fn encoders_are_checked_against_the_container() {
+        assert!(validate_encode(None, &ConcatOptions::default(), Path::new("out.webm")).valid);
+        let x264 = ConcatOptions { video_codec: Some("libx264".to_string()), ..Default::default() };
+        assert!(!validate_encode(None, &x264, Path::new("out.webm")).valid);
+    }
+}
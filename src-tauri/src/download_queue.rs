@@ -0,0 +1,453 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::jobs::{self, JobRegistry};
+use crate::persist;
+use crate::DownloadOptions;
+
+// Emitted with a QueueSnapshot whenever an item is added, started, finished or reordered
+pub const QUEUE_EVENT: &str = "download-queue-changed";
+
+const QUEUE_FILE: &str = "download_queue.json";
+const DEFAULT_MAX_CONCURRENT: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedDownload {
+    // Also used as the job id, so progress events and cancel_job work with it
+    pub id: String,
+    pub options: DownloadOptions,
+    // Higher runs first; items with equal priority keep their queue order
+    pub priority: i32,
+    pub status: QueueStatus,
+    pub attempts: u32,
+    pub max_retries: u32,
+    pub error: Option<String>,
+    pub result: Option<String>,
+    pub added_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueAddRequest {
+    pub options: DownloadOptions,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub max_retries: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueSnapshot {
+    pub max_concurrent: usize,
+    pub items: Vec<QueuedDownload>,
+}
+
+impl Default for QueueSnapshot {
+    fn default() -> Self {
+        QueueSnapshot {
+            max_concurrent: DEFAULT_MAX_CONCURRENT,
+            items: Vec::new(),
+        }
+    }
+}
+
+impl QueueSnapshot {
+    // Marks the next pending items as running, by priority, until the concurrency limit is reached
+    fn start_next(&mut self) -> Vec<QueuedDownload> {
+        let running = self.items.iter().filter(|item| item.status == QueueStatus::Running).count();
+        let free = self.max_concurrent.saturating_sub(running);
+
+        let mut pending: Vec<usize> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.status == QueueStatus::Pending)
+            .map(|(index, _)| index)
+            .collect();
+        // Stable sort keeps the user's order among equal priorities
+        pending.sort_by_key(|&index| std::cmp::Reverse(self.items[index].priority));
+
+        let mut started = Vec::new();
+        for index in pending.into_iter().take(free) {
+            let item = &mut self.items[index];
+            item.status = QueueStatus::Running;
+            item.attempts += 1;
+            item.error = None;
+            started.push(item.clone());
+        }
+        started
+    }
+
+    // Records how a download ended. Failures with retries left go to the back of the queue.
+    fn finish(&mut self, id: &str, result: Result<String, String>) {
+        // The item may have been removed while it was running
+        let Some(index) = self.items.iter().position(|entry| entry.id == id) else {
+            return;
+        };
+        let entry = &mut self.items[index];
+        match result {
+            Ok(message) => {
+                entry.status = QueueStatus::Completed;
+                entry.result = Some(message);
+            }
+            Err(e) if e == jobs::cancelled_error(id) => {
+                entry.status = QueueStatus::Cancelled;
+            }
+            Err(e) => {
+                entry.error = Some(e);
+                if entry.attempts <= entry.max_retries {
+                    // Retry by putting it back in line, behind what is already waiting
+                    entry.status = QueueStatus::Pending;
+                    let entry = self.items.remove(index);
+                    self.items.push(entry);
+                } else {
+                    entry.status = QueueStatus::Failed;
+                }
+            }
+        }
+    }
+
+    fn find_index(&self, id: &str) -> Result<usize, String> {
+        self.items
+            .iter()
+            .position(|item| item.id == id)
+            .ok_or(format!("No queued download with id {}", id))
+    }
+
+    fn move_item(&mut self, id: &str, index: usize) -> Result<(), String> {
+        let from = self.find_index(id)?;
+        let item = self.items.remove(from);
+        let to = index.min(self.items.len());
+        self.items.insert(to, item);
+        Ok(())
+    }
+}
+
+pub struct DownloadQueue {
+    state: Mutex<QueueSnapshot>,
+    path: Option<PathBuf>,
+    // Set when an unreadable queue file couldn't be moved aside; saving would overwrite it
+    load_error: Option<String>,
+}
+
+impl DownloadQueue {
+    // Restores the queue saved by a previous run. Downloads that were running when the app
+    // closed are put back to pending so they start over. A queue file that doesn't parse is
+    // moved aside to download_queue.json.bak.
+    pub fn load(app: &AppHandle) -> Self {
+        let path = app.path_resolver().app_data_dir().map(|dir| dir.join(QUEUE_FILE));
+
+        let (mut state, load_error) = match path.as_ref().map(|path| persist::load_json::<QueueSnapshot>(path)) {
+            Some(Ok(state)) => (state, None),
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                (QueueSnapshot::default(), Some(e))
+            }
+            None => (QueueSnapshot::default(), None),
+        };
+
+        for item in state.items.iter_mut() {
+            if item.status == QueueStatus::Running {
+                item.status = QueueStatus::Pending;
+            }
+        }
+
+        DownloadQueue {
+            state: Mutex::new(state),
+            path,
+            load_error,
+        }
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        self.state.lock().unwrap().clone()
+    }
+
+    fn save(&self, state: &QueueSnapshot) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(error) = &self.load_error {
+            return Err(error.clone());
+        }
+        let json = serde_json::to_vec_pretty(state).map_err(|e| format!("Failed to serialize the download queue: {}", e))?;
+        persist::write_atomic(path, &json)
+    }
+
+    // Applies a change to a copy of the queue and swaps it in once it is saved, then notifies the frontend
+    fn update<T>(&self, app: &AppHandle, change: impl FnOnce(&mut QueueSnapshot) -> Result<T, String>) -> Result<T, String> {
+        let (result, snapshot) = {
+            let mut state = self.state.lock().unwrap();
+            let mut next = state.clone();
+            let result = change(&mut next)?;
+            self.save(&next)?;
+            *state = next;
+            (result, state.clone())
+        };
+        let _ = app.emit_all(QUEUE_EVENT, snapshot);
+        Ok(result)
+    }
+
+    // Like update, for downloads starting and ending: those happen whether or not they can be saved
+    fn advance<T>(&self, app: &AppHandle, change: impl FnOnce(&mut QueueSnapshot) -> T) -> T {
+        let (result, snapshot) = {
+            let mut state = self.state.lock().unwrap();
+            let result = change(&mut state);
+            if let Err(e) = self.save(&state) {
+                eprintln!("{}", e);
+            }
+            (result, state.clone())
+        };
+        let _ = app.emit_all(QUEUE_EVENT, snapshot);
+        result
+    }
+
+    // Picks the next pending items by priority and starts them until the concurrency limit is reached
+    pub fn pump(&self, app: &AppHandle) {
+        let started = self.advance(app, QueueSnapshot::start_next);
+
+        for item in started {
+            let app = app.clone();
+            tauri::async_runtime::spawn_blocking(move || run_item(app, item));
+        }
+    }
+}
+
+fn run_item(app: AppHandle, item: QueuedDownload) {
+    let result = match app.get_window("main") {
+        Some(window) => {
+            let jobs = app.state::<JobRegistry>();
+            let result = crate::ytdlp_download_internal(&window, &jobs, &item.id, &item.options);
+            crate::ytdlp_progress::emit_result(&window, &item.id, &result);
+            result
+        }
+        None => Err("Main window is not available".to_string()),
+    };
+
+    let queue = app.state::<DownloadQueue>();
+    queue.advance(&app, |state| state.finish(&item.id, result));
+    queue.pump(&app);
+}
+
+#[tauri::command]
+pub fn queue_add(request: QueueAddRequest, app: AppHandle, queue: State<'_, DownloadQueue>) -> Result<QueuedDownload, String> {
+    let added_at = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let item = QueuedDownload {
+        id: crate::new_job_id(),
+        options: request.options,
+        priority: request.priority,
+        status: QueueStatus::Pending,
+        attempts: 0,
+        max_retries: request.max_retries,
+        error: None,
+        result: None,
+        added_at,
+    };
+
+    queue.update(&app, |state| {
+        state.items.push(item.clone());
+        Ok(())
+    })?;
+    queue.pump(&app);
+
+    Ok(item)
+}
+
+#[tauri::command]
+pub fn queue_list(queue: State<'_, DownloadQueue>) -> QueueSnapshot {
+    queue.snapshot()
+}
+
+// Removes an item, cancelling it first if it is currently downloading
+#[tauri::command]
+pub fn queue_remove(
+    id: String,
+    app: AppHandle,
+    queue: State<'_, DownloadQueue>,
+    jobs: State<'_, JobRegistry>,
+) -> Result<(), String> {
+    let removed = queue.update(&app, |state| {
+        let index = state.find_index(&id)?;
+        Ok(state.items.remove(index))
+    })?;
+
+    if removed.status == QueueStatus::Running {
+        let _ = jobs.cancel(&id);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn queue_move(id: String, index: usize, app: AppHandle, queue: State<'_, DownloadQueue>) -> Result<(), String> {
+    queue.update(&app, |state| state.move_item(&id, index))
+}
+
+#[tauri::command]
+pub fn queue_set_priority(id: String, priority: i32, app: AppHandle, queue: State<'_, DownloadQueue>) -> Result<(), String> {
+    queue.update(&app, |state| {
+        let index = state.find_index(&id)?;
+        state.items[index].priority = priority;
+        Ok(())
+    })
+}
+
+// Puts a failed or cancelled item back in the queue with a fresh retry budget
+#[tauri::command]
+pub fn queue_retry(id: String, app: AppHandle, queue: State<'_, DownloadQueue>) -> Result<(), String> {
+    queue.update(&app, |state| {
+        let index = state.find_index(&id)?;
+        let item = &mut state.items[index];
+        if !matches!(item.status, QueueStatus::Failed | QueueStatus::Cancelled) {
+            return Err("Only failed or cancelled downloads can be retried".to_string());
+        }
+        item.status = QueueStatus::Pending;
+        item.attempts = 0;
+        item.error = None;
+        Ok(())
+    })?;
+    queue.pump(&app);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn queue_set_concurrency(max_concurrent: usize, app: AppHandle, queue: State<'_, DownloadQueue>) -> Result<(), String> {
+    if max_concurrent == 0 {
+        return Err("At least one download must be allowed to run".to_string());
+    }
+    queue.update(&app, |state| {
+        state.max_concurrent = max_concurrent;
+        Ok(())
+    })?;
+    queue.pump(&app);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn queue_clear_finished(app: AppHandle, queue: State<'_, DownloadQueue>) -> Result<(), String> {
+    queue.update(&app, |state| {
+        state.items.retain(|item| item.status != QueueStatus::Completed);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, priority: i32, max_retries: u32) -> QueuedDownload {
+        let options = serde_json::from_value(serde_json::json!({
+            "url": format!("https://example.com/{}", id),
+            "output_dir": "/downloads",
+            "quality": "best",
+            "format": "mp4",
+            "audio_only": false,
+            "audio_format": "mp3",
+            "embed_subs": false,
+            "embed_thumbnail": false,
+            "embed_metadata": false,
+            "retries": 0,
+            "cookie_file": null,
+        }))
+        .unwrap();
+        QueuedDownload {
+            id: id.to_string(),
+            options,
+            priority,
+            status: QueueStatus::Pending,
+            attempts: 0,
+            max_retries,
+            error: None,
+            result: None,
+            added_at: 0,
+        }
+    }
+
+    fn queue(max_concurrent: usize, items: Vec<QueuedDownload>) -> QueueSnapshot {
+        QueueSnapshot { max_concurrent, items }
+    }
+
+    fn ids(items: &[QueuedDownload]) -> Vec<&str> {
+        items.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    #[test]
+    fn higher_priority_starts_first_and_ties_keep_queue_order() {
+        let mut state = queue(3, vec![item("a", 0, 0), item("b", 5, 0), item("c", 0, 0), item("d", 5, 0)]);
+        assert_eq!(ids(&state.start_next()), ["b", "d", "a"]);
+        assert_eq!(state.items[2].status, QueueStatus::Pending);
+        assert_eq!(state.items[1].attempts, 1);
+    }
+
+    #[test]
+    fn running_items_count_against_the_limit() {
+        let mut state = queue(2, vec![item("a", 0, 0), item("b", 0, 0), item("c", 0, 0)]);
+        assert_eq!(ids(&state.start_next()), ["a", "b"]);
+        assert!(state.start_next().is_empty());
+
+        state.finish("a", Ok("done".to_string()));
+        assert_eq!(state.items[0].status, QueueStatus::Completed);
+        assert_eq!(ids(&state.start_next()), ["c"]);
+
+        state.max_concurrent = 3;
+        assert!(state.start_next().is_empty());
+    }
+
+    #[test]
+    fn moving_an_item_clamps_the_index() {
+        let mut state = queue(1, vec![item("a", 0, 0), item("b", 0, 0), item("c", 0, 0)]);
+        state.move_item("c", 0).unwrap();
+        assert_eq!(ids(&state.items), ["c", "a", "b"]);
+        state.move_item("c", 99).unwrap();
+        assert_eq!(ids(&state.items), ["a", "b", "c"]);
+        assert!(state.move_item("x", 0).is_err());
+
+        // The new order decides among equal priorities
+        assert_eq!(ids(&state.start_next()), ["a"]);
+    }
+
+    #[test]
+    fn failed_items_with_retries_left_go_to_the_back() {
+        let mut state = queue(1, vec![item("a", 0, 1), item("b", 0, 0)]);
+        state.start_next();
+
+        state.finish("a", Err("HTTP Error 503".to_string()));
+        assert_eq!(ids(&state.items), ["b", "a"]);
+        assert_eq!(state.items[1].status, QueueStatus::Pending);
+        assert_eq!(state.items[1].error.as_deref(), Some("HTTP Error 503"));
+        assert_eq!(ids(&state.start_next()), ["b"]);
+
+        // Out of retries on the second failure
+        state.finish("b", Ok("done".to_string()));
+        assert_eq!(ids(&state.start_next()), ["a"]);
+        state.finish("a", Err("HTTP Error 503".to_string()));
+        assert_eq!(state.items[1].status, QueueStatus::Failed);
+        assert_eq!(state.items[1].attempts, 2);
+    }
+
+    #[test]
+    fn cancelled_downloads_are_not_retried() {
+        let mut state = queue(1, vec![item("a", 0, 3)]);
+        state.start_next();
+        state.finish("a", Err(jobs::cancelled_error("a")));
+        assert_eq!(state.items[0].status, QueueStatus::Cancelled);
+        assert!(state.start_next().is_empty());
+    }
+}
@@ -0,0 +1,690 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, Window};
+
+use crate::create_hidden_command;
+use crate::library::{EntryMetadata, MediaLibrary};
+use crate::scanner::ScanError;
+use crate::MediaFile;
+
+// Emitted while the duplicate finder works through its stages
+pub const PROGRESS_EVENT: &str = "duplicate-scan-progress";
+
+// Bytes read from each end of a file for the quick hash that weeds out most same-size files
+const QUICK_HASH_BYTES: u64 = 64 * 1024;
+// Where in a video the fingerprint frames are taken, as a fraction of its duration
+const FRAME_POSITIONS: [f64; 6] = [0.1, 0.25, 0.4, 0.55, 0.7, 0.85];
+// Audio fingerprints cover at most this much of the start of a file, in windows of AUDIO_WINDOW seconds
+const AUDIO_SECONDS: u32 = 180;
+const AUDIO_RATE: usize = 8000;
+const AUDIO_WINDOW: f64 = 0.25;
+// How many windows an audio fingerprint may be shifted by, for copies with a little extra at the start
+const AUDIO_MAX_SHIFT: usize = 8;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DuplicateOptions {
+    pub exact: bool,
+    pub similar: bool,
+    // Seconds two durations may differ by and still be compared; 1% of the longer one is always allowed
+    pub duration_tolerance: f64,
+    // 0-1; how alike two fingerprints have to be to count as the same media
+    pub similarity_threshold: f64,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        DuplicateOptions {
+            exact: true,
+            similar: true,
+            duration_tolerance: 2.0,
+            similarity_threshold: 0.9,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateKind {
+    // Byte-for-byte identical
+    Exact,
+    // The same media at a different resolution, bitrate or encoding
+    Similar,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateFile {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub modified: u64,
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub bit_rate: Option<u64>,
+    // How alike this file is to the one to keep; None for exact duplicates and the kept file itself
+    pub similarity: Option<f64>,
+    pub keep: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    // Path of the best copy: highest resolution, then bitrate, then no "(1)" style suffix, then oldest
+    pub keep: String,
+    pub files: Vec<DuplicateFile>,
+    // Space freed by deleting everything but the kept copy
+    pub reclaimable_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+    pub files_checked: usize,
+    pub errors: Vec<ScanError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DuplicateProgress {
+    stage: &'static str,
+    done: usize,
+    total: usize,
+}
+
+// A file together with what is known about its content
+struct Candidate<'a> {
+    file: &'a MediaFile,
+    metadata: Option<EntryMetadata>,
+}
+
+impl Candidate<'_> {
+    fn duration(&self) -> Option<f64> {
+        self.metadata.as_ref().and_then(|m| m.duration).filter(|d| *d > 0.0)
+    }
+
+    fn has_video(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|m| m.video_codec.is_some())
+    }
+
+    fn has_audio(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|m| m.audio_codec.is_some())
+    }
+
+    fn to_report(&self, similarity: Option<f64>, keep: bool) -> DuplicateFile {
+        let metadata = self.metadata.as_ref();
+        DuplicateFile {
+            path: self.file.path.clone(),
+            name: self.file.name.clone(),
+            size: self.file.size,
+            modified: self.file.modified,
+            duration: metadata.and_then(|m| m.duration),
+            width: metadata.and_then(|m| m.width),
+            height: metadata.and_then(|m| m.height),
+            bit_rate: metadata.and_then(|m| m.bit_rate),
+            similarity,
+            keep,
+        }
+    }
+}
+
+// "clip (1)", "clip(2)", "clip - Copy" and "clip copy" are what browsers and file managers produce
+fn has_copy_suffix(name: &str) -> bool {
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let stem = stem.trim_end();
+
+    if let Some(inner) = stem.strip_suffix(')').and_then(|rest| rest.rsplit_once('(')).map(|(_, inner)| inner) {
+        if !inner.is_empty() && inner.chars().all(|c| c.is_ascii_digit()) {
+            return true;
+        }
+    }
+    stem.ends_with(" - copy") || stem.ends_with(" copy")
+}
+
+// Index of the copy worth keeping
+fn best_copy(candidates: &[&Candidate]) -> usize {
+    let rank = |candidate: &Candidate| {
+        let metadata = candidate.metadata.as_ref();
+        let pixels = metadata.map(|m| m.width.unwrap_or(0) as u64 * m.height.unwrap_or(0) as u64).unwrap_or(0);
+        let bit_rate = metadata.and_then(|m| m.bit_rate).unwrap_or(0);
+        (
+            pixels,
+            bit_rate,
+            !has_copy_suffix(&candidate.file.name),
+            std::cmp::Reverse(candidate.file.modified),
+        )
+    };
+    (0..candidates.len())
+        .max_by_key(|&index| rank(candidates[index]))
+        .unwrap_or(0)
+}
+
+fn hash_file(path: &str, quick: bool) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+
+    if quick {
+        let len = file.metadata()?.len();
+        let mut buf = Vec::new();
+        file.by_ref().take(QUICK_HASH_BYTES).read_to_end(&mut buf)?;
+        if len > QUICK_HASH_BYTES * 2 {
+            file.seek(SeekFrom::End(-(QUICK_HASH_BYTES as i64)))?;
+            file.read_to_end(&mut buf)?;
+        }
+        hasher.update(&buf);
+    } else {
+        io::copy(&mut file, &mut hasher)?;
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Groups indices of `candidates` that hash the same. Only same-size files are hashed, and only
+// files whose quick hash collides are read in full.
+fn exact_groups(
+    candidates: &[Candidate],
+    errors: &mut Vec<ScanError>,
+    mut on_progress: impl FnMut(usize, usize),
+) -> Vec<Vec<usize>> {
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, candidate) in candidates.iter().enumerate() {
+        // Empty files are all identical and not worth reporting
+        if candidate.file.size > 0 {
+            by_size.entry(candidate.file.size).or_default().push(index);
+        }
+    }
+    let same_size: Vec<Vec<usize>> = by_size.into_values().filter(|group| group.len() > 1).collect();
+    let total: usize = same_size.iter().map(Vec::len).sum();
+    let mut done = 0;
+
+    let mut groups = Vec::new();
+    for group in same_size {
+        let mut by_quick: HashMap<String, Vec<usize>> = HashMap::new();
+        for index in group {
+            done += 1;
+            on_progress(done, total);
+            match hash_file(&candidates[index].file.path, true) {
+                Ok(hash) => by_quick.entry(hash).or_default().push(index),
+                Err(e) => errors.push(ScanError {
+                    path: candidates[index].file.path.clone(),
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        for collided in by_quick.into_values().filter(|group| group.len() > 1) {
+            let mut by_full: HashMap<String, Vec<usize>> = HashMap::new();
+            for index in collided {
+                match hash_file(&candidates[index].file.path, false) {
+                    Ok(hash) => by_full.entry(hash).or_default().push(index),
+                    Err(e) => errors.push(ScanError {
+                        path: candidates[index].file.path.clone(),
+                        message: e.to_string(),
+                    }),
+                }
+            }
+            groups.extend(by_full.into_values().filter(|group| group.len() > 1));
+        }
+    }
+    groups
+}
+
+// Difference hash of one frame: 64 bits saying whether each pixel of a 9x8 grey image is
+// darker than its right neighbour. Survives scaling, re-encoding and small colour changes.
+fn frame_hash(path: &str, position: f64) -> Option<u64> {
+    let output = create_hidden_command("ffmpeg")
+        .args(["-hide_banner", "-v", "error", "-ss", &format!("{:.3}", position), "-i", path])
+        .args(["-frames:v", "1", "-vf", "scale=9:8,format=gray", "-f", "rawvideo", "-"])
+        .output()
+        .ok()?;
+    let pixels = output.stdout;
+    if !output.status.success() || pixels.len() < 72 {
+        return None;
+    }
+
+    let mut hash = 0u64;
+    for row in 0..8 {
+        for col in 0..8 {
+            hash <<= 1;
+            if pixels[row * 9 + col] < pixels[row * 9 + col + 1] {
+                hash |= 1;
+            }
+        }
+    }
+    Some(hash)
+}
+
+fn video_fingerprint(path: &str, duration: f64) -> Option<Vec<u64>> {
+    FRAME_POSITIONS
+        .iter()
+        .map(|position| frame_hash(path, duration * position))
+        .collect()
+}
+
+// Loudness envelope of the start of the audio: one bit per window saying whether it got louder
+fn audio_fingerprint(path: &str) -> Option<Vec<bool>> {
+    let output = create_hidden_command("ffmpeg")
+        .args(["-hide_banner", "-v", "error", "-i", path, "-map", "0:a:0"])
+        .args(["-t", &AUDIO_SECONDS.to_string(), "-ac", "1", "-ar", &AUDIO_RATE.to_string()])
+        .args(["-f", "s16le", "-"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let window = (AUDIO_RATE as f64 * AUDIO_WINDOW) as usize;
+    let energies: Vec<f64> = output
+        .stdout
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f64)
+        .collect::<Vec<f64>>()
+        .chunks(window)
+        .filter(|chunk| chunk.len() == window)
+        .map(|chunk| chunk.iter().map(|sample| sample * sample).sum::<f64>() / window as f64)
+        .collect();
+
+    let bits: Vec<bool> = energies.windows(2).map(|pair| pair[1] > pair[0]).collect();
+    // A few seconds of sound are needed for the comparison to mean anything
+    (bits.len() >= 16).then_some(bits)
+}
+
+fn video_similarity(a: &[u64], b: &[u64]) -> f64 {
+    let differing: u32 = a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum();
+    1.0 - differing as f64 / (a.len().max(1) * 64) as f64
+}
+
+// Best match over small shifts in either direction
+fn audio_similarity(a: &[bool], b: &[bool]) -> f64 {
+    let compare = |a: &[bool], b: &[bool]| {
+        let len = a.len().min(b.len());
+        if len < 16 {
+            return 0.0;
+        }
+        a.iter().zip(b).filter(|(a, b)| a == b).count() as f64 / len as f64
+    };
+    (0..=AUDIO_MAX_SHIFT)
+        .flat_map(|shift| {
+            [
+                compare(a.get(shift..).unwrap_or_default(), b),
+                compare(a, b.get(shift..).unwrap_or_default()),
+            ]
+        })
+        .fold(0.0, f64::max)
+}
+
+// Similarity of matching pairs, keyed by (lower index, higher index)
+type PairScores = HashMap<(usize, usize), f64>;
+
+#[derive(Default)]
+struct Fingerprint {
+    video: Option<Vec<u64>>,
+    audio: Option<Vec<bool>>,
+}
+
+fn find_root(root: &mut [usize], index: usize) -> usize {
+    let mut current = index;
+    while root[current] != current {
+        root[current] = root[root[current]];
+        current = root[current];
+    }
+    current
+}
+
+// Pairs up files of similar duration, compares their fingerprints and returns the groups
+// (by union of matching pairs) along with the similarity of every matching pair
+fn similar_groups(
+    candidates: &[Candidate],
+    considered: &[usize],
+    options: &DuplicateOptions,
+    mut on_progress: impl FnMut(usize, usize),
+) -> (Vec<Vec<usize>>, PairScores) {
+    let mut by_duration: Vec<(usize, f64)> = considered
+        .iter()
+        .filter_map(|&index| candidates[index].duration().map(|d| (index, d)))
+        .collect();
+    by_duration.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    let mut pairs = Vec::new();
+    for (i, &(a, duration_a)) in by_duration.iter().enumerate() {
+        for &(b, duration_b) in &by_duration[i + 1..] {
+            let tolerance = options.duration_tolerance.max(duration_b * 0.01);
+            if duration_b - duration_a > tolerance {
+                break;
+            }
+            pairs.push((a, b));
+        }
+    }
+
+    // Fingerprints are only taken for files that are in at least one pair
+    let mut fingerprints: HashMap<usize, Fingerprint> = HashMap::new();
+    let mut needed: Vec<usize> = pairs.iter().flat_map(|&(a, b)| [a, b]).collect();
+    needed.sort_unstable();
+    needed.dedup();
+    for (done, &index) in needed.iter().enumerate() {
+        on_progress(done + 1, needed.len());
+        let candidate = &candidates[index];
+        let path = &candidate.file.path;
+        let fingerprint = Fingerprint {
+            video: candidate
+                .has_video()
+                .then(|| candidate.duration().and_then(|d| video_fingerprint(path, d)))
+                .flatten(),
+            // Videos are compared by their frames; audio is only needed when there is no picture
+            audio: (!candidate.has_video() && candidate.has_audio())
+                .then(|| audio_fingerprint(path))
+                .flatten(),
+        };
+        fingerprints.insert(index, fingerprint);
+    }
+
+    let mut root: Vec<usize> = (0..candidates.len()).collect();
+    let mut scores = HashMap::new();
+    for (a, b) in pairs {
+        let (Some(fa), Some(fb)) = (fingerprints.get(&a), fingerprints.get(&b)) else {
+            continue;
+        };
+        let score = match (&fa.video, &fb.video, &fa.audio, &fb.audio) {
+            (Some(va), Some(vb), _, _) => video_similarity(va, vb),
+            (None, None, Some(aa), Some(ab)) => audio_similarity(aa, ab),
+            // A video and an audio-only file are never the same media
+            _ => continue,
+        };
+        if score >= options.similarity_threshold {
+            scores.insert((a.min(b), a.max(b)), score);
+            let (ra, rb) = (find_root(&mut root, a), find_root(&mut root, b));
+            root[ra] = rb;
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &index in considered {
+        let group_root = find_root(&mut root, index);
+        groups.entry(group_root).or_default().push(index);
+    }
+    let groups = groups.into_values().filter(|group| group.len() > 1).collect();
+    (groups, scores)
+}
+
+fn build_group(
+    kind: DuplicateKind,
+    candidates: &[Candidate],
+    members: &[usize],
+    scores: &PairScores,
+) -> DuplicateGroup {
+    let refs: Vec<&Candidate> = members.iter().map(|&index| &candidates[index]).collect();
+    let keep = members[best_copy(&refs)];
+
+    let files: Vec<DuplicateFile> = members
+        .iter()
+        .map(|&index| {
+            let similarity = (index != keep)
+                .then(|| scores.get(&(index.min(keep), index.max(keep))).copied())
+                .flatten();
+            candidates[index].to_report(similarity, index == keep)
+        })
+        .collect();
+    let reclaimable_bytes = files.iter().filter(|file| !file.keep).map(|file| file.size).sum();
+
+    DuplicateGroup {
+        kind,
+        keep: candidates[keep].file.path.clone(),
+        files,
+        reclaimable_bytes,
+    }
+}
+
+pub fn find_duplicates_in(
+    files: &[MediaFile],
+    options: &DuplicateOptions,
+    library: &MediaLibrary,
+    mut on_progress: impl FnMut(&'static str, usize, usize),
+) -> DuplicateReport {
+    let mut report = DuplicateReport {
+        files_checked: files.len(),
+        ..Default::default()
+    };
+
+    // Probing is the slow part, so the library index is used whenever it knows the file already
+    let mut candidates = Vec::with_capacity(files.len());
+    for (done, file) in files.iter().enumerate() {
+        on_progress("probing", done + 1, files.len());
+        let metadata = library
+            .metadata_for(file)
+            .or_else(|| Some(EntryMetadata::probe(&file.path)).filter(|metadata| metadata.error.is_none()));
+        candidates.push(Candidate { file, metadata });
+    }
+
+    // Only one copy of each set of identical files takes part in the similarity search
+    let mut considered: Vec<usize> = (0..candidates.len()).collect();
+    let no_scores = HashMap::new();
+    if options.exact {
+        let groups = exact_groups(&candidates, &mut report.errors, |done, total| on_progress("hashing", done, total));
+        for members in groups {
+            let group = build_group(DuplicateKind::Exact, &candidates, &members, &no_scores);
+            considered.retain(|index| !members.contains(index) || candidates[*index].file.path == group.keep);
+            report.groups.push(group);
+        }
+    }
+
+    if options.similar {
+        let (groups, scores) = similar_groups(&candidates, &considered, options, |done, total| {
+            on_progress("fingerprinting", done, total)
+        });
+        for members in groups {
+            report.groups.push(build_group(DuplicateKind::Similar, &candidates, &members, &scores));
+        }
+    }
+
+    // Biggest savings first
+    report.groups.sort_by_key(|group| std::cmp::Reverse(group.reclaimable_bytes));
+    report
+}
+
+// Looks for identical and near-identical copies among scanned files, e.g. the result of scan_media_files
+#[tauri::command]
+pub async fn find_duplicates(
+    files: Vec<MediaFile>,
+    options: Option<DuplicateOptions>,
+    window: Window,
+    app: AppHandle,
+) -> Result<DuplicateReport, String> {
+    let options = options.unwrap_or_default();
+    tauri::async_runtime::spawn_blocking(move || {
+        let library = app.state::<MediaLibrary>();
+        find_duplicates_in(&files, &options, &library, |stage, done, total| {
+            let _ = window.emit(PROGRESS_EVENT, DuplicateProgress { stage, done, total });
+        })
+    })
+    .await
+    .map_err(|e| format!("Duplicate search failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str, modified: u64, size: Option<(u32, u32)>, bit_rate: Option<u64>) -> (MediaFile, Option<EntryMetadata>) {
+        let file = MediaFile {
+            name: name.to_string(),
+            path: format!("/media/{}", name),
+            size: 1000,
+            modified,
+            file_type: "video".to_string(),
+        };
+        let metadata = EntryMetadata {
+            duration: Some(60.0),
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
+            video_codec: None,
+            audio_codec: None,
+            bit_rate,
+            format_name: String::new(),
+            error: None,
+        };
+        (file, Some(metadata))
+    }
+
+    fn best(files: &[(MediaFile, Option<EntryMetadata>)]) -> usize {
+        let candidates: Vec<Candidate> = files
+            .iter()
+            .map(|(file, metadata)| Candidate { file, metadata: metadata.clone() })
+            .collect();
+        best_copy(&candidates.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn copy_suffixes() {
+        for name in ["clip (1).mp4", "clip(2).mp4", "clip - Copy.mp4", "clip copy.MKV", "clip (12) .mp4"] {
+            assert!(has_copy_suffix(name), "{}", name);
+        }
+        for name in ["clip.mp4", "clip (final).mp4", "clip ().mp4", "copycat.mp4"] {
+            assert!(!has_copy_suffix(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn best_copy_prefers_resolution_then_bit_rate() {
+        let files = [
+            candidate("a.mp4", 1, Some((1280, 720)), Some(9_000_000)),
+            candidate("b.mp4", 1, Some((1920, 1080)), Some(4_000_000)),
+        ];
+        assert_eq!(best(&files), 1);
+
+        let files = [
+            candidate("a.mp4", 1, Some((1920, 1080)), Some(4_000_000)),
+            candidate("b.mp4", 1, Some((1920, 1080)), Some(6_000_000)),
+        ];
+        assert_eq!(best(&files), 1);
+    }
+
+    #[test]
+    fn best_copy_prefers_the_original_name_then_the_oldest_file() {
+        let files = [
+            candidate("clip (1).mp4", 1, Some((1920, 1080)), None),
+            candidate("clip.mp4", 5, Some((1920, 1080)), None),
+        ];
+        assert_eq!(best(&files), 1);
+
+        let files = [candidate("a.mp4", 9, None, None), candidate("b.mp4", 3, None, None)];
+        assert_eq!(best(&files), 1);
+    }
+
+    fn write_file(dir: &Path, name: &str, contents: &[u8]) -> MediaFile {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        MediaFile {
+            name: name.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            size: contents.len() as u64,
+            modified: 0,
+            file_type: "video".to_string(),
+        }
+    }
+
+    fn sorted_names(groups: Vec<Vec<usize>>, files: &[MediaFile]) -> Vec<Vec<String>> {
+        let mut groups: Vec<Vec<String>> = groups
+            .into_iter()
+            .map(|group| {
+                let mut names: Vec<String> = group.into_iter().map(|index| files[index].name.clone()).collect();
+                names.sort();
+                names
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    #[test]
+    fn exact_groups_hash_same_size_files_in_full_after_a_quick_hash_collision() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = vec![7u8; 1000];
+        let mut other_small = small.clone();
+        other_small[500] = 8;
+        // Larger than both quick hash ends, differing only in the middle
+        let large = vec![1u8; QUICK_HASH_BYTES as usize * 3];
+        let mut other_large = large.clone();
+        other_large[QUICK_HASH_BYTES as usize + 10] = 2;
+
+        let mut files = vec![
+            write_file(dir.path(), "a.mp4", &small),
+            write_file(dir.path(), "a (1).mp4", &small),
+            write_file(dir.path(), "b.mp4", &other_small),
+            write_file(dir.path(), "c.mp4", &large),
+            write_file(dir.path(), "c copy.mp4", &large),
+            write_file(dir.path(), "d.mp4", &other_large),
+            write_file(dir.path(), "empty.mp4", b""),
+            write_file(dir.path(), "empty (1).mp4", b""),
+            write_file(dir.path(), "alone.mp4", &[3u8; 10]),
+        ];
+        let missing = write_file(dir.path(), "missing.mp4", &small);
+        std::fs::remove_file(&missing.path).unwrap();
+        files.push(missing);
+
+        let candidates: Vec<Candidate> = files.iter().map(|file| Candidate { file, metadata: None }).collect();
+        let mut errors = Vec::new();
+        let mut progress = Vec::new();
+        let groups = exact_groups(&candidates, &mut errors, |done, total| progress.push((done, total)));
+
+        assert_eq!(
+            sorted_names(groups, &files),
+            vec![vec!["a (1).mp4", "a.mp4"], vec!["c copy.mp4", "c.mp4"]]
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].path.ends_with("missing.mp4"));
+        // Empty files and files without a same-size partner are never hashed
+        assert_eq!(progress.len(), 7);
+        assert_eq!(progress.last(), Some(&(7, 7)));
+    }
+
+    #[test]
+    fn video_similarity_counts_differing_bits() {
+        let a = [0u64, u64::MAX, 0x0f0f_0f0f_0f0f_0f0f];
+        assert_eq!(video_similarity(&a, &a), 1.0);
+
+        let b = [1u64, u64::MAX, 0x0f0f_0f0f_0f0f_0f0e];
+        assert_eq!(video_similarity(&a, &b), 1.0 - 2.0 / 192.0);
+        assert_eq!(video_similarity(&[0], &[u64::MAX]), 0.0);
+    }
+
+    // Deterministic bits without a visible pattern
+    fn envelope(len: usize, seed: u32) -> Vec<bool> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                state & 0x4000_0000 != 0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn audio_similarity_matches_small_shifts_in_either_direction() {
+        let original = envelope(200, 1);
+        assert_eq!(audio_similarity(&original, &original), 1.0);
+
+        // A copy with a few windows of extra audio at the start
+        let mut padded = envelope(3, 2);
+        padded.extend(&original);
+        assert_eq!(audio_similarity(&original, &padded), 1.0);
+        assert_eq!(audio_similarity(&padded, &original), 1.0);
+
+        let mut shifted_too_far = envelope(AUDIO_MAX_SHIFT + 4, 2);
+        shifted_too_far.extend(&original);
+        assert!(audio_similarity(&original, &shifted_too_far) < 0.8);
+
+        // Too short to compare
+        assert_eq!(audio_similarity(&original[..10], &original[..10]), 0.0);
+    }
+}
@@ -0,0 +1,400 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Instant;
+
+use serde::Serialize;
+use tauri::Window;
+
+use crate::create_hidden_command;
+use crate::jobs::{self, JobRegistry, ProcessOutput};
+
+// Event emitted to the window for every FFmpeg progress block
+pub const PROGRESS_EVENT: &str = "ffmpeg-progress";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FfmpegPhase {
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FfmpegProgress {
+    pub job_id: String,
+    pub phase: FfmpegPhase,
+    // Of the whole job, across all of its passes
+    pub percent: Option<f64>,
+    // Seconds of output written so far by the current pass
+    pub out_time: f64,
+    pub duration: Option<f64>,
+    pub frame: Option<u64>,
+    pub fps: Option<f64>,
+    // Encoding speed relative to realtime, e.g. 2.5 for "2.5x"
+    pub speed: Option<f64>,
+    // Estimated seconds until the job finishes, assuming later passes run at the same rate
+    pub eta: Option<f64>,
+    pub total_size: Option<u64>,
+    // Why the job failed, for the Failed phase
+    pub message: Option<String>,
+}
+
+// The share of a job that one FFmpeg run makes up, for operations that run FFmpeg several times
+// under one job id. Progress is mapped into offset..offset + span of the whole job, and only the
+// last pass reports the job as finished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pass {
+    pub offset: f64,
+    pub span: f64,
+    pub last: bool,
+}
+
+impl Pass {
+    pub const WHOLE: Pass = Pass { offset: 0.0, span: 1.0, last: true };
+
+    // Pass `index` (from 0) of `count` passes that each take an equal share
+    pub fn nth(index: usize, count: usize) -> Pass {
+        Pass::weighted(&vec![1.0; count], index)
+    }
+
+    // Pass `index` of passes that take shares in proportion to `weights`, e.g. their durations
+    pub fn weighted(weights: &[f64], index: usize) -> Pass {
+        let total: f64 = weights.iter().map(|weight| weight.max(0.0)).sum();
+        if total <= 0.0 || index >= weights.len() {
+            return Pass::WHOLE;
+        }
+        let before: f64 = weights[..index].iter().map(|weight| weight.max(0.0)).sum();
+        Pass {
+            offset: before / total,
+            span: weights[index].max(0.0) / total,
+            last: index + 1 == weights.len(),
+        }
+    }
+}
+
+// FFmpeg command that writes machine readable "key=value" progress blocks to stdout
+// instead of the interactive status line on stderr
+pub fn command() -> Command {
+    let mut cmd = create_hidden_command("ffmpeg");
+    cmd.args(["-hide_banner", "-nostats", "-progress", "pipe:1"]);
+    cmd
+}
+
+// Collects the key=value lines of one progress block; FFmpeg ends every block with "progress=continue|end"
+struct ProgressParser {
+    job_id: String,
+    duration: Option<f64>,
+    pass: Pass,
+    started: Instant,
+    current: FfmpegProgress,
+}
+
+impl ProgressParser {
+    fn new(job_id: &str, duration: Option<f64>, pass: Pass) -> Self {
+        ProgressParser {
+            job_id: job_id.to_string(),
+            duration: duration.filter(|d| *d > 0.0),
+            pass,
+            started: Instant::now(),
+            current: FfmpegProgress {
+                job_id: job_id.to_string(),
+                phase: FfmpegPhase::Running,
+                percent: None,
+                out_time: 0.0,
+                duration,
+                frame: None,
+                fps: None,
+                speed: None,
+                eta: None,
+                total_size: None,
+                message: None,
+            },
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<FfmpegProgress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+
+        match key {
+            "frame" => self.current.frame = value.parse().ok(),
+            "fps" => self.current.fps = value.parse().ok().filter(|fps: &f64| *fps > 0.0),
+            "total_size" => self.current.total_size = value.parse().ok(),
+            // Despite the name, out_time_ms is in microseconds just like out_time_us
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.parse::<i64>() {
+                    self.current.out_time = (us.max(0) as f64) / 1_000_000.0;
+                }
+            }
+            "speed" => self.current.speed = value.trim_end_matches('x').trim().parse().ok().filter(|s: &f64| *s > 0.0),
+            "progress" => {
+                let finished = value == "end";
+                return Some(self.snapshot(finished));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn snapshot(&mut self, finished: bool) -> FfmpegProgress {
+        let mut progress = self.current.clone();
+        progress.job_id = self.job_id.clone();
+
+        if finished && self.pass.last {
+            progress.phase = FfmpegPhase::Finished;
+            progress.percent = Some(100.0);
+            progress.eta = Some(0.0);
+            return progress;
+        }
+
+        if let Some(duration) = self.duration {
+            let out_time = if finished { duration } else { progress.out_time };
+            let done = (out_time / duration).clamp(0.0, 1.0);
+            progress.percent = Some((self.pass.offset + self.pass.span * done) * 100.0);
+
+            // Average over the whole run so far, which is steadier than the instantaneous speed.
+            // The passes after this one are expected to take as long per share of the job.
+            let elapsed = self.started.elapsed().as_secs_f64();
+            let remaining = (duration - out_time).max(0.0);
+            let later = (1.0 - self.pass.offset - self.pass.span).max(0.0) / self.pass.span.max(f64::EPSILON);
+            progress.eta = if out_time > 0.0 && elapsed > 1.0 {
+                Some(elapsed * remaining / out_time + elapsed * duration / out_time * later)
+            } else {
+                progress.speed.map(|speed| (remaining + duration * later) / speed)
+            };
+        } else if finished {
+            progress.percent = Some((self.pass.offset + self.pass.span) * 100.0);
+        }
+
+        progress
+    }
+
+    // The last progress seen, marked as failed or cancelled
+    fn stopped(&mut self, phase: FfmpegPhase, message: Option<String>) -> FfmpegProgress {
+        let mut progress = self.snapshot(false);
+        progress.phase = phase;
+        progress.eta = None;
+        progress.message = message;
+        progress
+    }
+}
+
+// Runs a command built with `command()` as a cancellable job, emitting progress relative to
+// `duration` (the expected output length in seconds). Outputs are removed if the job is cancelled.
+pub fn run_job(
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    cmd: &mut Command,
+    outputs: Vec<PathBuf>,
+    duration: Option<f64>,
+) -> Result<(), String> {
+    run_job_output(window, jobs, job_id, cmd, outputs, duration, Pass::WHOLE).map(|_| ())
+}
+
+// Like run_job, for one of several FFmpeg runs that make up the job
+pub fn run_pass(
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    cmd: &mut Command,
+    outputs: Vec<PathBuf>,
+    duration: Option<f64>,
+    pass: Pass,
+) -> Result<(), String> {
+    run_job_output(window, jobs, job_id, cmd, outputs, duration, pass).map(|_| ())
+}
+
+// Like run_pass, for a run the caller falls back from when it fails: only cancelling ends the job
+// with an event. Success doesn't finish the job either; call emit_finished if nothing follows.
+pub fn run_attempt(
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    cmd: &mut Command,
+    outputs: Vec<PathBuf>,
+    duration: Option<f64>,
+    pass: Pass,
+) -> Result<(), String> {
+    let pass = Pass { last: false, ..pass };
+    run(window, jobs, job_id, cmd, outputs, duration, pass, false).map(|_| ())
+}
+
+// Reports a job as finished after a run_attempt that turned out to be its last pass
+pub fn emit_finished(window: &Window, job_id: &str) {
+    let mut parser = ProgressParser::new(job_id, None, Pass::WHOLE);
+    let _ = window.emit(PROGRESS_EVENT, parser.snapshot(true));
+}
+
+// Like run_pass, but hands back what FFmpeg printed, for filters that report their results on stderr
+pub fn run_job_output(
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    cmd: &mut Command,
+    outputs: Vec<PathBuf>,
+    duration: Option<f64>,
+    pass: Pass,
+) -> Result<ProcessOutput, String> {
+    run(window, jobs, job_id, cmd, outputs, duration, pass, true)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run(
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    cmd: &mut Command,
+    outputs: Vec<PathBuf>,
+    duration: Option<f64>,
+    pass: Pass,
+    report_failure: bool,
+) -> Result<ProcessOutput, String> {
+    let mut parser = ProgressParser::new(job_id, duration, pass);
+    let _ = window.emit(PROGRESS_EVENT, parser.snapshot(false));
+
+    let result = jobs.run(job_id, cmd, outputs, |line| {
+        if let Some(progress) = parser.parse_line(line) {
+            let _ = window.emit(PROGRESS_EVENT, progress);
+        }
+    });
+
+    // Every way out other than success ends with a Failed or Cancelled event, so the UI can stop
+    // showing the job as running. Attempts leave failures to whatever the caller falls back to.
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            let message = if e.kind() == std::io::ErrorKind::NotFound {
+                "FFmpeg not found. Please install FFmpeg and add it to your PATH.".to_string()
+            } else {
+                format!("Failed to execute ffmpeg: {}", e)
+            };
+            if report_failure {
+                let _ = window.emit(PROGRESS_EVENT, parser.stopped(FfmpegPhase::Failed, Some(message.clone())));
+            }
+            return Err(message);
+        }
+    };
+
+    if output.cancelled {
+        let _ = window.emit(PROGRESS_EVENT, parser.stopped(FfmpegPhase::Cancelled, None));
+        return Err(jobs::cancelled_error(job_id));
+    }
+    if !output.success {
+        // FFmpeg puts the actual reason on its last line
+        let reason = output.stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or_default();
+        if report_failure {
+            let _ = window.emit(PROGRESS_EVENT, parser.stopped(FfmpegPhase::Failed, Some(reason.trim().to_string())));
+        }
+        return Err(format!("ffmpeg failed: {}", output.stderr));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(parser: &mut ProgressParser, block: &str) -> Option<FfmpegProgress> {
+        block.lines().filter_map(|line| parser.parse_line(line)).last()
+    }
+
+    #[test]
+    fn block_is_reported_on_the_progress_line() {
+        let mut parser = ProgressParser::new("job", Some(100.0), Pass::WHOLE);
+        assert!(parser.parse_line("frame=250").is_none());
+        let progress = feed(
+            &mut parser,
+            "fps=49.95\ntotal_size=1048576\nout_time_us=25000000\nspeed=2.5x\nprogress=continue",
+        )
+        .unwrap();
+        assert_eq!(progress.phase, FfmpegPhase::Running);
+        assert_eq!(progress.frame, Some(250));
+        assert_eq!(progress.fps, Some(49.95));
+        assert_eq!(progress.total_size, Some(1_048_576));
+        assert_eq!(progress.out_time, 25.0);
+        assert_eq!(progress.percent, Some(25.0));
+        assert_eq!(progress.speed, Some(2.5));
+        // Less than a second in, so the estimate comes from the speed
+        assert_eq!(progress.eta, Some(30.0));
+    }
+
+    #[test]
+    fn out_time_ms_is_microseconds_too() {
+        let mut parser = ProgressParser::new("job", Some(10.0), Pass::WHOLE);
+        let progress = feed(&mut parser, "out_time_ms=5000000\nprogress=continue").unwrap();
+        assert_eq!(progress.out_time, 5.0);
+        assert_eq!(progress.percent, Some(50.0));
+
+        // Negative timestamps at the very start count as zero
+        let progress = feed(&mut parser, "out_time_us=-23220\nprogress=continue").unwrap();
+        assert_eq!(progress.out_time, 0.0);
+    }
+
+    #[test]
+    fn unavailable_values_are_left_out() {
+        let mut parser = ProgressParser::new("job", None, Pass::WHOLE);
+        feed(&mut parser, "out_time_us=4000000\nprogress=continue");
+        let progress = feed(
+            &mut parser,
+            "frame=N/A\nfps=0.00\ntotal_size=N/A\nout_time_us=N/A\nspeed=N/A\nprogress=continue",
+        )
+        .unwrap();
+        assert_eq!((progress.frame, progress.fps, progress.total_size, progress.speed), (None, None, None, None));
+        // The last known position is kept
+        assert_eq!(progress.out_time, 4.0);
+        // Without a duration there is nothing to relate it to
+        assert_eq!((progress.percent, progress.eta), (None, None));
+        assert!(parser.parse_line("garbage").is_none());
+    }
+
+    #[test]
+    fn end_and_stopped_events() {
+        let mut parser = ProgressParser::new("job", Some(10.0), Pass::WHOLE);
+        feed(&mut parser, "out_time_us=4000000\nprogress=continue");
+
+        let failed = parser.stopped(FfmpegPhase::Failed, Some("Conversion failed!".to_string()));
+        assert_eq!(failed.phase, FfmpegPhase::Failed);
+        assert_eq!(failed.percent, Some(40.0));
+        assert_eq!(failed.eta, None);
+        assert_eq!(failed.message.as_deref(), Some("Conversion failed!"));
+
+        let end = parser.parse_line("progress=end").unwrap();
+        assert_eq!(end.phase, FfmpegPhase::Finished);
+        assert_eq!(end.percent, Some(100.0));
+        assert_eq!(end.eta, Some(0.0));
+        assert_eq!(end.job_id, "job");
+    }
+
+    #[test]
+    fn passes_report_their_share_of_the_job() {
+        assert_eq!(Pass::nth(1, 3).offset, 1.0 / 3.0);
+        assert!(!Pass::nth(1, 3).last && Pass::nth(2, 3).last);
+        assert_eq!(Pass::weighted(&[10.0, 30.0], 1), Pass { offset: 0.25, span: 0.75, last: true });
+
+        let mut first = ProgressParser::new("job", Some(10.0), Pass::nth(0, 2));
+        let progress = feed(&mut first, "out_time_us=5000000\nspeed=2x\nprogress=continue").unwrap();
+        assert_eq!(progress.percent, Some(25.0));
+        // 2.5 s left of this pass and 5 s for the second one
+        assert_eq!(progress.eta, Some(7.5));
+
+        // The end of an earlier pass is not the end of the job
+        let end = first.parse_line("progress=end").unwrap();
+        assert_eq!(end.phase, FfmpegPhase::Running);
+        assert_eq!(end.percent, Some(50.0));
+
+        let mut second = ProgressParser::new("job", Some(10.0), Pass::nth(1, 2));
+        let start = second.snapshot(false);
+        assert_eq!(start.percent, Some(50.0));
+        let progress = feed(&mut second, "out_time_us=5000000\nprogress=continue").unwrap();
+        assert_eq!(progress.percent, Some(75.0));
+        let end = second.parse_line("progress=end").unwrap();
+        assert_eq!((end.phase, end.percent), (FfmpegPhase::Finished, Some(100.0)));
+
+        // Without a duration an earlier pass still moves the job on when it ends
+        let mut unknown = ProgressParser::new("job", None, Pass::nth(0, 4));
+        assert_eq!(unknown.parse_line("progress=end").unwrap().percent, Some(25.0));
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tauri::State;
+
+// Result of a process run through the registry
+pub struct ProcessOutput {
+    pub success: bool,
+    pub cancelled: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+struct Job {
+    // None while no process of the job is running, e.g. between the passes of a multi-pass operation
+    child: Option<Arc<Mutex<Child>>>,
+    // Files the job writes to, removed again if the job gets cancelled
+    outputs: Vec<PathBuf>,
+    cancelled: bool,
+    paused: bool,
+    // Held by a JobReservation for the whole operation instead of a single process
+    reserved: bool,
+}
+
+impl Job {
+    fn new(reserved: bool) -> Self {
+        Job {
+            child: None,
+            outputs: Vec::new(),
+            cancelled: false,
+            paused: false,
+            reserved,
+        }
+    }
+}
+
+// Keeps a job id registered across every process of an operation (two-pass encodes, batches, …),
+// so a cancel that lands between two processes still stops the next one. The id is released on drop.
+pub struct JobReservation<'a> {
+    registry: &'a JobRegistry,
+    job_id: String,
+}
+
+impl JobReservation<'_> {
+    pub fn is_cancelled(&self) -> bool {
+        let jobs = self.registry.jobs.lock().unwrap();
+        jobs.get(&self.job_id).is_some_and(|job| job.cancelled)
+    }
+}
+
+impl Drop for JobReservation<'_> {
+    fn drop(&mut self) {
+        self.registry.jobs.lock().unwrap().remove(&self.job_id);
+    }
+}
+
+// Keeps every running yt-dlp / FFmpeg child process under the job id the frontend passed in,
+// so it can be cancelled, paused or resumed from another command.
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl JobRegistry {
+    // Registers `job_id` for an operation that runs several processes one after another
+    pub fn reserve(&self, job_id: &str) -> Result<JobReservation<'_>, String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.contains_key(job_id) {
+            return Err(format!("A job with id {} is already running", job_id));
+        }
+        jobs.insert(job_id.to_string(), Job::new(true));
+        Ok(JobReservation {
+            registry: self,
+            job_id: job_id.to_string(),
+        })
+    }
+
+    // Spawns the command, registers it under `job_id` and feeds every stdout line to `on_line`
+    // until the process exits. Partial outputs are deleted when the job was cancelled.
+    pub fn run(
+        &self,
+        job_id: &str,
+        cmd: &mut Command,
+        outputs: Vec<PathBuf>,
+        mut on_line: impl FnMut(&str),
+    ) -> std::io::Result<ProcessOutput> {
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        // Give the job its own process group so cancel/pause also reach the processes it spawns
+        // (yt-dlp runs ffmpeg for merging and post-processing)
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+
+        // Claim the id and spawn under one lock, so two runs with the same id can't both start.
+        // A reserved id is reused as long as none of its processes is running.
+        let (child, stdout, mut stderr, owned) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let owned = match jobs.get(job_id) {
+                None => true,
+                Some(job) if job.reserved && job.child.is_none() => false,
+                Some(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("A job with id {} is already running", job_id),
+                    ))
+                }
+            };
+            let job = jobs.entry(job_id.to_string()).or_insert_with(|| Job::new(false));
+            // Cancelled between two passes of the operation: don't start the next one
+            if job.cancelled {
+                return Ok(ProcessOutput {
+                    success: false,
+                    cancelled: true,
+                    stdout: String::new(),
+                    stderr: String::new(),
+                });
+            }
+
+            let mut child = match cmd.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    if owned {
+                        jobs.remove(job_id);
+                    }
+                    return Err(e);
+                }
+            };
+            if job.paused {
+                let _ = signal_tree(&child, Signal::Stop);
+            }
+            let stdout = child.stdout.take().expect("stdout is piped");
+            let stderr = child.stderr.take().expect("stderr is piped");
+            let child = Arc::new(Mutex::new(child));
+            job.child = Some(child.clone());
+            job.outputs = outputs;
+            (child, stdout, stderr, owned)
+        };
+
+        // Drain stderr on its own thread so a chatty process can't fill the pipe and stall
+        let stderr_reader = thread::spawn(move || {
+            let mut buffer = String::new();
+            let _ = stderr.read_to_string(&mut buffer);
+            buffer
+        });
+
+        let mut collected = String::new();
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            on_line(&line);
+            collected.push_str(&line);
+            collected.push('\n');
+        }
+
+        // Poll instead of blocking in wait() so cancel/pause can still lock the child meanwhile
+        let status = loop {
+            if let Some(status) = child.lock().unwrap().try_wait()? {
+                break status;
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        let stderr = stderr_reader.join().unwrap_or_default();
+
+        let (cancelled, outputs) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            if owned {
+                jobs.remove(job_id).map(|job| (job.cancelled, job.outputs))
+            } else {
+                // Keep the reservation (and its cancelled flag) for the next pass
+                jobs.get_mut(job_id).map(|job| {
+                    job.child = None;
+                    (job.cancelled, std::mem::take(&mut job.outputs))
+                })
+            }
+            .unwrap_or_default()
+        };
+        if cancelled {
+            for output in outputs {
+                let _ = fs::remove_file(output);
+            }
+        }
+
+        Ok(ProcessOutput {
+            success: status.success() && !cancelled,
+            cancelled,
+            stdout: collected,
+            stderr,
+        })
+    }
+
+    // Replaces the list of files that get removed if the job is cancelled
+    pub fn set_outputs(&self, job_id: &str, outputs: Vec<PathBuf>) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(job_id) {
+            job.outputs = outputs;
+        }
+    }
+
+    pub fn cancel(&self, job_id: &str) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id).ok_or(format!("No running job with id {}", job_id))?;
+
+        job.cancelled = true;
+        match &job.child {
+            Some(child) => kill_tree(&mut child.lock().unwrap()),
+            // Between two passes; the reservation stops the next one from starting
+            None => Ok(()),
+        }
+    }
+
+    pub fn pause(&self, job_id: &str) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id).ok_or(format!("No running job with id {}", job_id))?;
+
+        if !job.paused {
+            // A pass started later is stopped right after it spawns
+            if let Some(child) = &job.child {
+                signal_tree(&child.lock().unwrap(), Signal::Stop)?;
+            }
+            job.paused = true;
+        }
+        Ok(())
+    }
+
+    pub fn resume(&self, job_id: &str) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id).ok_or(format!("No running job with id {}", job_id))?;
+
+        if job.paused {
+            if let Some(child) = &job.child {
+                signal_tree(&child.lock().unwrap(), Signal::Continue)?;
+            }
+            job.paused = false;
+        }
+        Ok(())
+    }
+}
+
+// Shorthand for the error every job-aware command returns when it was cancelled
+pub fn cancelled_error(job_id: &str) -> String {
+    format!("Job {} was cancelled", job_id)
+}
+
+enum Signal {
+    Stop,
+    Continue,
+}
+
+#[cfg(unix)]
+fn kill_tree(child: &mut Child) -> Result<(), String> {
+    // Negative pid targets the whole process group created in run()
+    let result = unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+    if result != 0 {
+        child.kill().map_err(|e| format!("Failed to kill process: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn kill_tree(child: &mut Child) -> Result<(), String> {
+    let status = crate::create_hidden_command("taskkill")
+        .args(["/PID", &child.id().to_string(), "/T", "/F"])
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        _ => child.kill().map_err(|e| format!("Failed to kill process: {}", e)),
+    }
+}
+
+#[cfg(unix)]
+fn signal_tree(child: &Child, signal: Signal) -> Result<(), String> {
+    let signal = match signal {
+        Signal::Stop => libc::SIGSTOP,
+        Signal::Continue => libc::SIGCONT,
+    };
+    let result = unsafe { libc::kill(-(child.id() as i32), signal) };
+    if result != 0 {
+        return Err(format!("Failed to signal process: {}", std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn signal_tree(_child: &Child, _signal: Signal) -> Result<(), String> {
+    Err("Pausing jobs is not supported on Windows".to_string())
+}
+
+#[tauri::command]
+pub fn cancel_job(job_id: String, jobs: State<'_, JobRegistry>) -> Result<(), String> {
+    jobs.cancel(&job_id)
+}
+
+#[tauri::command]
+pub fn pause_job(job_id: String, jobs: State<'_, JobRegistry>) -> Result<(), String> {
+    jobs.pause(&job_id)
+}
+
+#[tauri::command]
+pub fn resume_job(job_id: String, jobs: State<'_, JobRegistry>) -> Result<(), String> {
+    jobs.resume(&job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_reserved_id_cant_be_taken_twice() {
+        let jobs = JobRegistry::default();
+        let reservation = jobs.reserve("job").unwrap();
+        assert!(jobs.reserve("job").is_err());
+        drop(reservation);
+        assert!(jobs.reserve("job").is_ok());
+    }
+
+    #[test]
+    fn a_cancel_between_passes_stops_the_next_one() {
+        let jobs = JobRegistry::default();
+        let reservation = jobs.reserve("job").unwrap();
+        jobs.cancel("job").unwrap();
+        assert!(reservation.is_cancelled());
+
+        // Never spawned, so a missing program doesn't matter
+        let output = jobs.run("job", &mut Command::new("yeyo-does-not-exist"), Vec::new(), |_| {}).unwrap();
+        assert!(output.cancelled && !output.success);
+    }
+
+    #[test]
+    fn a_failed_spawn_releases_the_id() {
+        let jobs = JobRegistry::default();
+        for _ in 0..2 {
+            let error = jobs.run("job", &mut Command::new("yeyo-does-not-exist"), Vec::new(), |_| {});
+            assert_eq!(error.err().map(|e| e.kind()), Some(std::io::ErrorKind::NotFound));
+        }
+        assert!(jobs.reserve("job").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn passes_run_under_a_reservation() {
+        let jobs = JobRegistry::default();
+        let reservation = jobs.reserve("job").unwrap();
+        for _ in 0..2 {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", "echo pass"]);
+            let output = jobs.run("job", &mut cmd, Vec::new(), |_| {}).unwrap();
+            assert!(output.success);
+            assert_eq!(output.stdout, "pass\n");
+        }
+        // Still held between and after the passes
+        assert!(jobs.reserve("job").is_err());
+        drop(reservation);
+        assert!(jobs.cancel("job").is_err());
+    }
+}
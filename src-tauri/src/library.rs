@@ -0,0 +1,615 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::media_type;
+use crate::persist;
+use crate::probe::{self, MediaProbe};
+use crate::watcher::LibraryWatcher;
+use crate::MediaFile;
+
+// Emitted once background probing has filled in metadata for new or changed files
+pub const LIBRARY_EVENT: &str = "media-library-updated";
+
+const LIBRARY_FILE: &str = "media_library.json";
+// Save progress every this many probed files so a crash mid-scan doesn't lose everything
+const SAVE_EVERY: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryEntry {
+    pub path: String,
+    pub name: String,
+    pub extension: String,
+    pub size: u64,
+    pub modified: u64,
+    pub file_type: String,
+    pub metadata: Option<EntryMetadata>,
+}
+
+// The parts of a probe that are worth keeping for filtering and display
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryMetadata {
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub bit_rate: Option<u64>,
+    pub format_name: String,
+    // Set when ffprobe could not read the file, so it is not retried until it changes
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryRoot {
+    pub directory: String,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LibraryState {
+    roots: Vec<LibraryRoot>,
+    entries: HashMap<String, LibraryEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RescanReport {
+    pub directory: String,
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LibraryQuery {
+    // Only entries below this directory
+    pub root: Option<String>,
+    pub file_type: Option<String>,
+    pub extensions: Option<Vec<String>>,
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    // Unix timestamps (seconds) for the file modification time
+    pub modified_after: Option<u64>,
+    pub modified_before: Option<u64>,
+    // Case-insensitive match on the file name
+    pub name_contains: Option<String>,
+    // "name" (default), "modified", "size" or "duration"
+    pub sort_by: Option<String>,
+    pub descending: bool,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+// Whether a registered root other than `directory` covers the entry, i.e. it lies below that root
+// and would be listed by it. Nested roots can scan different extensions, and each one only drops
+// what no other root keeps; extension-less files, found by sniffing, are left to every root.
+fn claimed_by_other_root(roots: &[LibraryRoot], directory: &str, entry: &LibraryEntry) -> bool {
+    roots.iter().any(|root| {
+        root.directory != directory
+            && Path::new(&entry.path).starts_with(&root.directory)
+            && (entry.extension.is_empty() || root.extensions.contains(&entry.extension))
+    })
+}
+
+pub struct MediaLibrary {
+    state: Mutex<LibraryState>,
+    path: Option<PathBuf>,
+    // Set when an unreadable library file couldn't be moved aside; saving would overwrite it
+    load_error: Option<String>,
+    // Snapshots are numbered while the state is locked and written after unlocking it;
+    // `written` is the newest one on disk, so an older snapshot never replaces a newer one
+    revision: AtomicU64,
+    written: Mutex<u64>,
+    probing: AtomicBool,
+}
+
+impl MediaLibrary {
+    // A library file that doesn't parse is moved aside to media_library.json.bak and the index starts empty
+    pub fn load(app: &AppHandle) -> Self {
+        let path = app.path_resolver().app_data_dir().map(|dir| dir.join(LIBRARY_FILE));
+
+        let (state, load_error) = match path.as_ref().map(|path| persist::load_json::<LibraryState>(path)) {
+            Some(Ok(state)) => (state, None),
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                (LibraryState::default(), Some(e))
+            }
+            None => (LibraryState::default(), None),
+        };
+
+        MediaLibrary {
+            state: Mutex::new(state),
+            path,
+            load_error,
+            revision: AtomicU64::new(0),
+            written: Mutex::new(0),
+            probing: AtomicBool::new(false),
+        }
+    }
+
+    // An index that is never saved
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        MediaLibrary {
+            state: Mutex::new(LibraryState::default()),
+            path: None,
+            load_error: None,
+            revision: AtomicU64::new(0),
+            written: Mutex::new(0),
+            probing: AtomicBool::new(false),
+        }
+    }
+
+    // Serializes the state and releases the lock before touching the disk
+    fn save(&self, state: MutexGuard<'_, LibraryState>) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(error) = &self.load_error {
+            return Err(error.clone());
+        }
+        let revision = self.revision.fetch_add(1, Ordering::SeqCst) + 1;
+        let json = serde_json::to_vec(&*state).map_err(|e| format!("Failed to serialize the library: {}", e))?;
+        drop(state);
+
+        let mut written = self.written.lock().unwrap();
+        if *written > revision {
+            return Ok(());
+        }
+        persist::write_atomic(path, &json)?;
+        *written = revision;
+        Ok(())
+    }
+
+    // For changes from the watcher and the prober, which have nobody to report a failed save to
+    fn save_or_log(&self, state: MutexGuard<'_, LibraryState>) {
+        if let Err(e) = self.save(state) {
+            eprintln!("{}", e);
+        }
+    }
+
+    pub fn roots(&self) -> Vec<LibraryRoot> {
+        self.state.lock().unwrap().roots.clone()
+    }
+
+    // Merges a fresh directory listing into the index. Files whose size and mtime are unchanged keep
+    // their probed metadata; new or changed files are queued for probing; vanished files are dropped,
+    // except below the `unread` directories the scan failed to list.
+    pub fn sync_directory(
+        &self,
+        directory: &str,
+        extensions: &[String],
+        files: &[MediaFile],
+        unread: &[String],
+    ) -> Result<RescanReport, String> {
+        let mut report = RescanReport::default();
+        self.merge_files(files, &mut report);
+        let seen: HashSet<String> = files.iter().map(|file| file.path.clone()).collect();
+        self.finish_sync(directory, extensions, Some(&seen), unread, report)
+    }
+
+    // Adds or updates part of a listing without saving, so a streaming scan can merge batch by batch
+    pub fn merge_files(&self, files: &[MediaFile], report: &mut RescanReport) {
+        let mut state = self.state.lock().unwrap();
+        for file in files {
+            match state.entries.get_mut(&file.path) {
+                Some(entry) if entry.size == file.size && entry.modified == file.modified => {
+                    // A type settled by a successful probe beats the sniffed one
+                    if entry.metadata.as_ref().is_none_or(|metadata| metadata.error.is_some()) {
+                        entry.file_type = file.file_type.clone();
+                    }
+                    report.unchanged += 1;
+                }
+                Some(entry) => {
+                    *entry = LibraryEntry::from_file(file);
+                    report.changed += 1;
+                }
+                None => {
+                    state.entries.insert(file.path.clone(), LibraryEntry::from_file(file));
+                    report.added += 1;
+                }
+            }
+        }
+    }
+
+    // Registers the root and saves what merge_files added. With the paths of the complete listing,
+    // entries below the directory that are not in it are dropped; pass None after a partial scan.
+    // Entries below `unread`, the directories the scan couldn't list, are kept either way.
+    pub fn finish_sync(
+        &self,
+        directory: &str,
+        extensions: &[String],
+        seen: Option<&HashSet<String>>,
+        unread: &[String],
+        mut report: RescanReport,
+    ) -> Result<RescanReport, String> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.roots.iter_mut().find(|root| root.directory == directory) {
+            Some(root) => root.extensions = extensions.to_vec(),
+            None => state.roots.push(LibraryRoot {
+                directory: directory.to_string(),
+                extensions: extensions.to_vec(),
+            }),
+        }
+
+        report.directory = directory.to_string();
+        if let Some(seen) = seen {
+            let root = Path::new(directory);
+            let roots = state.roots.clone();
+            let before = state.entries.len();
+            state.entries.retain(|path, entry| {
+                !Path::new(path).starts_with(root)
+                    || seen.contains(path)
+                    || unread.iter().any(|dir| Path::new(path).starts_with(dir))
+                    || claimed_by_other_root(&roots, directory, entry)
+            });
+            report.removed = before - state.entries.len();
+        }
+
+        self.save(state)?;
+        Ok(report)
+    }
+
+    // The registered root a path lives under, if any
+    pub fn root_for(&self, path: &Path) -> Option<LibraryRoot> {
+        let state = self.state.lock().unwrap();
+        state.roots.iter().find(|root| path.starts_with(&root.directory)).cloned()
+    }
+
+    // Probed metadata for a file, if the index has it for this exact version of the file
+    pub fn metadata_for(&self, file: &MediaFile) -> Option<EntryMetadata> {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .get(&file.path)
+            .filter(|entry| entry.size == file.size && entry.modified == file.modified)
+            .and_then(|entry| entry.metadata.clone())
+            .filter(|metadata| metadata.error.is_none())
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.state.lock().unwrap().entries.contains_key(path)
+    }
+
+    // Adds or refreshes a single file; returns false when nothing changed
+    pub fn upsert_file(&self, file: &MediaFile) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let changed = match state.entries.get(&file.path) {
+            Some(entry) => entry.size != file.size || entry.modified != file.modified,
+            None => true,
+        };
+        if changed {
+            state.entries.insert(file.path.clone(), LibraryEntry::from_file(file));
+            self.save_or_log(state);
+        }
+        changed
+    }
+
+    // Removes a file, or everything below it when a whole directory went away
+    pub fn remove_path(&self, path: &Path) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let before = state.entries.len();
+        state.entries.retain(|entry, _| !Path::new(entry).starts_with(path));
+        let removed = before - state.entries.len();
+        if removed > 0 {
+            self.save_or_log(state);
+        }
+        removed
+    }
+
+    // Moves entries to their new path, keeping the probed metadata. Works for single files and directories.
+    // `file` is a renamed file as found under its new name; a new extension can change its type.
+    pub fn rename_path(&self, from: &Path, to: &Path, file: Option<&MediaFile>) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let moved: Vec<String> = state
+            .entries
+            .keys()
+            .filter(|entry| Path::new(entry).starts_with(from))
+            .cloned()
+            .collect();
+
+        for old in &moved {
+            if let Some(mut entry) = state.entries.remove(old) {
+                let relative = Path::new(old).strip_prefix(from).unwrap_or(Path::new(""));
+                let new_path = if relative.as_os_str().is_empty() { to.to_path_buf() } else { to.join(relative) };
+                entry.path = new_path.to_string_lossy().to_string();
+                entry.name = new_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                if let Some(file) = file.filter(|file| file.path == entry.path) {
+                    entry.extension = extension_of(&file.path);
+                    entry.file_type = file.file_type.clone();
+                }
+                state.entries.insert(entry.path.clone(), entry);
+            }
+        }
+        if !moved.is_empty() {
+            self.save_or_log(state);
+        }
+        moved.len()
+    }
+
+    pub fn remove_root(&self, directory: &str) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        state.roots.retain(|root| root.directory != directory);
+        let root = Path::new(directory);
+        state.entries.retain(|path, _| !Path::new(path).starts_with(root));
+        self.save(state)
+    }
+
+    // Probes every entry that has no metadata yet on a background thread. Only one prober runs at a time;
+    // entries added while it is busy are picked up by its next pass.
+    pub fn spawn_probe(&self, app: &AppHandle) {
+        if self.probing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let app = app.clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            let library = app.state::<MediaLibrary>();
+            loop {
+                let pending: Vec<String> = {
+                    let state = library.state.lock().unwrap();
+                    state
+                        .entries
+                        .values()
+                        .filter(|entry| entry.metadata.is_none())
+                        .map(|entry| entry.path.clone())
+                        .collect()
+                };
+                if pending.is_empty() {
+                    library.probing.store(false, Ordering::SeqCst);
+                    // Something may have been added between the check and clearing the flag; if so, and
+                    // no other prober took over in the meantime, keep going
+                    if !library.has_unprobed() || library.probing.swap(true, Ordering::SeqCst) {
+                        break;
+                    }
+                    continue;
+                }
+
+                for (done, path) in pending.iter().enumerate() {
+                    let probed = probe::probe(path);
+                    // The scan only sniffed the file; the probe settles what it really contains
+                    let file_type = probed.as_ref().ok().and_then(media_type::type_from_probe);
+                    let metadata = EntryMetadata::from_probe(probed);
+                    let mut state = library.state.lock().unwrap();
+                    if let Some(entry) = state.entries.get_mut(path) {
+                        entry.metadata = Some(metadata);
+                        if let Some(file_type) = file_type {
+                            entry.file_type = file_type.as_str().to_string();
+                        }
+                    }
+                    if (done + 1) % SAVE_EVERY == 0 {
+                        library.save_or_log(state);
+                    }
+                }
+
+                library.save_or_log(library.state.lock().unwrap());
+            }
+
+            let _ = app.emit_all(LIBRARY_EVENT, ());
+        });
+    }
+
+    fn has_unprobed(&self) -> bool {
+        self.state.lock().unwrap().entries.values().any(|entry| entry.metadata.is_none())
+    }
+
+    pub fn query(&self, query: &LibraryQuery) -> Vec<LibraryEntry> {
+        let state = self.state.lock().unwrap();
+
+        let extensions: Option<Vec<String>> = query
+            .extensions
+            .as_ref()
+            .map(|list| list.iter().map(|ext| ext.trim_start_matches('.').to_lowercase()).collect());
+        let name_contains = query.name_contains.as_ref().map(|text| text.to_lowercase());
+        let duration = |entry: &LibraryEntry| entry.metadata.as_ref().and_then(|m| m.duration);
+
+        let mut results: Vec<LibraryEntry> = state
+            .entries
+            .values()
+            .filter(|entry| {
+                query.root.as_ref().is_none_or(|root| Path::new(&entry.path).starts_with(root))
+                    && query.file_type.as_ref().is_none_or(|kind| &entry.file_type == kind)
+                    && extensions.as_ref().is_none_or(|list| list.contains(&entry.extension))
+                    && query.modified_after.is_none_or(|after| entry.modified >= after)
+                    && query.modified_before.is_none_or(|before| entry.modified <= before)
+                    && name_contains.as_ref().is_none_or(|text| entry.name.to_lowercase().contains(text))
+                    // Entries that are not probed yet have no duration and don't match a duration range
+                    && query.min_duration.is_none_or(|min| duration(entry).is_some_and(|d| d >= min))
+                    && query.max_duration.is_none_or(|max| duration(entry).is_some_and(|d| d <= max))
+            })
+            .cloned()
+            .collect();
+
+        match query.sort_by.as_deref() {
+            Some("modified") => results.sort_by_key(|entry| entry.modified),
+            Some("size") => results.sort_by_key(|entry| entry.size),
+            Some("duration") => results.sort_by(|a, b| {
+                duration(a).unwrap_or(0.0).total_cmp(&duration(b).unwrap_or(0.0))
+            }),
+            _ => results.sort_by(|a, b| a.name.cmp(&b.name)),
+        }
+        if query.descending {
+            results.reverse();
+        }
+
+        results
+            .into_iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+fn extension_of(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+impl LibraryEntry {
+    fn from_file(file: &MediaFile) -> Self {
+        LibraryEntry {
+            path: file.path.clone(),
+            name: file.name.clone(),
+            extension: extension_of(&file.path),
+            size: file.size,
+            modified: file.modified,
+            file_type: file.file_type.clone(),
+            metadata: None,
+        }
+    }
+}
+
+impl EntryMetadata {
+    pub fn probe(path: &str) -> Self {
+        Self::from_probe(probe::probe(path))
+    }
+
+    fn from_probe(probed: Result<MediaProbe, String>) -> Self {
+        match probed {
+            Ok(media) => {
+                let video = media.primary_video();
+                let audio = media.primary_audio();
+                EntryMetadata {
+                    duration: media.duration(),
+                    width: video.and_then(|s| s.video.as_ref()).map(|v| v.width),
+                    height: video.and_then(|s| s.video.as_ref()).map(|v| v.height),
+                    video_codec: video.and_then(|s| s.codec_name.clone()),
+                    audio_codec: audio.and_then(|s| s.codec_name.clone()),
+                    bit_rate: media.format.bit_rate,
+                    format_name: media.format.format_name.clone(),
+                    error: None,
+                }
+            }
+            Err(e) => EntryMetadata {
+                duration: None,
+                width: None,
+                height: None,
+                video_codec: None,
+                audio_codec: None,
+                bit_rate: None,
+                format_name: String::new(),
+                error: Some(e),
+            },
+        }
+    }
+}
+
+// Walks every registered directory again (or just the given one) and updates the index. Every walk
+// covers the whole tree (see scan_media_files); only files that are new or changed get probed again.
+#[tauri::command]
+pub async fn library_rescan(
+    directory: Option<String>,
+    app: AppHandle,
+    library: State<'_, MediaLibrary>,
+) -> Result<Vec<RescanReport>, String> {
+    let roots: Vec<LibraryRoot> = library
+        .roots()
+        .into_iter()
+        .filter(|root| directory.as_ref().is_none_or(|dir| &root.directory == dir))
+        .collect();
+
+    if roots.is_empty() {
+        return Err("Directory is not part of the library".to_string());
+    }
+
+    let mut reports = Vec::new();
+    for root in roots {
+        let (files, scan) = crate::scanner::scan_directory_recursive(Path::new(&root.directory), &root.extensions)
+            .map_err(|e| format!("Failed to scan directory: {}", e))?;
+        reports.push(library.sync_directory(&root.directory, &root.extensions, &files, &scan.unread_paths())?);
+    }
+    library.spawn_probe(&app);
+
+    Ok(reports)
+}
+
+#[tauri::command]
+pub fn library_query(query: LibraryQuery, library: State<'_, MediaLibrary>) -> Vec<LibraryEntry> {
+    library.query(&query)
+}
+
+#[tauri::command]
+pub fn library_roots(library: State<'_, MediaLibrary>) -> Vec<LibraryRoot> {
+    library.roots()
+}
+
+#[tauri::command]
+pub fn library_remove_root(
+    directory: String,
+    library: State<'_, MediaLibrary>,
+    watcher: State<'_, LibraryWatcher>,
+) -> Result<(), String> {
+    watcher.unwatch(&directory);
+    library.remove_root(&directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> MediaLibrary {
+        MediaLibrary::in_memory()
+    }
+
+    fn file(path: &str) -> MediaFile {
+        MediaFile {
+            name: Path::new(path).file_name().unwrap().to_string_lossy().to_string(),
+            path: path.to_string(),
+            size: 1,
+            modified: 1,
+            file_type: "video".to_string(),
+        }
+    }
+
+    #[test]
+    fn nested_roots_keep_each_others_entries() {
+        let library = library();
+        let video = ["mp4".to_string()];
+        let audio = ["mp3".to_string()];
+        library.sync_directory("/m", &video, &[file("/m/a.mp4"), file("/m/music/b.mp4")], &[]).unwrap();
+        library.sync_directory("/m/music", &audio, &[file("/m/music/c.mp3")], &[]).unwrap();
+        assert!(library.contains("/m/music/b.mp4"));
+
+        // A rescan of the parent doesn't see the nested root's mp3s
+        let report = library.sync_directory("/m", &video, &[file("/m/a.mp4"), file("/m/music/b.mp4")], &[]).unwrap();
+        assert_eq!(report.removed, 0);
+        assert!(library.contains("/m/music/c.mp3"));
+
+        // Files gone from disk are still dropped by the root that lists them
+        let report = library.sync_directory("/m", &video, &[file("/m/a.mp4")], &[]).unwrap();
+        assert_eq!(report.removed, 1);
+        assert!(!library.contains("/m/music/b.mp4"));
+    }
+
+    #[test]
+    fn unread_directories_keep_their_entries() {
+        let library = library();
+        let video = ["mp4".to_string()];
+        library.sync_directory("/m", &video, &[file("/m/a.mp4"), file("/m/nas/b.mp4"), file("/m/nas/x/c.mp4")], &[]).unwrap();
+
+        // The share dropped during the rescan, so nothing below it was listed
+        let report = library.sync_directory("/m", &video, &[file("/m/a.mp4")], &["/m/nas".to_string()]).unwrap();
+        assert_eq!(report.removed, 0);
+        assert!(library.contains("/m/nas/b.mp4"));
+        assert!(library.contains("/m/nas/x/c.mp4"));
+
+        // A partial scan prunes nothing at all
+        let report = library.finish_sync("/m", &video, None, &[], RescanReport::default()).unwrap();
+        assert_eq!(report.removed, 0);
+        assert!(library.contains("/m/nas/b.mp4"));
+    }
+}
@@ -0,0 +1,1002 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tauri::Window;
+
+use crate::capabilities::{self, FfmpegCapabilities};
+use crate::concat::{self, ConcatOptions};
+use crate::ffmpeg_progress::{self, Pass};
+use crate::jobs::JobRegistry;
+use crate::media_type::{self, MediaType};
+use crate::probe::{self, MediaProbe};
+
+// How far a stream-copied loop may miss the target before it is redone with re-encoding (seconds)
+const COPY_TOLERANCE: f64 = 0.1;
+// Each play is a line in the concat list of a playlist; a sanity limit for tiny clips and huge targets
+const MAX_PLAYLIST_ENTRIES: usize = 20_000;
+// Video codec of the playlist pieces. Lossless H.264 is several times smaller than FFV1 and quick to
+// write, which matters when the clips add up to hours of 1080p; FFV1 is for builds without libx264.
+const COMPACT_PIECE_VIDEO: &[&str] = &["-c:v", "libx264", "-qp", "0", "-preset", "ultrafast"];
+const LOSSLESS_PIECE_VIDEO: &[&str] = &["-c:v", "ffv1"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LoopOptions {
+    // Crossfade every seam instead of cutting hard from the end back to the start
+    pub seamless: bool,
+    // Crossfade lengths at each seam in seconds; 0 leaves that stream with a clean cut
+    pub video_crossfade: f64,
+    pub audio_crossfade: f64,
+    // Seconds to fade the output to black and silence at the end; 0 for none
+    pub fade_out: f64,
+    // Skip the stream copy attempt
+    pub force_reencode: bool,
+    // Encoder settings when re-encoding; FFmpeg's defaults for the container otherwise
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub crf: Option<u32>,
+    pub preset: Option<String>,
+    // More clips to fill the target with after the input; turns on playlist mode. Playlists
+    // crossfade between clips by the longer of the two crossfade lengths.
+    pub playlist: Vec<String>,
+    // Play the clips in random order, reshuffled every round, instead of in the given order
+    pub shuffle: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopMethod {
+    // -stream_loop with stream copy
+    Copy,
+    // -stream_loop with re-encoding, cutting hard at the seams
+    Reencode,
+    // A crossfaded loop unit, repeated and re-encoded
+    Seamless,
+    // Several clips normalised and joined one after another
+    Playlist,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoopResult {
+    pub output: String,
+    pub method: LoopMethod,
+    // How many times the input was played back to fill the target duration
+    pub loops: u32,
+    // Duration of the written file
+    pub duration: f64,
+    // Why stream copy was abandoned, when it was tried first
+    pub fallback_reason: Option<String>,
+    // Clips in the order they were played, in playlist mode
+    pub sequence: Vec<String>,
+}
+
+// The input and the streams that end up in the loop
+struct LoopSource<'a> {
+    path: &'a str,
+    duration: f64,
+    video: Option<u32>,
+    audio: Option<u32>,
+}
+
+impl LoopSource<'_> {
+    fn map_args(&self, input: usize) -> Vec<String> {
+        [self.video, self.audio]
+            .into_iter()
+            .flatten()
+            .flat_map(|index| ["-map".to_string(), format!("{}:{}", input, index)])
+            .collect()
+    }
+}
+
+fn encoder_args(options: &LoopOptions, output: &Path, has_video: bool, has_audio: bool) -> Vec<String> {
+    let mut args = Vec::new();
+    if has_video {
+        if let Some(codec) = &options.video_codec {
+            args.extend(["-c:v".to_string(), codec.clone()]);
+        }
+        // Only pass the quality options the encoder understands, like run_video_conversion does
+        let format = output.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let encoder = options.video_codec.as_deref().unwrap_or_else(|| capabilities::default_video_encoder(&format));
+        let rules = capabilities::encoder_rules(encoder);
+        if let (Some(crf), Some(_)) = (options.crf, rules.crf) {
+            args.extend(["-crf".to_string(), crf.to_string()]);
+            if rules.zero_bitrate_for_crf {
+                args.extend(["-b:v".to_string(), "0".to_string()]);
+            }
+        }
+        if let Some(preset) = options.preset.as_ref().filter(|_| !rules.presets.is_empty()) {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+    }
+    if has_audio {
+        if let Some(codec) = &options.audio_codec {
+            args.extend(["-c:a".to_string(), codec.clone()]);
+        }
+    }
+    args
+}
+
+fn fade_out_filters(options: &LoopOptions, target: f64) -> (Option<String>, Option<String>) {
+    if options.fade_out <= 0.0 {
+        return (None, None);
+    }
+    let fade = options.fade_out.min(target);
+    let start = target - fade;
+    (
+        Some(format!("fade=t=out:st={:.3}:d={:.3}", start, fade)),
+        Some(format!("afade=t=out:st={:.3}:d={:.3}", start, fade)),
+    )
+}
+
+// Returns why a stream-copied loop can't be used as it is, if it can't
+fn copy_mismatch(output: &Path, target: f64) -> Option<String> {
+    match probe::probe(&output.to_string_lossy()) {
+        Ok(media) => duration_mismatch(&media, target),
+        Err(e) => Some(format!("Could not read the stream copied file: {}", e)),
+    }
+}
+
+// Stream copy cuts at packets, so the file or one of its streams can miss the target
+fn duration_mismatch(media: &MediaProbe, target: f64) -> Option<String> {
+    let durations = media
+        .duration()
+        .into_iter()
+        .map(|d| ("The file", d))
+        .chain(media.primary_video().and_then(|s| s.duration).map(|d| ("The video", d)))
+        .chain(media.primary_audio().and_then(|s| s.duration).map(|d| ("The audio", d)));
+    for (what, duration) in durations {
+        if (duration - target).abs() > COPY_TOLERANCE {
+            return Some(format!("{} came out {:.2}s long instead of {:.2}s", what, duration, target));
+        }
+    }
+    None
+}
+
+// Tried first and replaced by reencode_loop when it fails, so it runs as an attempt that doesn't
+// end the job
+fn copy_loop(
+    source: &LoopSource,
+    target: f64,
+    loops: u32,
+    output: &Path,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<(), String> {
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-y", "-stream_loop", &(loops - 1).to_string(), "-i", source.path]);
+    cmd.args(["-t", &target.to_string(), "-c", "copy"]);
+    cmd.arg(output);
+    ffmpeg_progress::run_attempt(window, jobs, job_id, &mut cmd, vec![output.to_path_buf()], Some(target), Pass::nth(0, 2))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn reencode_loop(
+    source: &LoopSource,
+    target: f64,
+    loops: u32,
+    options: &LoopOptions,
+    output: &Path,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    pass: Pass,
+) -> Result<(), String> {
+    let (video_fade, audio_fade) = fade_out_filters(options, target);
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-y", "-stream_loop", &(loops - 1).to_string(), "-i", source.path]);
+    cmd.args(source.map_args(0));
+    cmd.args(["-t", &target.to_string()]);
+    if let (Some(filter), Some(_)) = (video_fade, source.video) {
+        cmd.args(["-vf", &filter]);
+    }
+    if let (Some(filter), Some(_)) = (audio_fade, source.audio) {
+        cmd.args(["-af", &filter]);
+    }
+    cmd.args(encoder_args(options, output, source.video.is_some(), source.audio.is_some()));
+    cmd.arg(output);
+    ffmpeg_progress::run_pass(window, jobs, job_id, &mut cmd, vec![output.to_path_buf()], Some(target), pass)
+}
+
+// Filter for one stream of the loop unit: the input from `overlap` to its end, fading over the
+// last `fade` seconds into the input just before `overlap`, so the unit ends where it begins.
+// Video and audio share `overlap`, which keeps them in sync when their fade lengths differ.
+fn unit_filter(kind: char, index: u32, duration: f64, overlap: f64, fade: f64) -> String {
+    let (split, trim, setpts) = if kind == 'v' { ("split", "trim", "setpts") } else { ("asplit", "atrim", "asetpts") };
+    if fade <= 0.0 {
+        return format!("[0:{index}]{trim}=start={overlap:.3},{setpts}=PTS-STARTPTS[{kind}u]");
+    }
+    let join = if kind == 'v' {
+        format!("xfade=transition=fade:duration={:.3}:offset={:.3}", fade, duration - overlap - fade)
+    } else {
+        format!("acrossfade=d={:.3}", fade)
+    };
+    format!(
+        "[0:{index}]{split}=2[{kind}body][{kind}head];\
+         [{kind}body]{trim}=start={overlap:.3},{setpts}=PTS-STARTPTS[{kind}b];\
+         [{kind}head]{trim}=start={head:.3}:end={overlap:.3},{setpts}=PTS-STARTPTS[{kind}h];\
+         [{kind}b][{kind}h]{join}[{kind}u]",
+        head = overlap - fade,
+    )
+}
+
+// The two FFmpeg runs of a seamless loop
+struct SeamlessPlan {
+    // Repeats of the unit after the intro
+    units: u32,
+    unit_length: f64,
+    // Renders the unit to `unit_path`
+    unit: Command,
+    // Plays the intro and the repeated unit into the output
+    render: Command,
+}
+
+fn seamless_plan(
+    source: &LoopSource,
+    target: f64,
+    options: &LoopOptions,
+    unit_path: &Path,
+    output: &Path,
+) -> Result<SeamlessPlan, String> {
+    let video_fade = if source.video.is_some() { options.video_crossfade.max(0.0) } else { 0.0 };
+    let audio_fade = if source.audio.is_some() { options.audio_crossfade.max(0.0) } else { 0.0 };
+    let overlap = video_fade.max(audio_fade);
+    if source.duration <= overlap * 2.0 {
+        return Err("Crossfades must be shorter than half of the input".to_string());
+    }
+
+    let unit_length = source.duration - overlap;
+    let units = crate::calculate_loops(unit_length, target - overlap).max(1);
+
+    let mut graph = Vec::new();
+    if let Some(index) = source.video {
+        graph.push(unit_filter('v', index, source.duration, overlap, video_fade));
+    }
+    if let Some(index) = source.audio {
+        graph.push(unit_filter('a', index, source.duration, overlap, audio_fade));
+    }
+    let mut unit = ffmpeg_progress::command();
+    unit.args(["-y", "-i", source.path, "-filter_complex", &graph.join(";")]);
+    if source.video.is_some() {
+        unit.args(["-map", "[vu]", "-c:v", "ffv1"]);
+    }
+    if source.audio.is_some() {
+        unit.args(["-map", "[au]", "-c:a", "flac"]);
+    }
+    unit.arg(unit_path);
+
+    // Intro (input up to the seam) then the repeated unit, cut to length and faded out
+    let mut labels = String::new();
+    let mut graph = Vec::new();
+    if let Some(index) = source.video {
+        graph.push(format!("[0:{}]setpts=PTS-STARTPTS[vi]", index));
+        labels.push_str("[vi]");
+    }
+    if let Some(index) = source.audio {
+        graph.push(format!("[0:{}]asetpts=PTS-STARTPTS[ai]", index));
+        labels.push_str("[ai]");
+    }
+    if source.video.is_some() {
+        labels.push_str("[1:v:0]");
+    }
+    if source.audio.is_some() {
+        labels.push_str("[1:a:0]");
+    }
+    let (video_out, audio_out) = fade_out_filters(options, target);
+    graph.push(format!(
+        "{}concat=n=2:v={}:a={}{}{}",
+        labels,
+        source.video.is_some() as u8,
+        source.audio.is_some() as u8,
+        if source.video.is_some() { "[vc]" } else { "" },
+        if source.audio.is_some() { "[ac]" } else { "" },
+    ));
+    if source.video.is_some() {
+        graph.push(format!("[vc]{}[vo]", video_out.unwrap_or_else(|| "null".to_string())));
+    }
+    if source.audio.is_some() {
+        graph.push(format!("[ac]{}[ao]", audio_out.unwrap_or_else(|| "anull".to_string())));
+    }
+
+    let mut render = ffmpeg_progress::command();
+    render.args(["-y", "-t", &format!("{:.3}", overlap), "-i", source.path]);
+    render.args(["-stream_loop", &(units - 1).to_string()]);
+    render.arg("-i").arg(unit_path);
+    render.args(["-filter_complex", &graph.join(";")]);
+    if source.video.is_some() {
+        render.args(["-map", "[vo]"]);
+    }
+    if source.audio.is_some() {
+        render.args(["-map", "[ao]"]);
+    }
+    render.args(["-t", &target.to_string()]);
+    render.args(encoder_args(options, output, source.video.is_some(), source.audio.is_some()));
+    render.arg(output);
+
+    Ok(SeamlessPlan { units, unit_length, unit, render })
+}
+
+// Renders a lossless loop unit with the crossfades baked in, then plays the start of the input up
+// to the seam followed by the unit as often as needed. Returns the number of units used.
+fn seamless_loop(
+    source: &LoopSource,
+    target: f64,
+    options: &LoopOptions,
+    output: &Path,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<u32, String> {
+    let unit_path = std::env::temp_dir().join(format!("yeyo-loop-{}.mkv", job_id));
+    let SeamlessPlan { units, unit_length, mut unit, mut render } =
+        seamless_plan(source, target, options, &unit_path, output)?;
+
+    let weights = [unit_length, target];
+    let unit_pass = Pass::weighted(&weights, 0);
+    let result = ffmpeg_progress::run_pass(window, jobs, job_id, &mut unit, Vec::new(), Some(unit_length), unit_pass)
+        .and_then(|_| {
+            let outputs = vec![output.to_path_buf()];
+            ffmpeg_progress::run_pass(window, jobs, job_id, &mut render, outputs, Some(target), Pass::weighted(&weights, 1))
+        });
+    let _ = fs::remove_file(&unit_path);
+    result.map(|_| units)
+}
+
+// Order in which to play clips of the given durations until they fill `target` seconds, where each
+// join overlaps by `crossfade`. Every clip plays once per round, and no clip follows itself.
+fn plan_playlist(durations: &[f64], target: f64, crossfade: f64, shuffle: bool) -> Result<Vec<usize>, String> {
+    let mut rng = rand::thread_rng();
+    let mut order: Vec<usize> = Vec::new();
+    let mut round: Vec<usize> = Vec::new();
+    let mut total = 0.0;
+
+    while total < target {
+        if round.is_empty() {
+            round = (0..durations.len()).collect();
+            if shuffle {
+                round.shuffle(&mut rng);
+                if round.len() > 1 && order.last() == round.first() {
+                    round.swap(0, 1);
+                }
+            }
+            // Taken from the back below
+            round.reverse();
+        }
+        let Some(next) = round.pop() else {
+            break;
+        };
+        total += durations[next] - if order.is_empty() { 0.0 } else { crossfade };
+        order.push(next);
+        if order.len() > MAX_PLAYLIST_ENTRIES {
+            return Err(format!(
+                "Filling {:.0} seconds would take more than {} clips; use longer clips or a shorter target",
+                target, MAX_PLAYLIST_ENTRIES
+            ));
+        }
+    }
+    Ok(order)
+}
+
+// One entry of a playlist's concat list
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Piece {
+    // Part of a clip: the whole clip without crossfades, otherwise 0 = head, 1 = body, 2 = tail
+    Clip(usize, usize),
+    // The crossfade from the tail of one clip into the head of the next
+    Join(usize, usize),
+}
+
+// The pieces that play the clips in `order`. With crossfades every clip plays its body, the joins
+// sit between them, and only the first head and the last tail are played.
+fn piece_sequence(order: &[usize], crossfade: bool) -> Vec<Piece> {
+    if !crossfade {
+        return order.iter().map(|&clip| Piece::Clip(clip, 0)).collect();
+    }
+    let mut sequence = Vec::new();
+    for (position, &clip) in order.iter().enumerate() {
+        match position {
+            0 => sequence.push(Piece::Clip(clip, 0)),
+            _ => sequence.push(Piece::Join(order[position - 1], clip)),
+        }
+        sequence.push(Piece::Clip(clip, 1));
+    }
+    if let Some(&last) = order.last() {
+        sequence.push(Piece::Clip(last, 2));
+    }
+    sequence
+}
+
+// What every piece of a playlist holds; the concat demuxer needs the same codecs in all of them
+#[derive(Debug, Clone, Copy)]
+struct PieceFormat {
+    has_video: bool,
+    has_audio: bool,
+    video_codec: &'static [&'static str],
+}
+
+impl PieceFormat {
+    fn new(has_video: bool, has_audio: bool, capabilities: Option<&FfmpegCapabilities>) -> Self {
+        let compact = capabilities.is_some_and(|capabilities| capabilities.has_encoder("libx264"));
+        PieceFormat {
+            has_video,
+            has_audio,
+            video_codec: if compact { COMPACT_PIECE_VIDEO } else { LOSSLESS_PIECE_VIDEO },
+        }
+    }
+}
+
+// Output arguments for a lossless playlist piece holding the streams labelled `suffix`
+fn piece_args(suffix: &str, format: &PieceFormat, path: &Path) -> Vec<String> {
+    let mut args = Vec::new();
+    if format.has_video {
+        args.extend(["-map".to_string(), format!("[v{}]", suffix)]);
+        args.extend(format.video_codec.iter().map(|arg| arg.to_string()));
+    }
+    if format.has_audio {
+        args.extend(["-map".to_string(), format!("[a{}]", suffix), "-c:a".to_string(), "flac".to_string()]);
+    }
+    args.push(path.to_string_lossy().to_string());
+    args
+}
+
+// Renders one clip normalised to the playlist's shared parameters. Without a crossfade that is the
+// single piece in `pieces`; with one the clip is split into [head, body, tail], its first and last
+// `crossfade` seconds and the part in between, so each join is rendered once per pair of clips.
+#[allow(clippy::too_many_arguments)]
+fn normalize_clip(
+    path: &str,
+    media: &MediaProbe,
+    duration: f64,
+    concat_options: &ConcatOptions,
+    format: &PieceFormat,
+    pieces: &[PathBuf],
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    pass: Pass,
+) -> Result<(), String> {
+    let (has_video, has_audio) = (format.has_video, format.has_audio);
+    let mut graph = concat::normalize_filters(std::slice::from_ref(media), &[duration], concat_options, has_video, has_audio);
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-y", "-i", path]);
+    let crossfade = concat_options.crossfade;
+    if crossfade <= 0.0 {
+        graph.pop();
+        cmd.args(["-filter_complex", &graph]);
+        cmd.args(piece_args("0", format, &pieces[0]));
+    } else {
+        let body_end = duration - crossfade;
+        for (kind, split, trim, setpts, wanted) in
+            [('v', "split", "trim", "setpts", has_video), ('a', "asplit", "atrim", "asetpts", has_audio)]
+        {
+            if wanted {
+                graph.push_str(&format!(
+                    "[{kind}0]{split}=3[{kind}h][{kind}b][{kind}t];\
+                     [{kind}h]{trim}=end={crossfade:.3},{setpts}=PTS-STARTPTS[{kind}head];\
+                     [{kind}b]{trim}=start={crossfade:.3}:end={body_end:.3},{setpts}=PTS-STARTPTS[{kind}body];\
+                     [{kind}t]{trim}=start={body_end:.3},{setpts}=PTS-STARTPTS[{kind}tail];"
+                ));
+            }
+        }
+        graph.pop();
+        cmd.args(["-filter_complex", &graph]);
+        for (suffix, piece) in ["head", "body", "tail"].iter().zip(pieces) {
+            cmd.args(piece_args(suffix, format, piece));
+        }
+    }
+    ffmpeg_progress::run_pass(window, jobs, job_id, &mut cmd, Vec::new(), Some(duration), pass)
+}
+
+// Crossfades the tail of one clip into the head of the next; both are `crossfade` seconds long
+#[allow(clippy::too_many_arguments)]
+fn render_join(
+    tail: &Path,
+    head: &Path,
+    crossfade: f64,
+    format: &PieceFormat,
+    output: &Path,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    pass: Pass,
+) -> Result<(), String> {
+    let mut graph = Vec::new();
+    if format.has_video {
+        graph.push(format!("[0:v][1:v]xfade=transition=fade:duration={:.3}:offset=0[vjoin]", crossfade));
+    }
+    if format.has_audio {
+        graph.push(format!("[0:a][1:a]acrossfade=d={:.3}[ajoin]", crossfade));
+    }
+    let mut cmd = ffmpeg_progress::command();
+    cmd.arg("-y").arg("-i").arg(tail).arg("-i").arg(head);
+    cmd.args(["-filter_complex", &graph.join(";")]);
+    cmd.args(piece_args("join", format, output));
+    ffmpeg_progress::run_pass(window, jobs, job_id, &mut cmd, Vec::new(), Some(crossfade), pass)
+}
+
+// A playlist is named after its first clip. When that clip is audio only but another one has video,
+// an audio container couldn't hold the output, so it takes the container of the first clip with
+// video instead.
+fn playlist_output(output: &Path, clips: &[&String], probes: &[MediaProbe]) -> PathBuf {
+    let audio_only = |path: &Path| {
+        let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        media_type::type_for_extension(&ext) == MediaType::Audio
+    };
+    let Some((video_clip, _)) = clips.iter().zip(probes).find(|(_, media)| media.primary_video().is_some()) else {
+        return output.to_path_buf();
+    };
+    if !audio_only(output) {
+        return output.to_path_buf();
+    }
+    match Path::new(video_clip.as_str()).extension().filter(|_| !audio_only(Path::new(video_clip.as_str()))) {
+        Some(ext) => output.with_extension(ext),
+        None => output.with_extension("mkv"),
+    }
+}
+
+// Normalises every distinct clip once into lossless pieces, renders each crossfaded join once per
+// pair of clips that meet, then plays the pieces in planned order through the concat demuxer. The
+// number of FFmpeg inputs depends on the number of clips, not on how often they are played.
+#[allow(clippy::too_many_arguments)]
+fn playlist_loop(
+    clips: &[String],
+    target: f64,
+    options: &LoopOptions,
+    output: &Path,
+    capabilities: Option<&FfmpegCapabilities>,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<LoopResult, String> {
+    // The same file twice would defeat the no-repeat rule
+    let mut unique: Vec<&String> = Vec::new();
+    for clip in clips {
+        if !unique.contains(&clip) {
+            unique.push(clip);
+        }
+    }
+    if unique.len() < 2 {
+        return Err("A playlist needs at least two different clips".to_string());
+    }
+
+    let probes: Vec<MediaProbe> = unique
+        .iter()
+        .map(|clip| probe::probe(clip).map_err(|e| format!("{}: {}", clip, e)))
+        .collect::<Result<_, _>>()?;
+    let output = &playlist_output(output, &unique, &probes);
+    if unique.iter().any(|clip| Path::new(clip.as_str()) == output) {
+        return Err("The output would overwrite one of the clips".to_string());
+    }
+    let durations: Vec<f64> = probes
+        .iter()
+        .zip(&unique)
+        .map(|(media, clip)| media.duration().filter(|d| *d > 0.0).ok_or(format!("{}: unknown duration", clip)))
+        .collect::<Result<_, _>>()?;
+
+    let crossfade = options.video_crossfade.max(options.audio_crossfade).max(0.0);
+    let shortest = durations.iter().cloned().fold(f64::INFINITY, f64::min);
+    // Every clip gives up a crossfade at both ends
+    if crossfade > 0.0 && crossfade * 2.0 >= shortest {
+        return Err(format!(
+            "Crossfade of {:.2}s is longer than half of the shortest clip ({:.2}s)",
+            crossfade, shortest
+        ));
+    }
+
+    let order = plan_playlist(&durations, target, crossfade, options.shuffle)?;
+    let format = PieceFormat::new(
+        probes.iter().any(|media| media.primary_video().is_some()),
+        probes.iter().any(|media| media.primary_audio().is_some()),
+        capabilities,
+    );
+    // Fix the shared parameters up front, as every clip is normalised on its own
+    let (width, height, fps) = concat::output_params(&probes, &ConcatOptions::default());
+    let concat_options = ConcatOptions {
+        crossfade,
+        width: Some(width),
+        height: Some(height),
+        fps: Some(fps),
+        ..ConcatOptions::default()
+    };
+
+    let temp_dir = std::env::temp_dir();
+    let temp = |name: String| temp_dir.join(format!("yeyo-playlist-{}-{}", job_id, name));
+    // [head, body, tail] per clip, or just the normalised clip without a crossfade
+    let pieces: Vec<Vec<PathBuf>> = (0..unique.len())
+        .map(|i| {
+            if crossfade > 0.0 {
+                ["head", "body", "tail"].iter().map(|part| temp(format!("{}-{}.mkv", i, part))).collect()
+            } else {
+                vec![temp(format!("{}.mkv", i))]
+            }
+        })
+        .collect();
+    let sequence = piece_sequence(&order, crossfade > 0.0);
+    let mut joins: Vec<((usize, usize), PathBuf)> = Vec::new();
+    for piece in &sequence {
+        if let Piece::Join(from, to) = *piece {
+            if !joins.iter().any(|(pair, _)| *pair == (from, to)) {
+                joins.push(((from, to), temp(format!("{}-{}.mkv", from, to))));
+            }
+        }
+    }
+    let list_path = temp("list.txt".to_string());
+    let list: String = sequence
+        .iter()
+        .map(|piece| match *piece {
+            Piece::Clip(clip, part) => concat::concat_list_entry(&pieces[clip][part]),
+            Piece::Join(from, to) => {
+                let (_, join) = joins.iter().find(|(pair, _)| *pair == (from, to)).unwrap();
+                concat::concat_list_entry(join)
+            }
+        })
+        .collect();
+
+    let (video_fade, audio_fade) = fade_out_filters(options, target);
+    let mut render = ffmpeg_progress::command();
+    render.args(["-y", "-f", "concat", "-safe", "0", "-i"]);
+    render.arg(&list_path);
+    if format.has_video {
+        render.args(["-map", "0:v"]);
+        if let Some(filter) = &video_fade {
+            render.args(["-vf", filter]);
+        }
+    }
+    if format.has_audio {
+        render.args(["-map", "0:a"]);
+        if let Some(filter) = &audio_fade {
+            render.args(["-af", filter]);
+        }
+    }
+    render.args(["-t", &target.to_string()]);
+    render.args(encoder_args(options, output, format.has_video, format.has_audio));
+    render.arg(output);
+
+    // One pass per clip, one per join and the final render, weighted by how much each one encodes
+    let weights: Vec<f64> = durations
+        .iter()
+        .cloned()
+        .chain(joins.iter().map(|_| crossfade))
+        .chain(std::iter::once(target))
+        .collect();
+    let result = unique
+        .iter()
+        .enumerate()
+        .try_for_each(|(i, clip)| {
+            let pass = Pass::weighted(&weights, i);
+            normalize_clip(clip, &probes[i], durations[i], &concat_options, &format, &pieces[i], window, jobs, job_id, pass)
+        })
+        .and_then(|_| {
+            joins.iter().enumerate().try_for_each(|(j, ((from, to), join))| {
+                let pass = Pass::weighted(&weights, unique.len() + j);
+                render_join(&pieces[*from][2], &pieces[*to][0], crossfade, &format, join, window, jobs, job_id, pass)
+            })
+        })
+        .and_then(|_| fs::write(&list_path, &list).map_err(|e| format!("Failed to write concat list: {}", e)))
+        .and_then(|_| {
+            let outputs = vec![output.to_path_buf()];
+            let pass = Pass::weighted(&weights, weights.len() - 1);
+            ffmpeg_progress::run_pass(window, jobs, job_id, &mut render, outputs, Some(target), pass)
+        });
+
+    let temps = pieces.iter().flatten().chain(joins.iter().map(|(_, join)| join)).chain(std::iter::once(&list_path));
+    for path in temps {
+        let _ = fs::remove_file(path);
+    }
+    result?;
+
+    let duration = probe::probe(&output.to_string_lossy())
+        .ok()
+        .and_then(|media| media.duration())
+        .unwrap_or(target);
+    Ok(LoopResult {
+        output: output.to_string_lossy().to_string(),
+        method: LoopMethod::Playlist,
+        loops: order.len() as u32,
+        duration,
+        fallback_reason: None,
+        sequence: order.iter().map(|&i| unique[i].clone()).collect(),
+    })
+}
+
+// Loops `input_path` to exactly `target` seconds, by stream copy when that lands on the target and
+// by re-encoding otherwise, or seamlessly with crossfades when asked to. With a playlist the input
+// is the first of several clips played in turn instead.
+#[allow(clippy::too_many_arguments)]
+pub fn loop_to_duration(
+    input_path: &str,
+    output: &Path,
+    target: f64,
+    options: &LoopOptions,
+    capabilities: Option<&FfmpegCapabilities>,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<LoopResult, String> {
+    if !options.playlist.is_empty() {
+        let clips: Vec<String> = std::iter::once(input_path.to_string()).chain(options.playlist.iter().cloned()).collect();
+        return playlist_loop(&clips, target, options, output, capabilities, window, jobs, job_id);
+    }
+
+    let media = probe::probe(input_path)?;
+    let source = LoopSource {
+        path: input_path,
+        duration: media.duration().filter(|d| *d > 0.0).ok_or("Invalid file duration")?,
+        video: media.primary_video().map(|stream| stream.index),
+        audio: media.primary_audio().map(|stream| stream.index),
+    };
+
+    let loops = crate::calculate_loops(source.duration, target);
+    if loops <= 1 {
+        return Err("Target duration must be longer than original".to_string());
+    }
+
+    let crossfades = (source.video.is_some() && options.video_crossfade > 0.0)
+        || (source.audio.is_some() && options.audio_crossfade > 0.0);
+    let (method, loops, fallback_reason) = if options.seamless && crossfades {
+        let units = seamless_loop(&source, target, options, output, window, jobs, job_id)?;
+        (LoopMethod::Seamless, units, None)
+    } else if options.force_reencode || options.seamless || options.fade_out > 0.0 {
+        reencode_loop(&source, target, loops, options, output, window, jobs, job_id, Pass::WHOLE)?;
+        (LoopMethod::Reencode, loops, None)
+    } else {
+        let mismatch = match copy_loop(&source, target, loops, output, window, jobs, job_id) {
+            Ok(()) => copy_mismatch(output, target),
+            Err(e) if e == crate::jobs::cancelled_error(job_id) => return Err(e),
+            Err(e) => Some(format!("Stream copy failed: {}", e)),
+        };
+        match mismatch {
+            None => {
+                ffmpeg_progress::emit_finished(window, job_id);
+                (LoopMethod::Copy, loops, None)
+            }
+            Some(reason) => {
+                reencode_loop(&source, target, loops, options, output, window, jobs, job_id, Pass::nth(1, 2))?;
+                (LoopMethod::Reencode, loops, Some(reason))
+            }
+        }
+    };
+
+    let duration = probe::probe(&output.to_string_lossy())
+        .ok()
+        .and_then(|media| media.duration())
+        .unwrap_or(target);
+    Ok(LoopResult {
+        output: output.to_string_lossy().to_string(),
+        method,
+        loops,
+        duration,
+        fallback_reason,
+        sequence: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playlist_plays_clips_in_order_until_the_target_is_filled() {
+        // 10 + (20 - 2) + (10 - 2) + (20 - 2) = 54 seconds
+        assert_eq!(plan_playlist(&[10.0, 20.0], 50.0, 2.0, false), Ok(vec![0, 1, 0, 1]));
+        assert_eq!(plan_playlist(&[10.0, 20.0], 10.0, 2.0, false), Ok(vec![0]));
+    }
+
+    #[test]
+    fn shuffled_playlist_never_repeats_a_clip_back_to_back() {
+        for clips in [2, 3, 5] {
+            let durations = vec![5.0; clips];
+            let order = plan_playlist(&durations, 400.0, 0.5, true).unwrap();
+            assert!(order.windows(2).all(|pair| pair[0] != pair[1]), "{:?}", order);
+            // Every round plays every clip once
+            for round in order.chunks_exact(clips) {
+                let mut sorted = round.to_vec();
+                sorted.sort();
+                assert_eq!(sorted, (0..clips).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn overly_long_playlists_are_refused() {
+        assert!(plan_playlist(&[1.0, 1.0], 100_000.0, 0.0, false).is_err());
+    }
+
+    #[test]
+    fn playlist_pieces_reuse_clips_and_joins() {
+        assert_eq!(piece_sequence(&[0, 1, 0], false), [Piece::Clip(0, 0), Piece::Clip(1, 0), Piece::Clip(0, 0)]);
+        assert_eq!(
+            piece_sequence(&[0, 1, 0, 1], true),
+            [
+                Piece::Clip(0, 0),
+                Piece::Clip(0, 1),
+                Piece::Join(0, 1),
+                Piece::Clip(1, 1),
+                Piece::Join(1, 0),
+                Piece::Clip(0, 1),
+                Piece::Join(0, 1),
+                Piece::Clip(1, 1),
+                Piece::Clip(1, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn quality_options_follow_the_encoder() {
+        let options = LoopOptions {
+            crf: Some(20),
+            preset: Some("slow".to_string()),
+            ..Default::default()
+        };
+        let args = encoder_args(&options, Path::new("out.mp4"), true, false);
+        assert_eq!(args, ["-crf", "20", "-preset", "slow"]);
+
+        // libvpx-vp9 takes no -preset and needs -b:v 0 for constant quality
+        let args = encoder_args(&options, Path::new("out.webm"), true, false);
+        assert_eq!(args, ["-crf", "20", "-b:v", "0"]);
+
+        let nvenc = LoopOptions { video_codec: Some("h264_nvenc".to_string()), ..options };
+        assert_eq!(encoder_args(&nvenc, Path::new("out.mp4"), true, false), ["-c:v", "h264_nvenc", "-preset", "slow"]);
+    }
+
+    #[test]
+    fn unit_crossfades_the_end_into_the_start() {
+        // A 10 s input with a 1 s seam: the body runs from 1 s to the end and its last second
+        // fades into the first second, so the unit is 9 s long and the fade starts at 8 s
+        assert_eq!(
+            unit_filter('v', 0, 10.0, 1.0, 1.0),
+            "[0:0]split=2[vbody][vhead];\
+             [vbody]trim=start=1.000,setpts=PTS-STARTPTS[vb];\
+             [vhead]trim=start=0.000:end=1.000,setpts=PTS-STARTPTS[vh];\
+             [vb][vh]xfade=transition=fade:duration=1.000:offset=8.000[vu]"
+        );
+        // A shorter audio fade uses the end of the same window, so both streams meet at the seam
+        assert_eq!(
+            unit_filter('a', 1, 10.0, 1.0, 0.5),
+            "[0:1]asplit=2[abody][ahead];\
+             [abody]atrim=start=1.000,asetpts=PTS-STARTPTS[ab];\
+             [ahead]atrim=start=0.500:end=1.000,asetpts=PTS-STARTPTS[ah];\
+             [ab][ah]acrossfade=d=0.500[au]"
+        );
+        assert_eq!(unit_filter('a', 1, 10.0, 1.0, 0.0), "[0:1]atrim=start=1.000,asetpts=PTS-STARTPTS[au]");
+    }
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args().map(|arg| arg.to_string_lossy().to_string()).collect()
+    }
+
+    fn value_after(args: &[String], flag: &str) -> String {
+        let position = args.iter().position(|arg| arg == flag).unwrap();
+        args[position + 1].clone()
+    }
+
+    #[test]
+    fn seamless_plan_repeats_the_unit_after_an_intro_up_to_the_seam() {
+        let source = LoopSource { path: "/in.mp4", duration: 10.0, video: Some(0), audio: Some(1) };
+        let options = LoopOptions {
+            seamless: true,
+            video_crossfade: 1.0,
+            audio_crossfade: 0.5,
+            fade_out: 2.0,
+            ..Default::default()
+        };
+        let plan = seamless_plan(&source, 60.0, &options, Path::new("/tmp/unit.mkv"), Path::new("/out.mp4")).unwrap();
+        // 1 s of intro, then 9 s units until 60 s: 59 / 9 rounds up to 7
+        assert_eq!(plan.unit_length, 9.0);
+        assert_eq!(plan.units, 7);
+
+        let unit = args(&plan.unit);
+        assert!(value_after(&unit, "-filter_complex").contains("offset=8.000"));
+        assert!(value_after(&unit, "-filter_complex").contains("acrossfade=d=0.500"));
+        assert_eq!(unit.last().map(String::as_str), Some("/tmp/unit.mkv"));
+
+        let render = args(&plan.render);
+        assert_eq!(value_after(&render, "-t"), "1.000");
+        assert_eq!(value_after(&render, "-stream_loop"), "6");
+        let graph = value_after(&render, "-filter_complex");
+        assert!(graph.contains("[vi][ai][1:v:0][1:a:0]concat=n=2:v=1:a=1[vc][ac]"), "{}", graph);
+        assert!(graph.contains("[vc]fade=t=out:st=58.000:d=2.000[vo]"), "{}", graph);
+        assert_eq!(render.last().map(String::as_str), Some("/out.mp4"));
+    }
+
+    #[test]
+    fn seamless_plan_only_counts_the_crossfades_of_streams_it_has() {
+        let source = LoopSource { path: "/in.wav", duration: 4.0, video: None, audio: Some(0) };
+        let options = LoopOptions { seamless: true, video_crossfade: 3.0, audio_crossfade: 1.0, ..Default::default() };
+        let plan = seamless_plan(&source, 30.0, &options, Path::new("/u.mkv"), Path::new("/o.wav")).unwrap();
+        assert_eq!(plan.unit_length, 3.0);
+        // (30 - 1) / 3 rounds up to 10
+        assert_eq!(plan.units, 10);
+        assert!(!args(&plan.unit).contains(&"[vu]".to_string()));
+
+        let short = LoopSource { duration: 2.0, ..source };
+        assert!(seamless_plan(&short, 30.0, &options, Path::new("/u.mkv"), Path::new("/o.wav")).is_err());
+    }
+
+    #[test]
+    fn fade_out_ends_at_the_target() {
+        assert_eq!(fade_out_filters(&LoopOptions::default(), 60.0), (None, None));
+
+        let options = LoopOptions { fade_out: 5.0, ..Default::default() };
+        let (video, audio) = fade_out_filters(&options, 60.0);
+        assert_eq!(video.as_deref(), Some("fade=t=out:st=55.000:d=5.000"));
+        assert_eq!(audio.as_deref(), Some("afade=t=out:st=55.000:d=5.000"));
+
+        // Never longer than the output itself
+        let (video, _) = fade_out_filters(&options, 3.0);
+        assert_eq!(video.as_deref(), Some("fade=t=out:st=0.000:d=3.000"));
+    }
+
+    #[test]
+    fn stream_copy_is_redone_when_a_stream_misses_the_target() {
+        let media = |audio: &str| {
+            let json = format!(
+                r#"{{ "streams": [
+                    {{ "index": 0, "codec_type": "video", "codec_name": "h264", "duration": "60.040000" }},
+                    {{ "index": 1, "codec_type": "audio", "codec_name": "aac", "duration": "{}" }}
+                ], "format": {{ "duration": "60.040000" }} }}"#,
+                audio
+            );
+            probe::from_json("/out.mp4", json.as_bytes()).unwrap()
+        };
+        assert_eq!(duration_mismatch(&media("59.950000"), 60.0), None);
+        assert_eq!(
+            duration_mismatch(&media("59.500000"), 60.0).as_deref(),
+            Some("The audio came out 59.50s long instead of 60.00s")
+        );
+    }
+
+    #[test]
+    fn playlist_pieces_use_lossless_h264_when_available() {
+        let libx264 = FfmpegCapabilities {
+            version: None,
+            encoders: vec![capabilities::Codec {
+                name: "libx264".to_string(),
+                kind: "video".to_string(),
+                codec: "h264".to_string(),
+                description: String::new(),
+            }],
+            decoders: Vec::new(),
+            muxers: Vec::new(),
+            filters: Vec::new(),
+            pixel_formats: Vec::new(),
+        };
+        let args = |capabilities| piece_args("0", &PieceFormat::new(true, true, capabilities), Path::new("/piece.mkv"));
+
+        assert_eq!(
+            args(Some(&libx264)),
+            ["-map", "[v0]", "-c:v", "libx264", "-qp", "0", "-preset", "ultrafast", "-map", "[a0]", "-c:a", "flac", "/piece.mkv"]
+        );
+        // Unknown capabilities fall back to FFV1, which every build has
+        assert_eq!(args(None)[..4], ["-map", "[v0]", "-c:v", "ffv1"]);
+    }
+
+    #[test]
+    fn playlist_output_can_hold_video_from_any_clip() {
+        let media = |path: &str, kind: &str| {
+            let json = format!(r#"{{ "streams": [ {{ "index": 0, "codec_type": "{}" }} ], "format": {{}} }}"#, kind);
+            probe::from_json(path, json.as_bytes()).unwrap()
+        };
+        let clips = ["/a.mp3".to_string(), "/b.mov".to_string(), "/c.m4a".to_string()];
+        let clips: Vec<&String> = clips.iter().collect();
+        let song = media("/a.mp3", "audio");
+        let video = media("/b.mov", "video");
+
+        // An audio container takes the container of the first clip with video
+        let output = playlist_output(Path::new("/out/Loop_a.mp3"), &clips[..2], &[song.clone(), video.clone()]);
+        assert_eq!(output, Path::new("/out/Loop_a.mov"));
+        // All audio: kept as is
+        let output = playlist_output(Path::new("/out/Loop_a.mp3"), &[clips[0], clips[2]], &[song.clone(), song.clone()]);
+        assert_eq!(output, Path::new("/out/Loop_a.mp3"));
+        // A video container is kept
+        let output = playlist_output(Path::new("/out/Loop_b.mov"), &[clips[1], clips[0]], &[video.clone(), song.clone()]);
+        assert_eq!(output, Path::new("/out/Loop_b.mov"));
+        // Video in an audio extension, e.g. cover art in an .m4a, falls back to Matroska
+        let output = playlist_output(Path::new("/out/Loop_a.mp3"), &[clips[0], clips[2]], &[song, media("/c.m4a", "video")]);
+        assert_eq!(output, Path::new("/out/Loop_a.mkv"));
+    }
+}
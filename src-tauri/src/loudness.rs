@@ -0,0 +1,427 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::{State, Window};
+
+use crate::ffmpeg_progress::{self, Pass};
+use crate::jobs::JobRegistry;
+use crate::probe::{self, MediaProbe};
+
+// ReplayGain 2.0 reference loudness
+const REPLAYGAIN_REFERENCE: f64 = -18.0;
+// Opus R128 gain tags are relative to EBU R128's -23 LUFS
+const OPUS_REFERENCE: f64 = -23.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoudnessPreset {
+    // Spotify, YouTube and most streaming services
+    Streaming,
+    // EBU R128
+    Broadcast,
+    // Apple Podcasts and most podcast hosts
+    Podcast,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessTarget {
+    // Integrated loudness, LUFS
+    pub integrated: f64,
+    // Maximum true peak, dBTP
+    pub true_peak: f64,
+    // Loudness range, LU
+    pub lra: f64,
+}
+
+impl LoudnessPreset {
+    fn target(&self) -> LoudnessTarget {
+        let (integrated, true_peak, lra) = match self {
+            LoudnessPreset::Streaming => (-14.0, -1.0, 11.0),
+            LoudnessPreset::Broadcast => (-23.0, -1.0, 15.0),
+            LoudnessPreset::Podcast => (-16.0, -1.5, 11.0),
+        };
+        LoudnessTarget { integrated, true_peak, lra }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoudnessMode {
+    // Measure, then correct the audio with loudnorm and re-encode it
+    #[default]
+    Normalize,
+    // Measure and only write ReplayGain tags; the streams are copied untouched
+    ReplayGain,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LoudnessOptions {
+    pub mode: LoudnessMode,
+    // Defaults to streaming; the explicit values below override single parts of it
+    pub preset: Option<LoudnessPreset>,
+    pub integrated: Option<f64>,
+    pub true_peak: Option<f64>,
+    pub lra: Option<f64>,
+    pub output_directory: Option<String>,
+    // Replace the input instead of writing a new file next to it; output_directory is ignored then
+    pub in_place: bool,
+    // Audio encoder settings for the corrected audio; FFmpeg's default for the container otherwise
+    pub audio_codec: Option<String>,
+    pub audio_bitrate: Option<String>,
+}
+
+impl LoudnessOptions {
+    fn target(&self) -> LoudnessTarget {
+        let preset = self.preset.unwrap_or(LoudnessPreset::Streaming).target();
+        LoudnessTarget {
+            // loudnorm only accepts these ranges
+            integrated: self.integrated.unwrap_or(preset.integrated).clamp(-70.0, -5.0),
+            true_peak: self.true_peak.unwrap_or(preset.true_peak).clamp(-9.0, 0.0),
+            lra: self.lra.unwrap_or(preset.lra).clamp(1.0, 50.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessMeasurement {
+    pub integrated: f64,
+    pub true_peak: f64,
+    pub lra: f64,
+    pub threshold: f64,
+    // Offset gain loudnorm suggests for the second pass
+    pub target_offset: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessResult {
+    pub output: String,
+    pub mode: LoudnessMode,
+    pub input: LoudnessMeasurement,
+    pub target: Option<LoudnessTarget>,
+    // What loudnorm measured on the corrected audio
+    pub result: Option<LoudnessMeasurement>,
+    // "linear" when a plain gain change was enough, "dynamic" when loudnorm had to compress
+    pub normalization_type: Option<String>,
+    pub replaygain_gain_db: Option<f64>,
+    pub replaygain_peak: Option<f64>,
+}
+
+// The JSON block loudnorm prints at the end of stderr with print_format=json
+#[derive(Debug, Deserialize)]
+struct LoudnormReport {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    output_i: Option<String>,
+    output_tp: Option<String>,
+    output_lra: Option<String>,
+    output_thresh: Option<String>,
+    normalization_type: Option<String>,
+    target_offset: String,
+}
+
+fn parse_report(stderr: &str) -> Result<LoudnormReport, String> {
+    let start = stderr.rfind('{').ok_or("loudnorm did not report a measurement")?;
+    let end = stderr[start..].find('}').map(|end| start + end + 1).ok_or("loudnorm report is incomplete")?;
+    serde_json::from_str(&stderr[start..end]).map_err(|e| format!("Failed to parse loudnorm report: {}", e))
+}
+
+// loudnorm reports "-inf" for silence, which can't be corrected
+fn number(value: &str, name: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or(format!("The input has no measurable {} (is it silent?)", name))
+}
+
+impl LoudnormReport {
+    fn input(&self) -> Result<LoudnessMeasurement, String> {
+        Ok(LoudnessMeasurement {
+            integrated: number(&self.input_i, "loudness")?,
+            true_peak: number(&self.input_tp, "peak")?,
+            lra: number(&self.input_lra, "loudness range")?,
+            threshold: number(&self.input_thresh, "threshold")?,
+            target_offset: number(&self.target_offset, "offset")?,
+        })
+    }
+
+    fn output(&self) -> Option<LoudnessMeasurement> {
+        let value = |field: &Option<String>| field.as_deref().and_then(|v| v.trim().parse::<f64>().ok());
+        Some(LoudnessMeasurement {
+            integrated: value(&self.output_i)?,
+            true_peak: value(&self.output_tp)?,
+            lra: value(&self.output_lra)?,
+            threshold: value(&self.output_thresh)?,
+            target_offset: 0.0,
+        })
+    }
+}
+
+fn loudnorm_filter(target: &LoudnessTarget) -> String {
+    format!("loudnorm=I={}:TP={}:LRA={}", target.integrated, target.true_peak, target.lra)
+}
+
+// Second pass: feed the measurement back so loudnorm can apply one linear gain where possible
+fn correction_filter(target: &LoudnessTarget, measured: &LoudnessMeasurement) -> String {
+    format!(
+        "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true:print_format=json",
+        loudnorm_filter(target),
+        measured.integrated,
+        measured.true_peak,
+        measured.lra,
+        measured.threshold,
+        measured.target_offset
+    )
+}
+
+// First pass: analyse the first audio stream without writing anything
+fn measure(
+    input_path: &str,
+    media: &MediaProbe,
+    target: &LoudnessTarget,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    pass: Pass,
+) -> Result<LoudnessMeasurement, String> {
+    let audio = media.primary_audio().ok_or("The input has no audio stream")?;
+    let filter = format!("{}:print_format=json", loudnorm_filter(target));
+
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-i", input_path, "-map", &format!("0:{}", audio.index), "-af", &filter, "-f", "null", "-"]);
+    let output = ffmpeg_progress::run_job_output(window, jobs, job_id, &mut cmd, Vec::new(), media.duration(), pass)?;
+    parse_report(&output.stderr)?.input()
+}
+
+fn output_path(input: &Path, options: &LoudnessOptions, suffix: &str) -> Result<PathBuf, String> {
+    let stem = input.file_stem().ok_or("Invalid input file")?.to_string_lossy();
+    let ext = input.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or("mka".to_string());
+    if options.in_place {
+        // Written next to the input first and moved over it once FFmpeg succeeded. Staying in the
+        // input's directory keeps that a rename on the same filesystem.
+        let dir = crate::output_dir_for(input, None)?;
+        Ok(dir.join(format!(".{}_{}.{}", stem, suffix, ext)))
+    } else {
+        let dir = crate::output_dir_for(input, options.output_directory.as_deref())?;
+        Ok(dir.join(format!("{}_{}.{}", stem, suffix, ext)))
+    }
+}
+
+fn finish_output(input: &Path, written: PathBuf, in_place: bool) -> Result<String, String> {
+    if !in_place {
+        return Ok(written.to_string_lossy().to_string());
+    }
+    fs::rename(&written, input).map_err(|e| {
+        let _ = fs::remove_file(&written);
+        format!("Failed to replace {}: {}", input.display(), e)
+    })?;
+    Ok(input.to_string_lossy().to_string())
+}
+
+// Metadata options for the ReplayGain tags, in the flavour the container expects
+fn replaygain_tags(output: &Path, measured: &LoudnessMeasurement) -> (Vec<String>, f64, f64) {
+    let gain = REPLAYGAIN_REFERENCE - measured.integrated;
+    let peak = 10f64.powf(measured.true_peak / 20.0);
+    let ext = output.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+
+    let mut args = vec![
+        "-metadata".to_string(),
+        format!("REPLAYGAIN_TRACK_GAIN={:.2} dB", gain),
+        "-metadata".to_string(),
+        format!("REPLAYGAIN_TRACK_PEAK={:.6}", peak),
+    ];
+    match ext.as_str() {
+        // Players read Opus gain from R128_TRACK_GAIN, a Q7.8 number relative to -23 LUFS
+        "opus" => args.extend([
+            "-metadata".to_string(),
+            format!("R128_TRACK_GAIN={}", ((OPUS_REFERENCE - measured.integrated) * 256.0).round() as i32),
+        ]),
+        // The MP4 muxer drops tags it doesn't know unless told otherwise
+        "mp4" | "m4a" | "m4b" | "mov" => args.extend(["-movflags".to_string(), "use_metadata_tags".to_string()]),
+        _ => {}
+    }
+    (args, gain, peak)
+}
+
+// Measures the loudness of the first audio stream (EBU R128) without changing anything
+#[tauri::command]
+pub async fn measure_loudness(
+    input_path: String,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+) -> Result<LoudnessMeasurement, String> {
+    let job_id = job_id.unwrap_or_else(crate::new_job_id);
+    let media = probe::probe(&input_path)?;
+    measure(&input_path, &media, &LoudnessPreset::Streaming.target(), &window, &jobs, &job_id, Pass::WHOLE)
+}
+
+// Two-pass loudnorm normalisation, or ReplayGain tagging without re-encoding
+#[tauri::command]
+pub async fn normalize_loudness(
+    input_path: String,
+    options: Option<LoudnessOptions>,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+) -> Result<LoudnessResult, String> {
+    let job_id = job_id.unwrap_or_else(crate::new_job_id);
+    let _job = jobs.reserve(&job_id)?;
+    let options = options.unwrap_or_default();
+    let target = options.target();
+    let input = Path::new(&input_path);
+    let media = probe::probe(&input_path)?;
+    let audio = media.primary_audio().ok_or("The input has no audio stream")?;
+
+    let measured = measure(&input_path, &media, &target, &window, &jobs, &job_id, Pass::nth(0, 2))?;
+
+    if options.mode == LoudnessMode::ReplayGain {
+        let written = output_path(input, &options, "replaygain")?;
+        let (tag_args, gain, peak) = replaygain_tags(&written, &measured);
+
+        let mut cmd = ffmpeg_progress::command();
+        cmd.args(["-y", "-i", &input_path, "-map", "0", "-map_metadata", "0", "-c", "copy"]);
+        cmd.args(tag_args);
+        cmd.arg(&written);
+        let outputs = vec![written.clone()];
+        ffmpeg_progress::run_pass(&window, &jobs, &job_id, &mut cmd, outputs, media.duration(), Pass::nth(1, 2))?;
+
+        return Ok(LoudnessResult {
+            output: finish_output(input, written, options.in_place)?,
+            mode: options.mode,
+            input: measured,
+            target: None,
+            result: None,
+            normalization_type: None,
+            replaygain_gain_db: Some(gain),
+            replaygain_peak: Some(peak),
+        });
+    }
+
+    let filter = correction_filter(&target, &measured);
+    // loudnorm works at 192 kHz internally; go back to the input's rate
+    let sample_rate = audio.audio.as_ref().and_then(|a| a.sample_rate).unwrap_or(48000);
+
+    let written = output_path(input, &options, "normalized")?;
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-y", "-i", &input_path, "-map", "0:v?", "-map", &format!("0:{}", audio.index)]);
+    cmd.args(["-c:v", "copy", "-af", &filter, "-ar", &sample_rate.to_string()]);
+    if let Some(codec) = &options.audio_codec {
+        cmd.args(["-c:a", codec]);
+    }
+    if let Some(bitrate) = options.audio_bitrate.as_ref().filter(|b| !b.is_empty()) {
+        cmd.args(["-b:a", bitrate]);
+    }
+    cmd.arg(&written);
+    let outputs = vec![written.clone()];
+    let pass = Pass::nth(1, 2);
+    let output = ffmpeg_progress::run_job_output(&window, &jobs, &job_id, &mut cmd, outputs, media.duration(), pass)?;
+    let report = parse_report(&output.stderr).ok();
+
+    Ok(LoudnessResult {
+        output: finish_output(input, written, options.in_place)?,
+        mode: options.mode,
+        input: measured,
+        target: Some(target),
+        result: report.as_ref().and_then(|report| report.output()),
+        normalization_type: report.and_then(|report| report.normalization_type),
+        replaygain_gain_db: None,
+        replaygain_peak: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND_PASS_STDERR: &str = r#"[Parsed_loudnorm_0 @ 0x55d0c8c3e5c0]
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-4.47",
+	"input_lra" : "18.06",
+	"input_thresh" : "-39.20",
+	"output_i" : "-14.02",
+	"output_tp" : "-1.00",
+	"output_lra" : "11.20",
+	"output_thresh" : "-24.64",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.02"
+}
+[out#0/null @ 0x55d0c8c3f940] video:0kB audio:1kB"#;
+
+    #[test]
+    fn report_is_read_from_the_end_of_stderr() {
+        let report = parse_report(SECOND_PASS_STDERR).unwrap();
+        let input = report.input().unwrap();
+        assert_eq!((input.integrated, input.true_peak, input.lra, input.threshold), (-27.61, -4.47, 18.06, -39.2));
+        assert_eq!(input.target_offset, 0.02);
+
+        let output = report.output().unwrap();
+        assert_eq!((output.integrated, output.true_peak), (-14.02, -1.0));
+        assert_eq!(report.normalization_type.as_deref(), Some("dynamic"));
+    }
+
+    #[test]
+    fn first_pass_report_has_no_output_and_silence_is_refused() {
+        let first_pass = r#"{ "input_i" : "-20.00", "input_tp" : "-3.00", "input_lra" : "5.00",
+            "input_thresh" : "-30.00", "output_i" : "-14.00", "output_tp" : "-1.00", "output_lra" : "4.00",
+            "output_thresh" : "-24.00", "normalization_type" : "dynamic", "target_offset" : "0.00" }"#;
+        assert!(parse_report(first_pass).unwrap().input().is_ok());
+
+        let silent = r#"{ "input_i" : "-inf", "input_tp" : "-inf", "input_lra" : "0.00",
+            "input_thresh" : "-70.00", "target_offset" : "inf" }"#;
+        let report = parse_report(silent).unwrap();
+        assert!(report.input().is_err());
+        assert!(report.output().is_none());
+
+        assert!(parse_report("no measurement here").is_err());
+        assert!(parse_report("{ \"input_i\" : ").is_err());
+    }
+
+    #[test]
+    fn second_pass_filter_carries_the_measurement() {
+        let target = LoudnessPreset::Podcast.target();
+        let measured = parse_report(SECOND_PASS_STDERR).unwrap().input().unwrap();
+        assert_eq!(
+            correction_filter(&target, &measured),
+            "loudnorm=I=-16:TP=-1.5:LRA=11:measured_I=-27.61:measured_TP=-4.47:measured_LRA=18.06:\
+             measured_thresh=-39.2:offset=0.02:linear=true:print_format=json"
+        );
+    }
+
+    #[test]
+    fn explicit_values_override_the_preset_within_loudnorm_limits() {
+        let options = LoudnessOptions {
+            preset: Some(LoudnessPreset::Broadcast),
+            true_peak: Some(3.0),
+            lra: Some(7.0),
+            ..Default::default()
+        };
+        let target = options.target();
+        assert_eq!((target.integrated, target.true_peak, target.lra), (-23.0, 0.0, 7.0));
+    }
+
+    #[test]
+    fn in_place_output_stays_next_to_the_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let elsewhere = dir.path().join("elsewhere");
+        let input = dir.path().join("song.flac");
+        let options = LoudnessOptions {
+            output_directory: Some(elsewhere.to_string_lossy().to_string()),
+            in_place: true,
+            ..Default::default()
+        };
+        assert_eq!(output_path(&input, &options, "normalized").unwrap(), dir.path().join(".song_normalized.flac"));
+
+        let options = LoudnessOptions { in_place: false, ..options };
+        assert_eq!(output_path(&input, &options, "normalized").unwrap(), elsewhere.join("song_normalized.flac"));
+    }
+}
@@ -4,15 +4,47 @@
     windows_subsystem = "windows"
 )]
 
-use std::fs::{self, File};
+use std::fs::File;
 use std::io::copy;
-use std::path::Path;
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
 use reqwest;
 use serde::{Deserialize, Serialize};
-use tauri::Window;
+use tauri::{AppHandle, Manager, State, Window};
+
+mod batch;
+mod capabilities;
+mod concat;
+mod download_queue;
+mod duplicates;
+mod ffmpeg_progress;
+mod jobs;
+mod library;
+mod looping;
+mod loudness;
+mod noise;
+mod media_type;
+mod persist;
+mod presets;
+mod probe;
+mod scanner;
+mod target_size;
+mod thumbnails;
+mod trim;
+mod watcher;
+mod ytdlp_args;
+mod ytdlp_progress;
+
+use capabilities::CapabilityCache;
+use download_queue::DownloadQueue;
+use jobs::JobRegistry;
+use library::MediaLibrary;
+use presets::PresetStore;
+use scanner::{ScanOptions, ScanRegistry};
+use thumbnails::ThumbnailCache;
+use watcher::LibraryWatcher;
 
 // Helper function to create Command with hidden console window on Windows
 fn create_hidden_command(program: &str) -> Command {
@@ -27,7 +59,29 @@ fn create_hidden_command(program: &str) -> Command {
     cmd
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Length of the input in seconds, used to turn FFmpeg's out_time into a percentage
+fn input_duration(path: &str) -> Option<f64> {
+    probe::probe(path).ok().and_then(|media| media.duration())
+}
+
+// Where an FFmpeg tool writes its output: the requested directory (created if needed) or next to the input
+fn output_dir_for(input_path: &Path, output_directory: Option<&str>) -> Result<PathBuf, String> {
+    let dir = match output_directory.filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => input_path
+            .parent()
+            .ok_or("Cannot determine output directory")?
+            .to_path_buf(),
+    };
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create output directory: {}", e))?;
+    Ok(dir)
+}
+
+fn new_job_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct MediaFile {
     name: String,
     path: String,
@@ -44,10 +98,10 @@ struct VideoInfo {
     duration: f64,
     fps: f64,
     codec: String,
-    bitrate: u32,
+    bitrate: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ConversionSettings {
     #[serde(rename = "videoCodec")]
     video_codec: String,
@@ -58,9 +112,15 @@ struct ConversionSettings {
     bitrate: Option<String>,
     #[serde(rename = "fastMode")]
     fast_mode: Option<bool>,
+    // Two-pass encode aiming for this file size instead of a CRF or fixed bitrate
+    #[serde(rename = "targetSizeMb")]
+    target_size_mb: Option<f64>,
+    // Scale down to at most this many lines, keeping the aspect ratio; smaller inputs are left alone
+    #[serde(rename = "maxHeight")]
+    max_height: Option<u32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct AudioConversionSettings {
     codec: String,
     bitrate: Option<String>,
@@ -83,13 +143,19 @@ struct NoiseReductionSettings {
     highpass_freq: Option<f32>,
     lowpass_freq: Option<f32>,
     notch_freq: Option<f32>,
+    // Learn the noise from this noise-only segment (see capture_noise_profile)
+    noise_profile: Option<noise::NoiseProfile>,
+    // RNNoise model for the arnndn and speech algorithms: a bundled model id or a .rnnn path;
+    // the default model when left out
+    model: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct DownloadOptions {
     url: String,
     output_dir: String,
     quality: String,
+    // Merge container for video downloads ("mp4", "webm", "mkv")
     format: String,
     audio_only: bool,
     audio_format: String,
@@ -98,6 +164,13 @@ struct DownloadOptions {
     embed_metadata: bool,
     retries: u32,
     cookie_file: Option<String>,
+    // Write subtitle files next to the download
+    #[serde(default)]
+    subtitles: bool,
+    #[serde(default)]
+    playlist: bool,
+    #[serde(default)]
+    custom_args: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -164,8 +237,19 @@ async fn select_directory() -> Result<String, String> {
     }
 }
 
+// Lists the media files below `directory` and syncs them into the library index. The walk itself
+// always covers the whole tree; what is incremental is the probing, as files whose size and mtime
+// are unchanged keep their metadata. Directory mtimes can't be used to skip subtrees because they
+// don't change when a file inside is rewritten in place.
 #[tauri::command]
-async fn scan_media_files(directory: String, extensions: Vec<String>) -> Result<Vec<MediaFile>, String> {
+async fn scan_media_files(
+    directory: String,
+    extensions: Vec<String>,
+    options: Option<ScanOptions>,
+    app: tauri::AppHandle,
+    library: State<'_, MediaLibrary>,
+    watcher: State<'_, LibraryWatcher>,
+) -> Result<Vec<MediaFile>, String> {
     let path = Path::new(&directory);
     
     if !path.exists() || !path.is_dir() {
@@ -174,74 +258,31 @@ async fn scan_media_files(directory: String, extensions: Vec<String>) -> Result<
 
     let mut files = Vec::new();
     let extensions_lower: Vec<String> = extensions.iter().map(|ext| ext.to_lowercase()).collect();
+    let options = options.unwrap_or_default();
 
-    match scan_directory_recursive(path, &extensions_lower) {
-        Ok(mut found_files) => {
-            files.append(&mut found_files);
-            // Sort by name
-            files.sort_by(|a, b| a.name.cmp(&b.name));
-            Ok(files)
-        }
-        Err(e) => Err(format!("Failed to scan directory: {}", e)),
+    let report = scanner::walk(path, &extensions_lower, &options, &AtomicBool::new(false), |mut batch| {
+        files.append(&mut batch)
+    })?;
+    if let Some(error) = report.errors.iter().find(|error| Path::new(&error.path) == path) {
+        return Err(format!("Failed to scan directory: {}", error.message));
     }
-}
-
-fn scan_directory_recursive(dir: &Path, extensions: &[String]) -> Result<Vec<MediaFile>, Box<dyn std::error::Error>> {
-    let mut files = Vec::new();
-    
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.is_dir() {
-                // Recursively scan subdirectories
-                match scan_directory_recursive(&path, extensions) {
-                    Ok(mut sub_files) => files.append(&mut sub_files),
-                    Err(_) => continue, // Skip inaccessible directories
-                }
-            } else if path.is_file() {
-                if let Some(extension) = path.extension() {
-                    if let Some(ext_str) = extension.to_str() {
-                        let ext_lower = ext_str.to_lowercase();
-                        if extensions.contains(&ext_lower) {
-                            if let Ok(metadata) = fs::metadata(&path) {
-                                let file_type = if is_video_extension(&ext_lower) {
-                                    "video"
-                                } else {
-                                    "audio"
-                                };
 
-                                let modified = metadata
-                                    .modified()
-                                    .unwrap_or(SystemTime::UNIX_EPOCH)
-                                    .duration_since(SystemTime::UNIX_EPOCH)
-                                    .unwrap_or_default()
-                                    .as_secs();
-
-                                files.push(MediaFile {
-                                    name: path.file_name()
-                                        .unwrap_or_default()
-                                        .to_string_lossy()
-                                        .to_string(),
-                                    path: path.to_string_lossy().to_string(),
-                                    size: metadata.len(),
-                                    modified,
-                                    file_type: file_type.to_string(),
-                                });
-                            }
-                        }
-                    }
-                }
-            }
+    // Keep the persistent library index in sync; new or changed files get probed in the background.
+    // A filtered scan didn't see everything, so it would wrongly drop the files it skipped.
+    if options.sees_everything() {
+        if let Err(e) = library.sync_directory(&directory, &extensions_lower, &files, &report.unread_paths()) {
+            eprintln!("{}", e);
         }
+        library.spawn_probe(&app);
+    }
+    // Pick up downloads and conversions that land here later without a manual rescan
+    if let Err(e) = watcher.watch(&app, &directory) {
+        eprintln!("{}", e);
     }
-    
-    Ok(files)
-}
 
-fn is_video_extension(ext: &str) -> bool {
-    matches!(ext, "mp4" | "avi" | "mkv" | "mov" | "wmv" | "flv" | "webm" | "m4v" | "3gp" | "ogv")
+    // Sort by name
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
 }
 
 #[tauri::command]
@@ -275,39 +316,9 @@ async fn toggle_fullscreen(window: Window) -> Result<(), String> {
 
 #[tauri::command]
 async fn get_media_duration(file_path: String) -> Result<f64, String> {
-    let output = create_hidden_command("ffprobe")
-        .args([
-            "-v", "quiet",
-            "-print_format", "json",
-            "-show_format",
-            "-show_streams",
-            &file_path
-        ])
-        .output()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                "FFprobe not found. Please install FFmpeg and add it to your PATH.".to_string()
-            } else {
-                format!("Failed to execute ffprobe: {}", e)
-            }
-        })?;
-
-    if !output.status.success() {
-        return Err("Failed to get file information".to_string());
-    }
-
-    let json_str = String::from_utf8(output.stdout)
-        .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
-    
-    let parsed: serde_json::Value = serde_json::from_str(&json_str)
-        .map_err(|e| format!("Failed to parse JSON: {}", e))?;
-    
-    let duration = parsed["format"]["duration"]
-        .as_str()
-        .and_then(|s| s.parse::<f64>().ok())
-        .ok_or("Could not parse duration")?;
-    
-    Ok(duration)
+    probe::probe(&file_path)?
+        .duration()
+        .ok_or("Could not parse duration".to_string())
 }
 
 #[tauri::command]
@@ -365,17 +376,19 @@ fn calculate_loops(file_duration: f64, target_duration: f64) -> u32 {
 
 // Process the file with FFmpeg
 #[tauri::command]
-async fn loop_media(input_path: String, output_directory: String, target_duration: f64) -> Result<String, String> {
-    let file_duration = get_media_duration(input_path.clone()).await?;
-    let loops_needed = calculate_loops(file_duration, target_duration);
-    
-    if loops_needed == 0 {
-        return Err("Invalid file duration".to_string());
-    }
-    
-    if loops_needed == 1 {
-        return Err("Target duration must be longer than original".to_string());
-    }
+#[allow(clippy::too_many_arguments)]
+async fn loop_media(
+    input_path: String,
+    output_directory: String,
+    target_duration: f64,
+    options: Option<looping::LoopOptions>,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<looping::LoopResult, String> {
+    let job_id = job_id.unwrap_or_else(new_job_id);
+    let _job = jobs.reserve(&job_id)?;
     
     // Generate output path with Loop_ prefix
     let input_path_buf = Path::new(&input_path);
@@ -398,32 +411,17 @@ async fn loop_media(input_path: String, output_directory: String, target_duratio
     std::fs::create_dir_all(output_dir)
         .map_err(|e| format!("Failed to create output directory: {}", e))?;
     
-    // FFmpeg command to loop the video/audio
-    let mut cmd = create_hidden_command("ffmpeg");
-    cmd.args([
-        "-y", // Overwrite output file
-        "-stream_loop", &(loops_needed - 1).to_string(), // Loop count (n-1 because original plays once)
-        "-i", &input_path,
-        "-t", &target_duration.to_string(), // Limit output duration
-        "-c", "copy", // Copy streams without re-encoding when possible
-        &final_output_path.to_string_lossy()
-    ]);
-    
-    let output = cmd.output()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                "FFmpeg not found. Please install FFmpeg and add it to your PATH.".to_string()
-            } else {
-                format!("Failed to execute ffmpeg: {}", e)
-            }
-        })?;
-    
-    if output.status.success() {
-        Ok(final_output_path.to_string_lossy().to_string())
-    } else {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
-        Err(format!("FFmpeg error: {}", error_msg))
-    }
+    // Stream copy when possible, re-encoding or crossfading the seams otherwise
+    looping::loop_to_duration(
+        &input_path,
+        &final_output_path,
+        target_duration,
+        &options.unwrap_or_default(),
+        capabilities.current().as_ref(),
+        &window,
+        &jobs,
+        &job_id,
+    )
 }
 
 async fn download_file_internal(url: String, path: String) -> Result<(), Box<dyn std::error::Error>> {
@@ -449,68 +447,34 @@ async fn download_file_internal(url: String, path: String) -> Result<(), Box<dyn
 
 #[tauri::command]
 async fn get_video_info(file_path: String) -> Result<VideoInfo, String> {
-    let output = create_hidden_command("ffprobe")
-        .args([
-            "-v", "quiet",
-            "-print_format", "json",
-            "-show_format",
-            "-show_streams",
-            &file_path
-        ])
-        .output()
-        .map_err(|e| format!("Failed to execute ffprobe: {}", e))?;
-
-    if !output.status.success() {
-        return Err("ffprobe failed to analyze the video".to_string());
-    }
-
-    let json_str = String::from_utf8(output.stdout)
-        .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
-
-    let parsed: serde_json::Value = serde_json::from_str(&json_str)
-        .map_err(|e| format!("Failed to parse JSON: {}", e))?;
-
-    // Find video stream
-    let streams = parsed["streams"].as_array()
-        .ok_or("No streams found")?;
-    
-    let video_stream = streams.iter()
-        .find(|stream| stream["codec_type"] == "video")
-        .ok_or("No video stream found")?;
-
-    let width = video_stream["width"].as_u64().unwrap_or(0) as u32;
-    let height = video_stream["height"].as_u64().unwrap_or(0) as u32;
-    let fps_str = video_stream["r_frame_rate"].as_str().unwrap_or("0/1");
-    let codec = video_stream["codec_name"].as_str().unwrap_or("unknown").to_string();
-    
-    // Parse FPS fraction
-    let fps = if let Some((num, den)) = fps_str.split_once('/') {
-        let num: f64 = num.parse().unwrap_or(0.0);
-        let den: f64 = den.parse().unwrap_or(1.0);
-        if den != 0.0 { num / den } else { 0.0 }
-    } else {
-        fps_str.parse().unwrap_or(0.0)
-    };
-
-    let duration = parsed["format"]["duration"].as_str()
-        .and_then(|s| s.parse::<f64>().ok())
-        .unwrap_or(0.0);
-
-    let bitrate = parsed["format"]["bit_rate"].as_str()
-        .and_then(|s| s.parse::<u32>().ok())
+    let media = probe::probe(&file_path)?;
+
+    // Cover art shows up as a video stream too, so use the stream a player would pick
+    let video_stream = media.primary_video().ok_or("No video stream found")?;
+    let video = video_stream.video.as_ref().ok_or("No video stream found")?;
+
+    // Some containers (e.g. mkv) only report the bitrate per stream
+    let bitrate = media
+        .format
+        .bit_rate
+        .or_else(|| {
+            let total: u64 = media.streams.iter().filter_map(|stream| stream.bit_rate).sum();
+            (total > 0).then_some(total)
+        })
         .unwrap_or(0);
 
     Ok(VideoInfo {
-        width,
-        height,
-        duration,
-        fps,
-        codec,
+        width: video.width,
+        height: video.height,
+        duration: media.duration().unwrap_or(0.0),
+        fps: video.frame_rate.unwrap_or(0.0),
+        codec: video_stream.codec_name.clone().unwrap_or("unknown".to_string()),
         bitrate,
     })
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn resize_video(
     input_path: String,
     output_width: u32,
@@ -518,7 +482,13 @@ async fn resize_video(
     maintain_aspect_ratio: bool,
     quality: u32,
     output_format: String,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
 ) -> Result<String, String> {
+    let job_id = job_id.unwrap_or_else(new_job_id);
+    let duration = input_duration(&input_path);
+
     // Generate output path
     let input_pathbuf = Path::new(&input_path);
     let file_stem = input_pathbuf.file_stem()
@@ -547,37 +517,25 @@ async fn resize_video(
     // Calculate CRF value from quality (higher quality = lower CRF)
     let crf = 51 - (quality * 51 / 100);
 
-    let output = create_hidden_command("ffmpeg")
-        .args([
-            "-i", &input_path,
-            "-vf", &scale_filter,
-            "-c:v", "libx264",
-            "-crf", &crf.to_string(),
-            "-c:a", "aac",
-            "-b:a", "128k",
-            "-y", // Overwrite output file
-            &output_path_str
-        ])
-        .output()
-        .map_err(|e| format!("Failed to execute ffmpeg: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("ffmpeg failed: {}", stderr));
-    }
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args([
+        "-i", &input_path,
+        "-vf", &scale_filter,
+        "-c:v", "libx264",
+        "-crf", &crf.to_string(),
+        "-c:a", "aac",
+        "-b:a", "128k",
+        "-y", // Overwrite output file
+        &output_path_str
+    ]);
+    ffmpeg_progress::run_job(&window, &jobs, &job_id, &mut cmd, vec![PathBuf::from(&output_path_str)], duration)?;
 
     Ok(output_path_str)
 }
 
-#[tauri::command]
-async fn convert_video(
-    input_path: String,
-    output_format: String,
-    output_directory: Option<String>,
-    settings: ConversionSettings,
-) -> Result<String, String> {
-    // Generate output path
-    let input_pathbuf = Path::new(&input_path);
+// Output for a single-file conversion: "<stem>_converted.<format>" in the output directory
+fn converted_output_path(input_path: &str, output_format: &str, output_directory: Option<String>) -> Result<PathBuf, String> {
+    let input_pathbuf = Path::new(input_path);
     let file_stem = input_pathbuf.file_stem()
         .ok_or("Invalid input file")?
         .to_string_lossy();
@@ -590,65 +548,83 @@ async fn convert_video(
             .to_path_buf()
     };
     
-    let output_path = output_dir.join(format!(
+    Ok(output_dir.join(format!(
         "{}_converted.{}",
         file_stem,
         output_format
-    ));
+    )))
+}
 
+// Runs one video conversion as job `job_id`; shared by convert_video and batch conversions
+fn run_video_conversion(
+    input_path: &str,
+    output_path: &Path,
+    settings: &ConversionSettings,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<(), String> {
+    let duration = input_duration(input_path);
     let output_path_str = output_path.to_string_lossy().to_string();
 
+    if let Some(target_size_mb) = settings.target_size_mb {
+        let target = target_size::TargetSizeSettings::from_conversion(settings, target_size_mb);
+        target_size::encode_to_size(input_path, output_path, &target, window, jobs, job_id)?;
+        return Ok(());
+    }
+
     // Check for fast mode (container change only)
     if settings.fast_mode.unwrap_or(false) || settings.video_codec == "copy" {
-        let output = create_hidden_command("ffmpeg")
-            .args([
-                "-i", &input_path,
-                "-c", "copy",
-                "-y", // Overwrite output file
-                &output_path_str
-            ])
-            .output()
-            .map_err(|e| format!("Failed to execute ffmpeg: {}", e))?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("ffmpeg failed: {}", stderr));
-        }
-
-        return Ok(output_path_str);
+        let mut cmd = ffmpeg_progress::command();
+        cmd.args([
+            "-i", input_path,
+            "-c", "copy",
+            "-y", // Overwrite output file
+            &output_path_str
+        ]);
+        return ffmpeg_progress::run_job(window, jobs, job_id, &mut cmd, vec![output_path.to_path_buf()], duration);
     }
 
     // Build ffmpeg command arguments
     let mut args = vec![
         "-i".to_string(),
-        input_path,
+        input_path.to_string(),
         "-c:v".to_string(),
         settings.video_codec.clone(),
         "-c:a".to_string(),
         settings.audio_codec.clone(),
     ];
 
-    // Add quality settings
-    if let Some(crf) = settings.crf {
-        if settings.video_codec == "libx264" || settings.video_codec == "libx265" {
-            args.push("-crf".to_string());
-            args.push(crf.to_string());
+    // Add quality settings, for encoders that have them
+    let rules = capabilities::encoder_rules(&settings.video_codec);
+    if let (Some(crf), Some(_)) = (settings.crf, rules.crf) {
+        args.push("-crf".to_string());
+        args.push(crf.to_string());
+        if rules.zero_bitrate_for_crf && settings.bitrate.as_deref().is_none_or(str::is_empty) {
+            args.push("-b:v".to_string());
+            args.push("0".to_string());
         }
     }
 
     // Add preset
-    if let Some(preset) = settings.preset {
-        if settings.video_codec == "libx264" || settings.video_codec == "libx265" {
+    if let Some(preset) = &settings.preset {
+        if !rules.presets.is_empty() {
             args.push("-preset".to_string());
-            args.push(preset);
+            args.push(preset.clone());
         }
     }
 
+    // Add downscaling
+    if let Some(max_height) = settings.max_height {
+        args.push("-vf".to_string());
+        args.push(format!("scale=-2:'min({},ih)'", max_height));
+    }
+
     // Add bitrate if specified
-    if let Some(bitrate) = settings.bitrate {
+    if let Some(bitrate) = &settings.bitrate {
         if !bitrate.is_empty() {
             args.push("-b:v".to_string());
-            args.push(bitrate);
+            args.push(bitrate.clone());
         }
     }
 
@@ -658,57 +634,52 @@ async fn convert_video(
 
     // Add overwrite flag and output path
     args.push("-y".to_string());
-    args.push(output_path_str.clone());
-
-    // Convert Vec<String> to Vec<&str> for args
-    let args_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-
-    let output = create_hidden_command("ffmpeg")
-        .args(args_refs)
-        .output()
-        .map_err(|e| format!("Failed to execute ffmpeg: {}", e))?;
+    args.push(output_path_str);
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("ffmpeg failed: {}", stderr));
-    }
-
-    Ok(output_path_str)
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(&args);
+    ffmpeg_progress::run_job(window, jobs, job_id, &mut cmd, vec![output_path.to_path_buf()], duration)
 }
 
 #[tauri::command]
-async fn convert_audio(
+#[allow(clippy::too_many_arguments)]
+async fn convert_video(
     input_path: String,
-    output_format: String,
+    output_format: Option<String>,
     output_directory: Option<String>,
-    settings: AudioConversionSettings,
+    settings: Option<ConversionSettings>,
+    // Name of a saved preset to use instead of `settings`
+    preset: Option<String>,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+    presets: State<'_, PresetStore>,
+    capabilities: State<'_, CapabilityCache>,
 ) -> Result<String, String> {
-    // Generate output path
-    let input_pathbuf = Path::new(&input_path);
-    let file_stem = input_pathbuf.file_stem()
-        .ok_or("Invalid input file")?
-        .to_string_lossy();
-    
-    let output_dir = if let Some(dir) = output_directory {
-        Path::new(&dir).to_path_buf()
-    } else {
-        input_pathbuf.parent()
-            .ok_or("Cannot determine output directory")?
-            .to_path_buf()
-    };
-    
-    let output_path = output_dir.join(format!(
-        "{}_converted.{}",
-        file_stem,
-        output_format
-    ));
+    let job_id = job_id.unwrap_or_else(new_job_id);
+    let _job = jobs.reserve(&job_id)?;
+    let (settings, output_format) = presets.video(settings, preset.as_deref(), output_format)?;
+    capabilities::validate_video(capabilities.current().as_ref(), &settings, &output_format).into_result()?;
+    let output_path = converted_output_path(&input_path, &output_format, output_directory)?;
+    run_video_conversion(&input_path, &output_path, &settings, &window, &jobs, &job_id)?;
+    Ok(output_path.to_string_lossy().to_string())
+}
 
-    let output_path_str = output_path.to_string_lossy().to_string();
+// Runs one audio conversion as job `job_id`; shared by convert_audio and batch conversions
+fn run_audio_conversion(
+    input_path: &str,
+    output_path: &Path,
+    settings: &AudioConversionSettings,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<(), String> {
+    let duration = input_duration(input_path);
 
     // Build ffmpeg command arguments
     let mut args = vec![
         "-i".to_string(),
-        input_path,
+        input_path.to_string(),
     ];
 
     // Add audio codec
@@ -716,10 +687,10 @@ async fn convert_audio(
     args.push(settings.codec.clone());
 
     // Add audio-specific settings
-    if let Some(bitrate) = settings.bitrate {
+    if let Some(bitrate) = &settings.bitrate {
         if !bitrate.is_empty() {
             args.push("-b:a".to_string());
-            args.push(bitrate);
+            args.push(bitrate.clone());
         }
     }
 
@@ -750,28 +721,49 @@ async fn convert_audio(
 
     // Add overwrite flag and output path
     args.push("-y".to_string());
-    args.push(output_path_str.clone());
-
-    // Convert Vec<String> to Vec<&str> for args
-    let args_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-
-    let output = create_hidden_command("ffmpeg")
-        .args(args_refs)
-        .output()
-        .map_err(|e| format!("Failed to execute ffmpeg: {}", e))?;
+    args.push(output_path.to_string_lossy().to_string());
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("ffmpeg failed: {}", stderr));
-    }
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(&args);
+    ffmpeg_progress::run_job(window, jobs, job_id, &mut cmd, vec![output_path.to_path_buf()], duration)
+}
 
-    Ok(output_path_str)
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn convert_audio(
+    input_path: String,
+    output_format: Option<String>,
+    output_directory: Option<String>,
+    settings: Option<AudioConversionSettings>,
+    // Name of a saved preset to use instead of `settings`
+    preset: Option<String>,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+    presets: State<'_, PresetStore>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<String, String> {
+    let job_id = job_id.unwrap_or_else(new_job_id);
+    let (settings, output_format) = presets.audio(settings, preset.as_deref(), output_format)?;
+    capabilities::validate_audio(capabilities.current().as_ref(), &settings, &output_format).into_result()?;
+    let output_path = converted_output_path(&input_path, &output_format, output_directory)?;
+    run_audio_conversion(&input_path, &output_path, &settings, &window, &jobs, &job_id)?;
+    Ok(output_path.to_string_lossy().to_string())
 }
 
 #[tauri::command]
-async fn reduce_noise(settings: NoiseReductionSettings) -> Result<String, String> {
+async fn reduce_noise(
+    mut settings: NoiseReductionSettings,
+    job_id: Option<String>,
+    app: AppHandle,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+) -> Result<String, String> {
+    noise::resolve_model(&app, &mut settings)?;
+    let job_id = job_id.unwrap_or_else(new_job_id);
     let input_path = settings.input_path.clone();
-    let output_dir = settings.output_dir;
+    let duration = input_duration(&input_path);
+    let output_dir = settings.output_dir.clone();
     
     // Generate output filename
     let input_pathbuf = Path::new(&input_path);
@@ -786,92 +778,17 @@ async fn reduce_noise(settings: NoiseReductionSettings) -> Result<String, String
     let output_path = Path::new(&output_dir).join(output_filename);
     let output_path_str = output_path.to_string_lossy().to_string();
 
-    // Build FFmpeg arguments based on algorithm
-    let mut args = vec![
-        "-i".to_string(),
-        input_path,
-        "-y".to_string(),
-    ];
-
-    // Build audio filter chain
-    let mut filters = Vec::new();
-
-    match settings.algorithm.as_str() {
-        "afftdn" => {
-            filters.push(format!("afftdn=nr={}:nf={}", settings.noise_reduction, settings.noise_floor));
-        },
-        "anlmdn" => {
-            filters.push(format!("anlmdn=s={}:o={}", settings.noise_reduction, settings.noise_floor.abs()));
-        },
-        "highpass" => {
-            if let Some(freq) = settings.highpass_freq {
-                filters.push(format!("highpass=f={}", freq));
-            }
-        },
-        "combined" => {
-            // High-pass filter to remove low-frequency noise
-            if let Some(freq) = settings.highpass_freq {
-                filters.push(format!("highpass=f={}", freq));
-            }
-            // FFT denoising
-            filters.push(format!("afftdn=nr={}:nf={}", settings.noise_reduction, settings.noise_floor));
-            // Low-pass filter if specified
-            if let Some(freq) = settings.lowpass_freq {
-                filters.push(format!("lowpass=f={}", freq));
-            }
-        },
-        "afftdn+highpass+lowpass" => {
-            // Speech enhancement preset
-            if let Some(freq) = settings.highpass_freq {
-                filters.push(format!("highpass=f={}", freq));
-            }
-            filters.push(format!("afftdn=nr={}:nf={}", settings.noise_reduction, settings.noise_floor));
-            if let Some(freq) = settings.lowpass_freq {
-                filters.push(format!("lowpass=f={}", freq));
-            }
-        },
-        "highpass+notch" => {
-            // Hum removal preset
-            if let Some(freq) = settings.highpass_freq {
-                filters.push(format!("highpass=f={}", freq));
-            }
-            if let Some(freq) = settings.notch_freq {
-                filters.push(format!("bandreject=f={}:w=10", freq));
-            }
-        },
-        _ => {
-            // Default to afftdn
-            filters.push(format!("afftdn=nr={}:nf={}", settings.noise_reduction, settings.noise_floor));
-        }
-    }
-
-    // Add notch filter for specific frequency removal if specified
-    if let Some(freq) = settings.notch_freq {
-        if !settings.algorithm.contains("notch") {
-            filters.push(format!("bandreject=f={}:w=10", freq));
-        }
-    }
-
-    // Join filters with comma
-    if !filters.is_empty() {
-        args.push("-af".to_string());
-        args.push(filters.join(","));
-    }
+    // Build FFmpeg arguments for the chosen algorithm, with the noise profile if one was captured
+    let mut args = vec!["-y".to_string()];
+    args.extend(noise::denoise_args(&settings, None, true)?);
 
     // Add output path
     args.push(output_path_str.clone());
 
     // Execute FFmpeg command
-    let mut cmd = create_hidden_command("ffmpeg");
+    let mut cmd = ffmpeg_progress::command();
     cmd.args(&args);
-
-    let output = cmd.output()
-        .map_err(|e| format!("Failed to execute ffmpeg: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("ffmpeg failed: {}", stderr));
-    }
+    ffmpeg_progress::run_job(&window, &jobs, &job_id, &mut cmd, vec![PathBuf::from(&output_path_str)], duration)?;
 
     Ok(output_path_str)
 }
@@ -1215,7 +1132,112 @@ async fn ytdlp_download(
     subtitles: bool,
     playlist: bool,
     custom_args: String,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+) -> Result<String, String> {
+    // Progress events are tagged with this id so the frontend can match them to its task
+    let job_id = job_id.unwrap_or_else(new_job_id);
+
+    // The positional parameters predate DownloadOptions; `format` doubles as the audio format
+    let options = DownloadOptions {
+        url,
+        output_dir: output_path,
+        quality,
+        format: format.clone(),
+        audio_only,
+        audio_format: format,
+        embed_subs: false,
+        embed_thumbnail: false,
+        embed_metadata: false,
+        retries: 10,
+        cookie_file: None,
+        subtitles,
+        playlist,
+        custom_args,
+    };
+
+    let result = ytdlp_download_internal(&window, &jobs, &job_id, &options);
+    ytdlp_progress::emit_result(&window, &job_id, &result);
+    result
+}
+
+#[tauri::command]
+async fn ytdlp_download_with_options(
+    options: DownloadOptions,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
 ) -> Result<String, String> {
+    let job_id = job_id.unwrap_or_else(new_job_id);
+
+    let result = ytdlp_download_internal(&window, &jobs, &job_id, &options);
+    ytdlp_progress::emit_result(&window, &job_id, &result);
+    result
+}
+
+// The flags shared by the first attempt and the fallback: subtitles, embedding, retries,
+// cookies, playlist handling and the already validated custom arguments
+fn download_option_args(options: &DownloadOptions, custom_args: &[String]) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut push = |flags: &[&str]| args.extend(flags.iter().map(|flag| flag.to_string()));
+
+    // Subtitle options
+    if options.subtitles {
+        push(&["--write-subs", "--write-auto-subs", "--sub-lang", "en,id"]);
+    }
+    if options.embed_subs && !options.audio_only {
+        push(&["--embed-subs"]);
+        if !options.subtitles {
+            push(&["--sub-lang", "en,id"]);
+        }
+    }
+
+    if options.embed_thumbnail {
+        push(&["--embed-thumbnail"]);
+    }
+    if options.embed_metadata {
+        push(&["--embed-metadata"]);
+    }
+
+    let retries = options.retries.to_string();
+    push(&["--retries", &retries, "--fragment-retries", &retries]);
+
+    if let Some(cookie_file) = options.cookie_file.as_deref().filter(|path| !path.is_empty()) {
+        push(&["--cookies", cookie_file]);
+    }
+
+    // Playlist handling
+    if !options.playlist {
+        push(&["--no-playlist"]);
+    }
+
+    // Custom arguments
+    args.extend(custom_args.iter().cloned());
+    args
+}
+
+fn ytdlp_download_internal(
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    options: &DownloadOptions,
+) -> Result<String, String> {
+    let url = &options.url;
+    let quality = &options.quality;
+    let audio_only = options.audio_only;
+
+    // Refuse dangerous custom arguments before anything is started
+    let custom_args = ytdlp_args::parse_custom_args(&options.custom_args)?.args;
+    // Covers the fallback attempt too
+    let _job = jobs.reserve(job_id)?;
+
+    if let Some(cookie_file) = options.cookie_file.as_deref().filter(|path| !path.is_empty()) {
+        if !Path::new(cookie_file).is_file() {
+            return Err(format!("Cookie file not found: {}", cookie_file));
+        }
+    }
+
     // Check if yt-dlp is available
     let mut check_cmd = create_hidden_command("yt-dlp");
     check_cmd.arg("--version");
@@ -1228,11 +1250,7 @@ async fn ytdlp_download(
     let mut cmd = create_hidden_command("yt-dlp");
     
     // Set output directory
-    let output_template = if audio_only {
-        format!("{}/%(title)s.%(ext)s", output_path)
-    } else {
-        format!("{}/%(title)s.%(ext)s", output_path)
-    };
+    let output_template = format!("{}/%(title)s.%(ext)s", options.output_dir);
     
     cmd.args(&["-o", &output_template]);
 
@@ -1244,7 +1262,7 @@ async fn ytdlp_download(
 
     // Audio-only options
     if audio_only {
-        cmd.args(&["-x", "--audio-format", &format]);
+        cmd.args(["-x", "--audio-format", &options.audio_format]);
         
         // Audio quality
         match quality.as_str() {
@@ -1298,11 +1316,12 @@ async fn ytdlp_download(
 
         // Use merge-output-format like in the working Python implementation
         // Extract the actual format from the format label or use mp4 as default
-        let merge_format = if format.to_lowercase().contains("mp4") || format.to_lowercase().contains("video") {
+        let format = options.format.to_lowercase();
+        let merge_format = if format.contains("mp4") || format.contains("video") {
             "mp4"
-        } else if format.to_lowercase().contains("webm") {
+        } else if format.contains("webm") {
             "webm"
-        } else if format.to_lowercase().contains("mkv") {
+        } else if format.contains("mkv") {
             "mkv"
         } else {
             "mp4" // Default to mp4
@@ -1310,33 +1329,20 @@ async fn ytdlp_download(
         cmd.args(&["--merge-output-format", merge_format]);
     }
 
-    // Subtitle options
-    if subtitles {
-        cmd.args(&["--write-subs", "--write-auto-subs", "--sub-lang", "en,id"]);
-    }
-
-    // Playlist handling
-    if !playlist {
-        cmd.arg("--no-playlist");
-    }
-
-    // Custom arguments
-    if !custom_args.trim().is_empty() {
-        let args: Vec<&str> = custom_args.split_whitespace().collect();
-        cmd.args(&args);
-    }
+    cmd.args(download_option_args(options, &custom_args));
 
     // Add URL
-    cmd.arg(&url);
+    cmd.arg(url);
 
-    // Execute command
-    match cmd.output() {
+    // Execute command, streaming progress events while it runs
+    match ytdlp_progress::run_with_progress(&mut cmd, window, jobs, job_id) {
+        Ok(output) if output.cancelled => Err(jobs::cancelled_error(job_id)),
         Ok(output) => {
-            if output.status.success() {
-                let output_str = String::from_utf8_lossy(&output.stdout);
+            if output.success {
+                let output_str = &output.stdout;
                 Ok(format!("Download completed successfully. Output: {}", output_str))
             } else {
-                let error_str = String::from_utf8_lossy(&output.stderr);
+                let error_str = &output.stderr;
                 
                 // Check if the error is about format not being available or HTTP 403
                 if error_str.contains("Requested format is not available") || error_str.contains("HTTP Error 403") || error_str.contains("unable to download") {
@@ -1349,7 +1355,7 @@ async fn ytdlp_download(
                     fallback_cmd.arg("--verbose"); // Keep verbose for debugging
                     
                     if audio_only {
-                        fallback_cmd.args(&["-x", "--audio-format", &format]);
+                        fallback_cmd.args(["-x", "--audio-format", &options.audio_format]);
                         fallback_cmd.arg("--audio-quality=0");
                     } else {
                         // Use working fallback format selectors without height>=
@@ -1370,29 +1376,19 @@ async fn ytdlp_download(
                         fallback_cmd.args(&["--merge-output-format", "mp4"]);
                     }
                     
-                    if subtitles {
-                        fallback_cmd.args(&["--write-subs", "--write-auto-subs", "--sub-lang", "en,id"]);
-                    }
-                    
-                    if !playlist {
-                        fallback_cmd.arg("--no-playlist");
-                    }
+                    fallback_cmd.args(download_option_args(options, &custom_args));
                     
-                    if !custom_args.trim().is_empty() {
-                        let args: Vec<&str> = custom_args.split_whitespace().collect();
-                        fallback_cmd.args(&args);
-                    }
-                    
-                    fallback_cmd.arg(&url);
+                    fallback_cmd.arg(url);
                     
                     // Try the fallback
-                    match fallback_cmd.output() {
+                    match ytdlp_progress::run_with_progress(&mut fallback_cmd, window, jobs, job_id) {
+                        Ok(fallback_output) if fallback_output.cancelled => Err(jobs::cancelled_error(job_id)),
                         Ok(fallback_output) => {
-                            if fallback_output.status.success() {
-                                let output_str = String::from_utf8_lossy(&fallback_output.stdout);
+                            if fallback_output.success {
+                                let output_str = &fallback_output.stdout;
                                 Ok(format!("Download completed with fallback format. Output: {}", output_str))
                             } else {
-                                let fallback_error = String::from_utf8_lossy(&fallback_output.stderr);
+                                let fallback_error = &fallback_output.stderr;
                                 Err(format!("yt-dlp download failed even with fallback: Original error: {}\nFallback error: {}", error_str, fallback_error))
                             }
                         }
@@ -1427,14 +1423,46 @@ async fn check_ytdlp() -> Result<String, String> {
 
 fn main() {
     tauri::Builder::default()
+        .manage(JobRegistry::default())
+        .manage(CapabilityCache::default())
+        .setup(|app| {
+            // Restore the download queue and pick up whatever was still pending
+            let handle = app.handle();
+            app.manage(DownloadQueue::load(&handle));
+            app.manage(MediaLibrary::load(&handle));
+            app.manage(LibraryWatcher::default());
+            app.manage(ScanRegistry::default());
+            app.manage(ThumbnailCache::load(&handle));
+            app.manage(PresetStore::load(&handle));
+            for root in app.state::<MediaLibrary>().roots() {
+                if let Err(e) = app.state::<LibraryWatcher>().watch(&handle, &root.directory) {
+                    eprintln!("{}", e);
+                }
+            }
+            app.state::<DownloadQueue>().pump(&handle);
+            Ok(())
+        })
+        .register_uri_scheme_protocol(thumbnails::PROTOCOL, thumbnails::serve)
         .invoke_handler(tauri::generate_handler![
             greet, 
             download_file, 
             select_directory, 
             scan_media_files, 
+            scanner::scan_media_files_streaming,
+            scanner::cancel_scan,
             get_file_url,
             toggle_fullscreen,
             get_media_duration,
+            probe::probe_media,
+            library::library_rescan,
+            library::library_query,
+            library::library_roots,
+            library::library_remove_root,
+            thumbnails::get_thumbnail,
+            duplicates::find_duplicates,
+            thumbnails::thumbnail_cache_info,
+            thumbnails::set_thumbnail_cache_limit,
+            thumbnails::clear_thumbnail_cache,
             open_file_location,
             check_ffmpeg,
             calculate_loops,
@@ -1444,13 +1472,108 @@ fn main() {
             convert_video,
             convert_audio,
             reduce_noise,
+            trim::trim_media,
+            concat::concat_media,
+            batch::batch_convert,
+            presets::list_presets,
+            presets::create_preset,
+            presets::update_preset,
+            presets::delete_preset,
+            presets::import_presets,
+            presets::export_presets,
+            capabilities::get_ffmpeg_capabilities,
+            capabilities::validate_conversion_settings,
+            target_size::convert_to_target_size,
+            loudness::measure_loudness,
+            loudness::normalize_loudness,
+            noise::capture_noise_profile,
+            noise::list_noise_models,
+            noise::preview_noise_reduction,
             ytdlp_get_info,
             ytdlp_get_playlist_info,
             ytdlp_get_video_details,
             ytdlp_list_formats,
             ytdlp_download,
-            check_ytdlp
+            ytdlp_download_with_options,
+            ytdlp_args::ytdlp_validate_args,
+            check_ytdlp,
+            jobs::cancel_job,
+            jobs::pause_job,
+            jobs::resume_job,
+            download_queue::queue_add,
+            download_queue::queue_list,
+            download_queue::queue_remove,
+            download_queue::queue_move,
+            download_queue::queue_set_priority,
+            download_queue::queue_retry,
+            download_queue::queue_set_concurrency,
+            download_queue::queue_clear_finished
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> DownloadOptions {
+        DownloadOptions {
+            url: "https://example.com/watch?v=1".to_string(),
+            output_dir: "/downloads".to_string(),
+            quality: "1080p".to_string(),
+            format: "mp4".to_string(),
+            audio_only: false,
+            audio_format: "mp3".to_string(),
+            embed_subs: false,
+            embed_thumbnail: false,
+            embed_metadata: false,
+            retries: 3,
+            cookie_file: None,
+            subtitles: false,
+            playlist: false,
+            custom_args: String::new(),
+        }
+    }
+
+    #[test]
+    fn plain_download_sets_retries_and_skips_playlists() {
+        assert_eq!(
+            download_option_args(&options(), &[]),
+            ["--retries", "3", "--fragment-retries", "3", "--no-playlist"]
+        );
+        let playlist = DownloadOptions { playlist: true, retries: 10, ..options() };
+        assert_eq!(download_option_args(&playlist, &[]), ["--retries", "10", "--fragment-retries", "10"]);
+    }
+
+    #[test]
+    fn embedded_subtitles_pick_languages_once() {
+        let embed = DownloadOptions { embed_subs: true, ..options() };
+        assert_eq!(download_option_args(&embed, &[])[..3], ["--embed-subs", "--sub-lang", "en,id"]);
+
+        let both = DownloadOptions { embed_subs: true, subtitles: true, ..options() };
+        let args = download_option_args(&both, &[]);
+        assert_eq!(args[..5], ["--write-subs", "--write-auto-subs", "--sub-lang", "en,id", "--embed-subs"]);
+        assert_eq!(args.iter().filter(|arg| *arg == "--sub-lang").count(), 1);
+    }
+
+    #[test]
+    fn audio_downloads_never_embed_subtitles() {
+        let audio = DownloadOptions { embed_subs: true, audio_only: true, ..options() };
+        let args = download_option_args(&audio, &[]);
+        assert!(!args.iter().any(|arg| arg == "--embed-subs" || arg == "--sub-lang"), "{:?}", args);
+    }
+
+    #[test]
+    fn cookie_file_and_custom_args_are_passed_on() {
+        let cookies = DownloadOptions { cookie_file: Some("/home/me/cookies.txt".to_string()), ..options() };
+        let args = download_option_args(&cookies, &["--limit-rate".to_string(), "1M".to_string()]);
+        assert_eq!(
+            args,
+            ["--retries", "3", "--fragment-retries", "3", "--cookies", "/home/me/cookies.txt", "--no-playlist", "--limit-rate", "1M"]
+        );
+
+        let empty = DownloadOptions { cookie_file: Some(String::new()), ..options() };
+        assert!(!download_option_args(&empty, &[]).contains(&"--cookies".to_string()));
+    }
+}
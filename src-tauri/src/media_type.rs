@@ -0,0 +1,384 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::probe::{self, MediaProbe, StreamKind};
+
+// How much of the file is read for sniffing. Enough for three MPEG-TS packets and the
+// first Ogg page, and for text formats to show their first cue or entry.
+const SNIFF_LEN: usize = 4096;
+
+// The category stored in MediaFile.file_type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Video,
+    Audio,
+    Image,
+    Subtitle,
+    Playlist,
+    Unknown,
+}
+
+impl MediaType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaType::Video => "video",
+            MediaType::Audio => "audio",
+            MediaType::Image => "image",
+            MediaType::Subtitle => "subtitle",
+            MediaType::Playlist => "playlist",
+            MediaType::Unknown => "unknown",
+        }
+    }
+}
+
+// Containers that hold video or audio-only content alike. Their extensions say which one is
+// usual; anything else (a wrong or missing extension) needs a probe to tell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Container {
+    IsoMedia,
+    Matroska,
+    Ogg,
+    Asf,
+}
+
+impl Container {
+    fn extension_hint(&self, ext: &str) -> Option<MediaType> {
+        let (video, audio): (&[&str], &[&str]) = match self {
+            Container::IsoMedia => (&["mp4", "m4v", "mov", "3gp", "3g2", "f4v"], &["m4a", "m4b", "m4p", "f4a"]),
+            Container::Matroska => (&["mkv", "webm", "mk3d"], &["mka", "weba"]),
+            Container::Ogg => (&["ogv"], &["ogg", "oga", "opus", "spx"]),
+            Container::Asf => (&["wmv", "asf"], &["wma"]),
+        };
+        if video.contains(&ext) {
+            Some(MediaType::Video)
+        } else if audio.contains(&ext) {
+            Some(MediaType::Audio)
+        } else {
+            None
+        }
+    }
+}
+
+enum Sniffed {
+    Known(MediaType),
+    Container(Container),
+    // Readable text we don't recognise; never worth a probe
+    Text,
+    Nothing,
+}
+
+// The category a file extension normally stands for
+pub fn type_for_extension(ext: &str) -> MediaType {
+    match ext {
+        "mp4" | "avi" | "mkv" | "mov" | "wmv" | "flv" | "webm" | "m4v" | "3gp" | "3g2" | "ogv" | "ts" | "mts"
+        | "m2ts" | "m2t" | "mpg" | "mpeg" | "vob" | "asf" | "f4v" | "mxf" | "divx" | "rm" | "rmvb" => MediaType::Video,
+        "mp3" | "wav" | "flac" | "aac" | "ogg" | "oga" | "opus" | "m4a" | "m4b" | "wma" | "aiff" | "aif" | "alac"
+        | "ape" | "wv" | "mka" | "weba" | "ac3" | "eac3" | "dts" | "amr" | "spx" | "caf" | "mid" | "midi" => {
+            MediaType::Audio
+        }
+        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tif" | "tiff" | "heic" | "avif" => MediaType::Image,
+        "srt" | "vtt" | "ass" | "ssa" | "sub" | "sbv" | "ttml" | "dfxp" | "lrc" => MediaType::Subtitle,
+        "m3u" | "m3u8" | "pls" | "xspf" | "wpl" | "cue" => MediaType::Playlist,
+        _ => MediaType::Unknown,
+    }
+}
+
+// Works out what a file contains: first from its leading bytes, then from a probe when the bytes
+// are ambiguous or unrecognised, and finally from the extension.
+pub fn detect(path: &Path) -> MediaType {
+    classify(path, true)
+}
+
+// Like detect, but never runs ffprobe: ambiguous or unrecognised files fall back to their extension.
+// Used while walking directories, where the library's background probe refines the type later.
+pub fn detect_without_probe(path: &Path) -> MediaType {
+    classify(path, false)
+}
+
+fn classify(path: &Path, allow_probe: bool) -> MediaType {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let probe = || if allow_probe { quick_probe(path) } else { None };
+
+    match sniff_file(path) {
+        // VobSub .sub files are MPEG program streams holding nothing but subtitle bitmaps
+        Sniffed::Known(MediaType::Video) if ext == "sub" => MediaType::Subtitle,
+        Sniffed::Known(kind) => kind,
+        Sniffed::Container(container) => container
+            .extension_hint(&ext)
+            .or_else(probe)
+            .unwrap_or(MediaType::Unknown),
+        // Plain text formats without a signature (.m3u, .cue, .lrc, MicroDVD .sub) are trusted as named
+        Sniffed::Text => match type_for_extension(&ext) {
+            kind @ (MediaType::Subtitle | MediaType::Playlist) => kind,
+            _ => MediaType::Unknown,
+        },
+        Sniffed::Nothing => {
+            let by_extension = type_for_extension(&ext);
+            match by_extension {
+                // Less common image formats (BMP, some TIFF variants) have no signature checked above
+                MediaType::Image => by_extension,
+                _ => probe().unwrap_or(by_extension),
+            }
+        }
+    }
+}
+
+fn sniff_file(path: &Path) -> Sniffed {
+    let Ok(mut file) = File::open(path) else {
+        return Sniffed::Nothing;
+    };
+    let mut buf = Vec::with_capacity(SNIFF_LEN);
+    if file.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut buf).is_err() {
+        return Sniffed::Nothing;
+    }
+    sniff(&buf)
+}
+
+fn sniff(buf: &[u8]) -> Sniffed {
+    if let Some(kind) = sniff_binary(buf) {
+        return kind;
+    }
+    sniff_text(buf)
+}
+
+fn sniff_binary(buf: &[u8]) -> Option<Sniffed> {
+    use MediaType::*;
+    let at = |offset: usize, magic: &[u8]| buf.get(offset..offset + magic.len()) == Some(magic);
+
+    // ISO base media (MP4, MOV, 3GP, M4A, HEIF/AVIF): the brand of the ftyp box gives it away
+    if at(4, b"ftyp") {
+        let brand = buf.get(8..12).unwrap_or_default();
+        return Some(match brand {
+            b"M4A " | b"M4B " | b"M4P " | b"F4A " | b"F4B " => Sniffed::Known(Audio),
+            b"heic" | b"heix" | b"mif1" | b"msf1" | b"avif" | b"avis" => Sniffed::Known(Image),
+            b"qt  " => Sniffed::Known(Video),
+            _ => Sniffed::Container(Container::IsoMedia),
+        });
+    }
+    if at(0, &[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some(Sniffed::Container(Container::Matroska));
+    }
+    if at(0, b"OggS") {
+        // The first page carries the identification header of the first stream
+        let head = buf.get(28..buf.len().clamp(28, 64)).unwrap_or_default();
+        let contains = |needle: &[u8]| head.windows(needle.len()).any(|window| window == needle);
+        return Some(if contains(b"theora") || contains(b"fishead") || contains(b"\x80daala") {
+            Sniffed::Known(Video)
+        } else if contains(b"vorbis") || contains(b"OpusHead") || contains(b"FLAC") || contains(b"Speex") {
+            Sniffed::Known(Audio)
+        } else {
+            Sniffed::Container(Container::Ogg)
+        });
+    }
+    if at(0, &[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) {
+        return Some(Sniffed::Container(Container::Asf));
+    }
+    if at(0, b"RIFF") {
+        return match buf.get(8..12).unwrap_or_default() {
+            b"AVI " => Some(Sniffed::Known(Video)),
+            b"WAVE" => Some(Sniffed::Known(Audio)),
+            b"WEBP" => Some(Sniffed::Known(Image)),
+            _ => None,
+        };
+    }
+
+    // MPEG transport streams sync on 0x47 every 188 bytes; Blu-ray M2TS adds a 4 byte timestamp to each packet
+    let ts_sync = |start: usize, packet: usize| (0..3).all(|i| buf.get(start + i * packet) == Some(&0x47));
+    if ts_sync(0, 188) || ts_sync(4, 192) {
+        return Some(Sniffed::Known(Video));
+    }
+
+    let video: &[&[u8]] = &[
+        b"FLV\x01",
+        &[0x00, 0x00, 0x01, 0xBA], // MPEG program stream (.mpg, .vob)
+        &[0x00, 0x00, 0x01, 0xB3], // MPEG-1/2 video elementary stream
+        b".RMF",
+        &[0x06, 0x0E, 0x2B, 0x34, 0x02, 0x05, 0x01, 0x01], // MXF
+    ];
+    let audio: &[&[u8]] = &[
+        b"ID3",
+        b"fLaC",
+        b"#!AMR",
+        b"MAC ",
+        b"wvpk",
+        b"caff",
+        b"MThd",
+        &[0x0B, 0x77], // AC-3
+        &[0x7F, 0xFE, 0x80, 0x01], // DTS
+    ];
+    let image: &[&[u8]] = &[
+        &[0xFF, 0xD8, 0xFF],
+        b"\x89PNG\r\n\x1a\n",
+        b"GIF87a",
+        b"GIF89a",
+        b"II*\x00",
+        b"MM\x00*",
+    ];
+    for (kind, signatures) in [(Video, video), (Audio, audio), (Image, image)] {
+        if signatures.iter().any(|magic| at(0, magic)) {
+            return Some(Sniffed::Known(kind));
+        }
+    }
+
+    if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        return Some(Sniffed::Known(Audio));
+    }
+    // MP3 and ADTS AAC without an ID3 tag start straight with a frame: 11 set sync bits
+    if buf.len() >= 2 && buf[0] == 0xFF && buf[1] & 0xE0 == 0xE0 {
+        return Some(Sniffed::Known(Audio));
+    }
+    None
+}
+
+fn sniff_text(buf: &[u8]) -> Sniffed {
+    let buf = buf.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(buf);
+    // A multi-byte character may be cut off at the end of the buffer
+    let text = match std::str::from_utf8(buf) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&buf[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return Sniffed::Nothing,
+    };
+    if text.contains('\0') {
+        return Sniffed::Nothing;
+    }
+    let trimmed = text.trim_start();
+
+    if trimmed.starts_with("WEBVTT") || trimmed.starts_with("[Script Info]") {
+        return Sniffed::Known(MediaType::Subtitle);
+    }
+    if trimmed.starts_with("#EXTM3U") || trimmed.to_ascii_lowercase().starts_with("[playlist]") {
+        return Sniffed::Known(MediaType::Playlist);
+    }
+    if trimmed.starts_with('<') {
+        let lower = trimmed.to_ascii_lowercase();
+        if lower.contains("<tt ") || lower.contains("<tt>") {
+            return Sniffed::Known(MediaType::Subtitle);
+        }
+        if lower.contains("<playlist") || lower.contains("<smil") {
+            return Sniffed::Known(MediaType::Playlist);
+        }
+        return Sniffed::Text;
+    }
+
+    // SubRip: a cue number followed by a "00:00:01,000 --> 00:00:02,000" timing line
+    let mut lines = trimmed.lines().map(str::trim);
+    let first = lines.next().unwrap_or_default();
+    let second = lines.next().unwrap_or_default();
+    if !first.is_empty() && first.chars().all(|c| c.is_ascii_digit()) && second.contains("-->") {
+        return Sniffed::Known(MediaType::Subtitle);
+    }
+    Sniffed::Text
+}
+
+// Asks ffprobe as a last resort
+fn quick_probe(path: &Path) -> Option<MediaType> {
+    let probe = probe::probe(&path.to_string_lossy()).ok()?;
+    type_from_probe(&probe)
+}
+
+// The category a probe shows. Cover art doesn't make an audio file a video.
+pub fn type_from_probe(probe: &MediaProbe) -> Option<MediaType> {
+    if probe.primary_video().is_some() {
+        // Still images come back as a single-frame video stream from the image2 or *_pipe demuxers
+        let format = probe.format.format_name.as_str();
+        if format == "image2" || format.ends_with("_pipe") {
+            return Some(MediaType::Image);
+        }
+        return Some(MediaType::Video);
+    }
+    if probe.primary_audio().is_some() {
+        return Some(MediaType::Audio);
+    }
+    if probe.streams.iter().any(|stream| stream.kind == StreamKind::Subtitle) {
+        return Some(MediaType::Subtitle);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known(buf: &[u8]) -> Option<MediaType> {
+        match sniff(buf) {
+            Sniffed::Known(kind) => Some(kind),
+            _ => None,
+        }
+    }
+
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut buf = vec![0, 0, 0, 0x20];
+        buf.extend_from_slice(b"ftyp");
+        buf.extend_from_slice(brand);
+        buf
+    }
+
+    #[test]
+    fn iso_media_brands() {
+        assert_eq!(known(&ftyp(b"M4A ")), Some(MediaType::Audio));
+        assert_eq!(known(&ftyp(b"avif")), Some(MediaType::Image));
+        assert_eq!(known(&ftyp(b"qt  ")), Some(MediaType::Video));
+        assert!(matches!(sniff(&ftyp(b"isom")), Sniffed::Container(Container::IsoMedia)));
+    }
+
+    #[test]
+    fn ambiguous_containers_use_the_extension() {
+        assert_eq!(Container::Matroska.extension_hint("mka"), Some(MediaType::Audio));
+        assert_eq!(Container::IsoMedia.extension_hint("mp4"), Some(MediaType::Video));
+        assert_eq!(Container::Ogg.extension_hint("bin"), None);
+        assert!(matches!(sniff(&[0x1A, 0x45, 0xDF, 0xA3, 0x01]), Sniffed::Container(Container::Matroska)));
+    }
+
+    #[test]
+    fn ogg_streams_by_codec() {
+        let page = |codec: &[u8]| {
+            let mut buf = b"OggS".to_vec();
+            buf.resize(28, 0);
+            buf.extend_from_slice(codec);
+            buf
+        };
+        assert_eq!(known(&page(b"\x01vorbis")), Some(MediaType::Audio));
+        assert_eq!(known(&page(b"OpusHead")), Some(MediaType::Audio));
+        assert_eq!(known(&page(b"\x80theora")), Some(MediaType::Video));
+    }
+
+    #[test]
+    fn binary_signatures() {
+        assert_eq!(known(b"RIFF\0\0\0\0WAVEfmt "), Some(MediaType::Audio));
+        assert_eq!(known(b"RIFF\0\0\0\0AVI LIST"), Some(MediaType::Video));
+        assert_eq!(known(b"ID3\x04\0"), Some(MediaType::Audio));
+        assert_eq!(known(&[0xFF, 0xFB, 0x90, 0x00]), Some(MediaType::Audio));
+        assert_eq!(known(b"\x89PNG\r\n\x1a\n"), Some(MediaType::Image));
+        assert_eq!(known(b"FLV\x01\x05"), Some(MediaType::Video));
+
+        let mut ts = vec![0u8; 188 * 3];
+        for packet in 0..3 {
+            ts[packet * 188] = 0x47;
+        }
+        assert_eq!(known(&ts), Some(MediaType::Video));
+    }
+
+    #[test]
+    fn text_formats() {
+        assert_eq!(known(b"\xEF\xBB\xBFWEBVTT\n\n"), Some(MediaType::Subtitle));
+        assert_eq!(known(b"1\n00:00:01,000 --> 00:00:02,000\nHello\n"), Some(MediaType::Subtitle));
+        assert_eq!(known(b"#EXTM3U\n#EXTINF:-1,Radio\n"), Some(MediaType::Playlist));
+        assert!(matches!(sniff(b"just some notes\n"), Sniffed::Text));
+        assert!(matches!(sniff(b"\0\0\0\x01\x02"), Sniffed::Nothing));
+    }
+
+    #[test]
+    fn extension_categories() {
+        assert_eq!(type_for_extension("m2ts"), MediaType::Video);
+        assert_eq!(type_for_extension("opus"), MediaType::Audio);
+        assert_eq!(type_for_extension("lrc"), MediaType::Subtitle);
+        assert_eq!(type_for_extension("nfo"), MediaType::Unknown);
+    }
+}
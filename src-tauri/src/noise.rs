@@ -0,0 +1,516 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State, Window};
+
+use crate::ffmpeg_progress::{self, Pass};
+use crate::jobs::JobRegistry;
+use crate::NoiseReductionSettings;
+
+// Name of the afftdn instance that learns the noise profile, so asendcmd can address it
+const PROFILE_TARGET: &str = "afftdn@profile";
+// afftdn only accepts noise floors in this range (dB)
+const NOISE_FLOOR_RANGE: (f64, f64) = (-80.0, -20.0);
+const MIN_PROFILE_SECONDS: f64 = 0.5;
+const DEFAULT_PREVIEW_SECONDS: f64 = 10.0;
+const MAX_PREVIEW_SECONDS: f64 = 30.0;
+// Silence between the original and the denoised half of the A/B file
+const AB_GAP_SECONDS: f64 = 0.5;
+// Preview files older than this are cleaned up when the next preview is rendered
+const PREVIEW_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+// Bundled RNNoise models for arnndn, relative to the resource directory
+const MODEL_DIR: &str = "models/rnnoise";
+const DEFAULT_MODEL: &str = "cb";
+// Band kept by the speech preset when no highpass/lowpass frequency is given (Hz)
+const SPEECH_BAND: (f32, f32) = (80.0, 12000.0);
+
+// (id, file, description) of the models shipped with the app, from GregorR/rnnoise-models and
+// checked in under MODEL_DIR (see models/rnnoise/README.md)
+const BUNDLED_MODELS: [(&str, &str, &str); 3] = [
+    ("cb", "cb.rnnn", "Speech over general background noise (fans, keyboards, rooms)"),
+    ("bd", "bd.rnnn", "Speech over recording noise (hiss, hum, cheap microphones)"),
+    ("sh", "sh.rnnn", "Any signal over general noise; gentler on music and effects"),
+];
+
+// A stretch of the input that contains only background noise, and how loud that noise is
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseProfile {
+    pub start: f64,
+    pub end: f64,
+    pub rms_level: f64,
+    pub peak_level: f64,
+    // The RMS level clamped to what afftdn accepts; used as its noise floor
+    pub noise_floor: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoisePreview {
+    pub original: String,
+    pub denoised: String,
+    // The original excerpt, a short gap, then the denoised excerpt
+    pub ab: String,
+    pub start: f64,
+    pub length: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoiseModel {
+    pub id: String,
+    pub description: String,
+    pub path: String,
+}
+
+fn bundled_model_path(app: &AppHandle, file: &str) -> Option<PathBuf> {
+    app.path_resolver().resolve_resource(format!("{}/{}", MODEL_DIR, file))
+}
+
+// Whether the algorithm runs arnndn and so needs a model. Must name exactly the filter_chain
+// arms that add arnndn; anything else falls back to afftdn there.
+fn uses_model(algorithm: &str) -> bool {
+    matches!(algorithm, "arnndn" | "speech")
+}
+
+// Turns `settings.model` (a bundled model id, a path to a .rnnn file, or nothing for the default
+// model) into an absolute path, so the filters can be built without the app handle
+pub fn resolve_model(app: &AppHandle, settings: &mut NoiseReductionSettings) -> Result<(), String> {
+    if !uses_model(&settings.algorithm) {
+        return Ok(());
+    }
+    let requested = settings.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string());
+    let path = match BUNDLED_MODELS.iter().find(|(id, _, _)| *id == requested) {
+        // Only missing from a checkout without the model files; see models/rnnoise/README.md
+        Some((id, file, _)) => bundled_model_path(app, file).filter(|path| path.is_file()).ok_or(format!(
+            "The RNNoise model {} is not installed ({} is missing). Pick an installed model or the path of a .rnnn file.",
+            id, file
+        ))?,
+        None => PathBuf::from(&requested),
+    };
+    if !path.is_file() {
+        return Err(format!("RNNoise model not found: {}", path.display()));
+    }
+    settings.model = Some(path.to_string_lossy().to_string());
+    Ok(())
+}
+
+// Escapes a path for use as a filter option inside a filtergraph: once for the option parser and
+// once more for the graph parser. Backslashes become slashes, which FFmpeg accepts on Windows too.
+fn filter_path(path: &str) -> String {
+    let escape = |value: String, special: &[char]| -> String {
+        value
+            .chars()
+            .flat_map(|c| if special.contains(&c) { vec!['\\', c] } else { vec![c] })
+            .collect()
+    };
+    let option = escape(path.replace('\\', "/"), &['\'', ':']);
+    escape(option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn arnndn_filter(settings: &NoiseReductionSettings) -> String {
+    // resolve_model has replaced the id with a path by now
+    let model = settings.model.as_deref().unwrap_or_default();
+    format!("arnndn=m={}", filter_path(model))
+}
+
+fn afftdn_filter(settings: &NoiseReductionSettings) -> String {
+    match &settings.noise_profile {
+        Some(profile) => format!("{}=nr={}:nf={}", PROFILE_TARGET, settings.noise_reduction, profile.noise_floor),
+        None => format!("afftdn=nr={}:nf={}", settings.noise_reduction, settings.noise_floor),
+    }
+}
+
+// The audio filters for the chosen algorithm, in order
+pub fn filter_chain(settings: &NoiseReductionSettings) -> Vec<String> {
+    let mut filters = Vec::new();
+    let afftdn = afftdn_filter(settings);
+
+    match settings.algorithm.as_str() {
+        "afftdn" => {
+            filters.push(afftdn);
+        },
+        "anlmdn" => {
+            filters.push(format!("anlmdn=s={}:o={}", settings.noise_reduction, settings.noise_floor.abs()));
+        },
+        "highpass" => {
+            if let Some(freq) = settings.highpass_freq {
+                filters.push(format!("highpass=f={}", freq));
+            }
+        },
+        "combined" | "afftdn+highpass+lowpass" => {
+            // High-pass filter to remove low-frequency noise, FFT denoising, then the optional low-pass
+            if let Some(freq) = settings.highpass_freq {
+                filters.push(format!("highpass=f={}", freq));
+            }
+            filters.push(afftdn);
+            if let Some(freq) = settings.lowpass_freq {
+                filters.push(format!("lowpass=f={}", freq));
+            }
+        },
+        "arnndn" => {
+            filters.push(arnndn_filter(settings));
+        },
+        "speech" => {
+            // Neural speech denoising inside the voice band
+            filters.push(format!("highpass=f={}", settings.highpass_freq.unwrap_or(SPEECH_BAND.0)));
+            filters.push(arnndn_filter(settings));
+            filters.push(format!("lowpass=f={}", settings.lowpass_freq.unwrap_or(SPEECH_BAND.1)));
+        },
+        "highpass+notch" => {
+            // Hum removal preset
+            if let Some(freq) = settings.highpass_freq {
+                filters.push(format!("highpass=f={}", freq));
+            }
+            if let Some(freq) = settings.notch_freq {
+                filters.push(format!("bandreject=f={}:w=10", freq));
+            }
+        },
+        _ => {
+            // Default to afftdn
+            filters.push(afftdn);
+        }
+    }
+
+    // Add notch filter for specific frequency removal if specified
+    if let Some(freq) = settings.notch_freq {
+        if !settings.algorithm.contains("notch") {
+            filters.push(format!("bandreject=f={}:w=10", freq));
+        }
+    }
+
+    filters
+}
+
+// FFmpeg arguments for denoising `settings.input_path`, up to the output path. `excerpt` limits the
+// input to (start, length) seconds. With a noise profile the noise segment is read as a second input
+// and played through afftdn first while it samples noise; that lead-in is cut off again afterwards.
+pub fn denoise_args(
+    settings: &NoiseReductionSettings,
+    excerpt: Option<(f64, f64)>,
+    keep_video: bool,
+) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    if let Some((start, length)) = excerpt {
+        args.extend(["-ss".to_string(), format!("{:.3}", start), "-t".to_string(), format!("{:.3}", length)]);
+    }
+    args.extend(["-i".to_string(), settings.input_path.clone()]);
+
+    let filters = filter_chain(settings);
+    let Some(profile) = &settings.noise_profile else {
+        if !filters.is_empty() {
+            args.extend(["-af".to_string(), filters.join(",")]);
+        }
+        if !keep_video {
+            args.push("-vn".to_string());
+        }
+        return Ok(args);
+    };
+
+    if !filters.iter().any(|filter| filter.starts_with(PROFILE_TARGET)) {
+        return Err(format!("A noise profile only works with afftdn based algorithms, not {}", settings.algorithm));
+    }
+    let lead_in = profile.end - profile.start;
+    args.extend([
+        "-ss".to_string(),
+        format!("{:.3}", profile.start),
+        "-t".to_string(),
+        format!("{:.3}", lead_in),
+        "-i".to_string(),
+        settings.input_path.clone(),
+    ]);
+
+    let graph = format!(
+        "[1:a:0]asetpts=PTS-STARTPTS[noise];[0:a:0]asetpts=PTS-STARTPTS[main];\
+         [noise][main]concat=n=2:v=0:a=1,\
+         asendcmd=c='0 {target} sample_noise start;{lead_in:.3} {target} sample_noise stop',\
+         {chain},atrim=start={lead_in:.3},asetpts=PTS-STARTPTS[out]",
+        target = PROFILE_TARGET,
+        lead_in = lead_in,
+        chain = filters.join(","),
+    );
+    args.extend(["-filter_complex".to_string(), graph]);
+    if keep_video {
+        args.extend(["-map".to_string(), "0:v?".to_string(), "-c:v".to_string(), "copy".to_string()]);
+    }
+    args.extend(["-map".to_string(), "[out]".to_string()]);
+    Ok(args)
+}
+
+// The bundled RNNoise models that are installed, for the model picker
+#[tauri::command]
+pub fn list_noise_models(app: AppHandle) -> Vec<NoiseModel> {
+    BUNDLED_MODELS
+        .iter()
+        .filter_map(|(id, file, description)| {
+            // Only models whose file is actually installed can be picked
+            let path = bundled_model_path(&app, file).filter(|path| path.is_file())?;
+            Some(NoiseModel {
+                id: id.to_string(),
+                description: description.to_string(),
+                path: path.to_string_lossy().to_string(),
+            })
+        })
+        .collect()
+}
+
+// Value after `label` on the last line that has it; astats prints the overall figures last.
+// Digital silence reports -inf, which counts as no value.
+fn astats_value(stderr: &str, label: &str) -> Option<f64> {
+    stderr
+        .lines()
+        .rev()
+        .find_map(|line| line.split_once(label).map(|(_, value)| value.trim().to_string()))
+        .and_then(|value| value.parse().ok())
+        .filter(|value: &f64| value.is_finite())
+}
+
+// Measures a noise-only stretch of the input so reduce_noise can learn its profile from it
+#[tauri::command]
+pub async fn capture_noise_profile(
+    input_path: String,
+    start: f64,
+    end: f64,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+) -> Result<NoiseProfile, String> {
+    if start < 0.0 || end - start < MIN_PROFILE_SECONDS {
+        return Err(format!("Pick at least {} seconds of noise", MIN_PROFILE_SECONDS));
+    }
+
+    let job_id = job_id.unwrap_or_else(crate::new_job_id);
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-ss", &format!("{:.3}", start), "-t", &format!("{:.3}", end - start)]);
+    cmd.args(["-i", &input_path, "-map", "0:a:0", "-af", "astats", "-f", "null", "-"]);
+    let length = Some(end - start);
+    let output = ffmpeg_progress::run_job_output(&window, &jobs, &job_id, &mut cmd, Vec::new(), length, Pass::WHOLE)?;
+
+    // Silence needs no profile anyway
+    let rms_level = astats_value(&output.stderr, "RMS level dB:").ok_or("The selected segment is silent")?;
+    let peak_level = astats_value(&output.stderr, "Peak level dB:").unwrap_or(rms_level);
+
+    Ok(NoiseProfile {
+        start,
+        end,
+        rms_level,
+        peak_level,
+        noise_floor: rms_level.clamp(NOISE_FLOOR_RANGE.0, NOISE_FLOOR_RANGE.1),
+    })
+}
+
+fn preview_dir() -> Result<PathBuf, String> {
+    let dir = std::env::temp_dir().join("yeyo-noise-preview");
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create preview directory: {}", e))?;
+
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let stale = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age > PREVIEW_MAX_AGE);
+            if stale {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+    Ok(dir)
+}
+
+// Renders a short excerpt before and after noise reduction, plus both back to back, as WAV files
+#[tauri::command]
+pub async fn preview_noise_reduction(
+    mut settings: NoiseReductionSettings,
+    start: f64,
+    length: Option<f64>,
+    job_id: Option<String>,
+    app: AppHandle,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+) -> Result<NoisePreview, String> {
+    resolve_model(&app, &mut settings)?;
+    let job_id = job_id.unwrap_or_else(crate::new_job_id);
+    let _job = jobs.reserve(&job_id)?;
+    let length = length.unwrap_or(DEFAULT_PREVIEW_SECONDS).clamp(1.0, MAX_PREVIEW_SECONDS);
+    let start = start.max(0.0);
+    let dir = preview_dir()?;
+    let original = dir.join(format!("{}_original.wav", job_id));
+    let denoised = dir.join(format!("{}_denoised.wav", job_id));
+    let ab = dir.join(format!("{}_ab.wav", job_id));
+
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-y", "-ss", &format!("{:.3}", start), "-t", &format!("{:.3}", length)]);
+    cmd.args(["-i", &settings.input_path, "-map", "0:a:0", "-c:a", "pcm_s16le"]);
+    cmd.arg(&original);
+    ffmpeg_progress::run_pass(&window, &jobs, &job_id, &mut cmd, vec![original.clone()], Some(length), Pass::nth(0, 3))?;
+
+    let mut cmd = ffmpeg_progress::command();
+    cmd.arg("-y");
+    cmd.args(denoise_args(&settings, Some((start, length)), false)?);
+    cmd.args(["-c:a", "pcm_s16le"]);
+    cmd.arg(&denoised);
+    ffmpeg_progress::run_pass(&window, &jobs, &job_id, &mut cmd, vec![denoised.clone()], Some(length), Pass::nth(1, 3))?;
+
+    let mut cmd = ffmpeg_progress::command();
+    cmd.arg("-y").arg("-i").arg(&original).arg("-i").arg(&denoised);
+    cmd.args([
+        "-filter_complex",
+        &format!(
+            "[0:a]apad=pad_dur={}[a];[1:a]aresample=async=1[b];[a][b]concat=n=2:v=0:a=1[out]",
+            AB_GAP_SECONDS
+        ),
+        "-map",
+        "[out]",
+        "-c:a",
+        "pcm_s16le",
+    ]);
+    cmd.arg(&ab);
+    let ab_length = length * 2.0 + AB_GAP_SECONDS;
+    ffmpeg_progress::run_pass(&window, &jobs, &job_id, &mut cmd, vec![ab.clone()], Some(ab_length), Pass::nth(2, 3))?;
+
+    Ok(NoisePreview {
+        original: original.to_string_lossy().to_string(),
+        denoised: denoised.to_string_lossy().to_string(),
+        ab: ab.to_string_lossy().to_string(),
+        start,
+        length,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(algorithm: &str) -> NoiseReductionSettings {
+        NoiseReductionSettings {
+            input_path: "/in.wav".to_string(),
+            output_dir: "/out".to_string(),
+            preset: String::new(),
+            algorithm: algorithm.to_string(),
+            noise_reduction: 12.0,
+            noise_floor: -50.0,
+            highpass_freq: Some(100.0),
+            lowpass_freq: None,
+            notch_freq: None,
+            noise_profile: None,
+            model: Some("/models/cb.rnnn".to_string()),
+        }
+    }
+
+    #[test]
+    fn model_is_needed_exactly_when_the_chain_runs_arnndn() {
+        let algorithms = [
+            "afftdn",
+            "anlmdn",
+            "highpass",
+            "combined",
+            "afftdn+highpass+lowpass",
+            "arnndn",
+            "speech",
+            "highpass+notch",
+            "arnndn+highpass",
+            "unknown",
+        ];
+        for algorithm in algorithms {
+            let runs_arnndn = filter_chain(&settings(algorithm)).iter().any(|filter| filter.starts_with("arnndn"));
+            assert_eq!(uses_model(algorithm), runs_arnndn, "{}", algorithm);
+        }
+    }
+
+    #[test]
+    fn model_paths_are_escaped_for_the_filtergraph() {
+        assert_eq!(filter_path(r"C:\models\cb.rnnn"), r"C\\:/models/cb.rnnn");
+        assert_eq!(filter_path("/m/it's.rnnn"), r"/m/it\\\'s.rnnn");
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn denoise_args_without_a_profile() {
+        let args = denoise_args(&settings("afftdn"), None, false).unwrap();
+        assert_eq!(args, strings(&["-i", "/in.wav", "-af", "afftdn=nr=12:nf=-50", "-vn"]));
+
+        // An excerpt seeks the input; video is only dropped when it isn't kept
+        let args = denoise_args(&settings("afftdn"), Some((5.0, 10.0)), true).unwrap();
+        assert_eq!(args, strings(&["-ss", "5.000", "-t", "10.000", "-i", "/in.wav", "-af", "afftdn=nr=12:nf=-50"]));
+    }
+
+    #[test]
+    fn denoise_args_with_a_profile_sample_the_noise_segment_first() {
+        let mut settings = settings("afftdn");
+        settings.noise_profile = Some(NoiseProfile {
+            start: 1.0,
+            end: 3.5,
+            rms_level: -62.0,
+            peak_level: -48.0,
+            noise_floor: -62.0,
+        });
+
+        let args = denoise_args(&settings, Some((20.0, 10.0)), true).unwrap();
+        assert_eq!(
+            args[..12],
+            strings(&["-ss", "20.000", "-t", "10.000", "-i", "/in.wav", "-ss", "1.000", "-t", "2.500", "-i", "/in.wav"])
+        );
+        let graph = &args[13];
+        assert_eq!(args[12], "-filter_complex");
+        assert!(graph.starts_with("[1:a:0]asetpts=PTS-STARTPTS[noise];[0:a:0]asetpts=PTS-STARTPTS[main];"));
+        assert!(graph.contains(
+            "asendcmd=c='0 afftdn@profile sample_noise start;2.500 afftdn@profile sample_noise stop',afftdn@profile=nr=12:nf=-62,"
+        ));
+        // The noise lead-in is cut off again
+        assert!(graph.ends_with("atrim=start=2.500,asetpts=PTS-STARTPTS[out]"));
+        assert_eq!(args[14..], strings(&["-map", "0:v?", "-c:v", "copy", "-map", "[out]"]));
+
+        assert!(!denoise_args(&settings, None, false).unwrap().contains(&"0:v?".to_string()));
+    }
+
+    #[test]
+    fn noise_profile_needs_an_afftdn_algorithm() {
+        for algorithm in ["anlmdn", "arnndn", "highpass"] {
+            let mut settings = settings(algorithm);
+            settings.noise_profile = Some(NoiseProfile {
+                start: 0.0,
+                end: 1.0,
+                rms_level: -60.0,
+                peak_level: -50.0,
+                noise_floor: -60.0,
+            });
+            let error = denoise_args(&settings, None, false).unwrap_err();
+            assert!(error.contains(algorithm), "{}", error);
+        }
+    }
+
+    const ASTATS: &str = "\
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] Channel: 1
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] DC offset: -0.000004
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] Peak level dB: -41.273910
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] RMS level dB: -58.905213
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] RMS peak dB: -55.120871
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] Channel: 2
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] Peak level dB: -inf
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] RMS level dB: -inf
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] Overall
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] DC offset: -0.000002
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] Peak level dB: -41.273910
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] RMS level dB: -61.915513
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] RMS peak dB: -58.130171
+[Parsed_astats_0 @ 0x55d0c1a2b3c0] Number of samples: 96000
+[out#0/null @ 0x55d0c1a2c000] video:0KiB audio:375KiB subtitle:0KiB other streams:0KiB global headers:0KiB muxing overhead: unknown
+size=N/A time=00:00:02.00 bitrate=N/A speed= 612x";
+
+    #[test]
+    fn astats_values_come_from_the_overall_section() {
+        assert_eq!(astats_value(ASTATS, "RMS level dB:"), Some(-61.915513));
+        assert_eq!(astats_value(ASTATS, "Peak level dB:"), Some(-41.27391));
+        assert_eq!(astats_value(ASTATS, "Crest factor:"), None);
+
+        // Digital silence
+        let silent = ASTATS.replace("RMS level dB: -61.915513", "RMS level dB: -inf");
+        assert_eq!(astats_value(&silent, "RMS level dB:"), None);
+    }
+}
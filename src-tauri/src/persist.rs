@@ -0,0 +1,102 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+
+// Reads a JSON state file; a missing file gives the default. A file that doesn't parse is renamed
+// to <name>.bak so the next save can't overwrite it. Fails when the file can't be read or moved
+// aside, in which case the caller must not save over it.
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    let json = match fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => {
+            return Err(format!(
+                "Failed to read {}: {}; not saving until it is readable",
+                path.display(),
+                e
+            ))
+        }
+    };
+
+    match serde_json::from_str(&json) {
+        Ok(value) => Ok(value),
+        Err(e) => {
+            let backup = backup_path(path);
+            match fs::rename(path, &backup) {
+                Ok(_) => {
+                    eprintln!("Failed to parse {}: {}; moved it to {}", path.display(), e, backup.display());
+                    Ok(T::default())
+                }
+                Err(rename_error) => Err(format!(
+                    "Failed to parse {}: {}; not saving until it is fixed or removed ({})",
+                    path.display(),
+                    e,
+                    rename_error
+                )),
+            }
+        }
+    }
+}
+
+// Writes to a temporary file next to `path` and renames it over `path`, so a crash mid-write
+// leaves either the old or the new contents, never a truncated file
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let temp = temp_path(path);
+    let written = fs::File::create(&temp)
+        .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temp, path));
+    written.map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        format!("Failed to save {}: {}", path.display(), e)
+    })
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn unparsable_file_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, "{ not json").unwrap();
+
+        let loaded: HashMap<String, u32> = load_json(&path).unwrap();
+        assert!(loaded.is_empty());
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(dir.path().join("state.json.bak")).unwrap(), "{ not json");
+    }
+
+    #[test]
+    fn missing_file_gives_the_default_and_saves_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("state.json");
+        let loaded: HashMap<String, u32> = load_json(&path).unwrap();
+        assert!(loaded.is_empty());
+
+        let value = HashMap::from([("a".to_string(), 1)]);
+        let json = serde_json::to_vec(&value).unwrap();
+        write_atomic(&path, &json).unwrap();
+        write_atomic(&path, &json).unwrap();
+        assert_eq!(load_json::<HashMap<String, u32>>(&path).unwrap(), value);
+        assert!(!dir.path().join("nested").join("state.json.tmp").exists());
+    }
+}
@@ -0,0 +1,497 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::persist;
+use crate::{AudioConversionSettings, ConversionSettings};
+
+const PRESETS_FILE: &str = "presets.json";
+
+// A saved set of conversion settings. Exactly one of the settings is present.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Preset {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub output_format: String,
+    pub video_settings: Option<ConversionSettings>,
+    pub audio_settings: Option<AudioConversionSettings>,
+    // Shipped with the app and read-only; ignored when importing
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+// Layout of the presets file and of exports; TOML needs a table at the top
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PresetFile {
+    #[serde(default)]
+    presets: Vec<Preset>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: Vec<String>,
+    // Names that already existed and were left alone
+    pub skipped: Vec<String>,
+}
+
+// Settings and output format of a conversion, from inline settings or a preset
+pub enum ResolvedSettings {
+    Video(ConversionSettings, String),
+    Audio(AudioConversionSettings, String),
+}
+
+fn builtin_presets() -> Vec<Preset> {
+    vec![
+        Preset {
+            name: "Discord 1080p".to_string(),
+            description: "H.264/AAC MP4 at up to 1080p that plays inline in Discord".to_string(),
+            output_format: "mp4".to_string(),
+            video_settings: Some(ConversionSettings {
+                video_codec: "libx264".to_string(),
+                audio_codec: "aac".to_string(),
+                crf: Some(23),
+                preset: Some("medium".to_string()),
+                bitrate: None,
+                fast_mode: None,
+                target_size_mb: None,
+                max_height: Some(1080),
+            }),
+            audio_settings: None,
+            builtin: true,
+        },
+        Preset {
+            name: "Podcast MP3 128k".to_string(),
+            description: "128 kbit/s 44.1 kHz MP3, the format podcast hosts expect".to_string(),
+            output_format: "mp3".to_string(),
+            video_settings: None,
+            audio_settings: Some(AudioConversionSettings {
+                codec: "libmp3lame".to_string(),
+                bitrate: Some("128k".to_string()),
+                sample_rate: Some(44100),
+                channels: None,
+                compression: None,
+                extract_from_video: Some(true),
+            }),
+            builtin: true,
+        },
+        Preset {
+            name: "Archive FLAC".to_string(),
+            description: "Lossless FLAC at the highest compression level".to_string(),
+            output_format: "flac".to_string(),
+            video_settings: None,
+            audio_settings: Some(AudioConversionSettings {
+                codec: "flac".to_string(),
+                bitrate: None,
+                sample_rate: None,
+                channels: None,
+                compression: Some(8),
+                extract_from_video: Some(true),
+            }),
+            builtin: true,
+        },
+    ]
+}
+
+fn validate(preset: &Preset) -> Result<(), String> {
+    if preset.name.trim().is_empty() {
+        return Err("A preset needs a name".to_string());
+    }
+    if preset.output_format.trim().is_empty() {
+        return Err(format!("Preset {} has no output format", preset.name));
+    }
+    if preset.video_settings.is_some() == preset.audio_settings.is_some() {
+        return Err(format!("Preset {} needs either video or audio settings", preset.name));
+    }
+    Ok(())
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml"))
+}
+
+// User presets, persisted in the app config dir. Built-in presets are added on top and can't be
+// changed; names are unique regardless of case.
+pub struct PresetStore {
+    presets: Mutex<Vec<Preset>>,
+    path: Option<PathBuf>,
+    // Set when an unreadable presets file couldn't be moved aside; saving would overwrite it
+    load_error: Option<String>,
+}
+
+impl PresetStore {
+    // A presets file that doesn't parse is renamed to presets.json.bak, so the next save can't
+    // overwrite the user's presets
+    pub fn load(app: &AppHandle) -> Self {
+        let path = app.path_resolver().app_config_dir().map(|dir| dir.join(PRESETS_FILE));
+
+        let (file, load_error) = match path.as_ref().map(|path| persist::load_json::<PresetFile>(path)) {
+            Some(Ok(file)) => (file, None),
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                (PresetFile::default(), Some(e))
+            }
+            None => (PresetFile::default(), None),
+        };
+
+        PresetStore {
+            presets: Mutex::new(file.presets),
+            path,
+            load_error,
+        }
+    }
+
+    fn save(&self, presets: &[Preset]) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(error) = &self.load_error {
+            return Err(error.clone());
+        }
+        let file = PresetFile { presets: presets.to_vec() };
+        let json = serde_json::to_string_pretty(&file).map_err(|e| format!("Failed to serialize presets: {}", e))?;
+        persist::write_atomic(path, json.as_bytes())
+    }
+
+    // Applies a change to a copy of the user presets and swaps it in once it is saved
+    fn modify<T>(&self, change: impl FnOnce(&mut Vec<Preset>) -> Result<T, String>) -> Result<T, String> {
+        let mut presets = self.presets.lock().unwrap();
+        let mut next = presets.clone();
+        let result = change(&mut next)?;
+        self.save(&next)?;
+        *presets = next;
+        Ok(result)
+    }
+
+    fn is_builtin(name: &str) -> bool {
+        builtin_presets().iter().any(|preset| preset.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn list(&self) -> Vec<Preset> {
+        let mut presets = builtin_presets();
+        presets.extend(self.presets.lock().unwrap().iter().cloned());
+        presets
+    }
+
+    pub fn get(&self, name: &str) -> Result<Preset, String> {
+        self.list()
+            .into_iter()
+            .find(|preset| preset.name.eq_ignore_ascii_case(name))
+            .ok_or(format!("No preset named {}", name))
+    }
+
+    pub fn create(&self, mut preset: Preset) -> Result<Preset, String> {
+        validate(&preset)?;
+        preset.builtin = false;
+        self.modify(|presets| {
+            if Self::is_builtin(&preset.name) || presets.iter().any(|p| p.name.eq_ignore_ascii_case(&preset.name)) {
+                return Err(format!("A preset named {} already exists", preset.name));
+            }
+            presets.push(preset.clone());
+            Ok(())
+        })?;
+        Ok(preset)
+    }
+
+    // Replaces preset `name`; `preset.name` may differ to rename it
+    pub fn update(&self, name: &str, mut preset: Preset) -> Result<Preset, String> {
+        validate(&preset)?;
+        if Self::is_builtin(name) {
+            return Err(format!("{} is a built-in preset and can't be changed", name));
+        }
+        preset.builtin = false;
+        self.modify(|presets| {
+            let index = presets
+                .iter()
+                .position(|p| p.name.eq_ignore_ascii_case(name))
+                .ok_or(format!("No preset named {}", name))?;
+            let clash = Self::is_builtin(&preset.name)
+                || presets
+                    .iter()
+                    .enumerate()
+                    .any(|(i, p)| i != index && p.name.eq_ignore_ascii_case(&preset.name));
+            if clash {
+                return Err(format!("A preset named {} already exists", preset.name));
+            }
+            presets[index] = preset.clone();
+            Ok(())
+        })?;
+        Ok(preset)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), String> {
+        if Self::is_builtin(name) {
+            return Err(format!("{} is a built-in preset and can't be deleted", name));
+        }
+        self.modify(|presets| {
+            let before = presets.len();
+            presets.retain(|p| !p.name.eq_ignore_ascii_case(name));
+            if presets.len() == before {
+                return Err(format!("No preset named {}", name));
+            }
+            Ok(())
+        })
+    }
+
+    // Reads presets from a JSON or TOML file (by extension). Existing names are skipped unless
+    // `overwrite` is set; built-in names are always skipped.
+    pub fn import(&self, path: &Path, overwrite: bool) -> Result<ImportReport, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let file: PresetFile = if is_toml(path) {
+            toml::from_str(&text).map_err(|e| format!("Invalid presets file: {}", e))?
+        } else {
+            serde_json::from_str(&text).map_err(|e| format!("Invalid presets file: {}", e))?
+        };
+        for preset in &file.presets {
+            validate(preset)?;
+        }
+
+        self.modify(|presets| {
+            let mut report = ImportReport { imported: Vec::new(), skipped: Vec::new() };
+            for mut preset in file.presets {
+                let existing = presets.iter().position(|p| p.name.eq_ignore_ascii_case(&preset.name));
+                if Self::is_builtin(&preset.name) || (existing.is_some() && !overwrite) {
+                    report.skipped.push(preset.name);
+                    continue;
+                }
+                preset.builtin = false;
+                report.imported.push(preset.name.clone());
+                match existing {
+                    Some(index) => presets[index] = preset,
+                    None => presets.push(preset),
+                }
+            }
+            Ok(report)
+        })
+    }
+
+    // Writes the named presets, or all user presets, to a JSON or TOML file (by extension)
+    pub fn export(&self, path: &Path, names: Option<Vec<String>>) -> Result<usize, String> {
+        let presets: Vec<Preset> = match names {
+            Some(names) => names.iter().map(|name| self.get(name)).collect::<Result<_, _>>()?,
+            None => self.presets.lock().unwrap().clone(),
+        };
+        let count = presets.len();
+        let file = PresetFile { presets };
+        let text = if is_toml(path) {
+            toml::to_string_pretty(&file).map_err(|e| format!("Failed to serialize presets: {}", e))?
+        } else {
+            serde_json::to_string_pretty(&file).map_err(|e| format!("Failed to serialize presets: {}", e))?
+        };
+        fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(count)
+    }
+
+    // Settings and output format for a video conversion, from inline settings or a preset name
+    pub fn video(
+        &self,
+        settings: Option<ConversionSettings>,
+        preset: Option<&str>,
+        output_format: Option<String>,
+    ) -> Result<(ConversionSettings, String), String> {
+        match (settings, preset) {
+            (Some(settings), None) => Ok((settings, output_format.ok_or("No output format given")?)),
+            (None, Some(name)) => {
+                let preset = self.get(name)?;
+                let settings = preset.video_settings.ok_or(format!("{} is not a video preset", preset.name))?;
+                Ok((settings, output_format.unwrap_or(preset.output_format)))
+            }
+            (Some(_), Some(_)) => Err("Give either settings or a preset name, not both".to_string()),
+            (None, None) => Err("No conversion settings or preset given".to_string()),
+        }
+    }
+
+    // Settings and output format for an audio conversion, from inline settings or a preset name
+    pub fn audio(
+        &self,
+        settings: Option<AudioConversionSettings>,
+        preset: Option<&str>,
+        output_format: Option<String>,
+    ) -> Result<(AudioConversionSettings, String), String> {
+        match (settings, preset) {
+            (Some(settings), None) => Ok((settings, output_format.ok_or("No output format given")?)),
+            (None, Some(name)) => {
+                let preset = self.get(name)?;
+                let settings = preset.audio_settings.ok_or(format!("{} is not an audio preset", preset.name))?;
+                Ok((settings, output_format.unwrap_or(preset.output_format)))
+            }
+            (Some(_), Some(_)) => Err("Give either settings or a preset name, not both".to_string()),
+            (None, None) => Err("No conversion settings or preset given".to_string()),
+        }
+    }
+
+    // Either kind of settings: inline video settings, inline audio settings or a preset name
+    pub fn resolve(
+        &self,
+        video_settings: Option<ConversionSettings>,
+        audio_settings: Option<AudioConversionSettings>,
+        preset: Option<&str>,
+        output_format: Option<String>,
+    ) -> Result<ResolvedSettings, String> {
+        if video_settings.is_some() && audio_settings.is_some() {
+            return Err("Give either video or audio settings, not both".to_string());
+        }
+        let audio = match preset {
+            Some(name) => self.get(name)?.audio_settings.is_some(),
+            None => audio_settings.is_some(),
+        };
+        if audio {
+            let (settings, format) = self.audio(audio_settings, preset, output_format)?;
+            Ok(ResolvedSettings::Audio(settings, format))
+        } else {
+            let (settings, format) = self.video(video_settings, preset, output_format)?;
+            Ok(ResolvedSettings::Video(settings, format))
+        }
+    }
+}
+
+#[tauri::command]
+pub fn list_presets(presets: State<'_, PresetStore>) -> Vec<Preset> {
+    presets.list()
+}
+
+#[tauri::command]
+pub fn create_preset(preset: Preset, presets: State<'_, PresetStore>) -> Result<Preset, String> {
+    presets.create(preset)
+}
+
+#[tauri::command]
+pub fn update_preset(name: String, preset: Preset, presets: State<'_, PresetStore>) -> Result<Preset, String> {
+    presets.update(&name, preset)
+}
+
+#[tauri::command]
+pub fn delete_preset(name: String, presets: State<'_, PresetStore>) -> Result<(), String> {
+    presets.delete(&name)
+}
+
+#[tauri::command]
+pub fn import_presets(
+    path: String,
+    overwrite: Option<bool>,
+    presets: State<'_, PresetStore>,
+) -> Result<ImportReport, String> {
+    presets.import(Path::new(&path), overwrite.unwrap_or(false))
+}
+
+// Returns the number of presets written
+#[tauri::command]
+pub fn export_presets(
+    path: String,
+    names: Option<Vec<String>>,
+    presets: State<'_, PresetStore>,
+) -> Result<usize, String> {
+    presets.export(Path::new(&path), names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> PresetStore {
+        PresetStore {
+            presets: Mutex::new(Vec::new()),
+            path: None,
+            load_error: None,
+        }
+    }
+
+    // A user preset based on the built-in video preset
+    fn preset(name: &str) -> Preset {
+        Preset {
+            name: name.to_string(),
+            builtin: false,
+            ..builtin_presets().remove(0)
+        }
+    }
+
+    fn user_names(store: &PresetStore) -> Vec<String> {
+        store.list().into_iter().filter(|p| !p.builtin).map(|p| p.name).collect()
+    }
+
+    #[test]
+    fn names_clash_regardless_of_case_and_with_built_ins() {
+        let store = store();
+        store.create(preset("Web")).unwrap();
+        assert!(store.create(preset("WEB")).is_err());
+        assert!(store.create(preset("discord 1080P")).is_err());
+        assert!(store.update("Web", preset("archive flac")).is_err());
+        assert!(store.delete("Discord 1080p").is_err());
+        assert_eq!(user_names(&store), ["Web"]);
+    }
+
+    #[test]
+    fn update_can_rename() {
+        let store = store();
+        store.create(preset("Web")).unwrap();
+        store.create(preset("Phone")).unwrap();
+
+        store.update("web", preset("Web small")).unwrap();
+        assert_eq!(user_names(&store), ["Web small", "Phone"]);
+        // Changing only the case of its own name is not a clash
+        store.update("phone", preset("PHONE")).unwrap();
+        assert!(store.update("Web small", preset("phone")).is_err());
+        assert!(store.update("Missing", preset("Other")).is_err());
+        assert_eq!(user_names(&store), ["Web small", "PHONE"]);
+    }
+
+    #[test]
+    fn import_skips_existing_names_unless_overwriting() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("presets.json");
+        let mut changed = preset("web");
+        changed.description = "imported".to_string();
+        let exported = PresetFile { presets: vec![changed, preset("New"), preset("Archive FLAC")] };
+        fs::write(&file, serde_json::to_string(&exported).unwrap()).unwrap();
+
+        let store = store();
+        store.create(preset("Web")).unwrap();
+        let report = store.import(&file, false).unwrap();
+        assert_eq!(report.imported, ["New"]);
+        assert_eq!(report.skipped, ["web", "Archive FLAC"]);
+        assert_eq!(store.get("Web").unwrap().description, builtin_presets()[0].description);
+
+        let report = store.import(&file, true).unwrap();
+        assert_eq!(report.imported, ["web", "New"]);
+        assert_eq!(report.skipped, ["Archive FLAC"]);
+        assert_eq!(store.get("Web").unwrap().description, "imported");
+        assert_eq!(user_names(&store), ["web", "New"]);
+    }
+
+    #[test]
+    fn failed_save_leaves_the_presets_unchanged() {
+        let mut store = store();
+        store.create(preset("Web")).unwrap();
+        store.path = Some(PathBuf::from("/presets.json"));
+        store.load_error = Some("presets.json is broken".to_string());
+
+        assert!(store.create(preset("New")).is_err());
+        assert!(store.update("Web", preset("Renamed")).is_err());
+        assert!(store.delete("Web").is_err());
+        assert_eq!(user_names(&store), ["Web"]);
+    }
+
+    #[test]
+    fn resolve_picks_the_kind_of_the_preset() {
+        let store = store();
+        match store.resolve(None, None, Some("podcast mp3 128k"), None).unwrap() {
+            ResolvedSettings::Audio(settings, format) => {
+                assert_eq!(settings.codec, "libmp3lame");
+                assert_eq!(format, "mp3");
+            }
+            ResolvedSettings::Video(..) => panic!("expected audio settings"),
+        }
+        match store.resolve(None, None, Some("Discord 1080p"), Some("mkv".to_string())).unwrap() {
+            ResolvedSettings::Video(settings, format) => {
+                assert_eq!(settings.max_height, Some(1080));
+                assert_eq!(format, "mkv");
+            }
+            ResolvedSettings::Audio(..) => panic!("expected video settings"),
+        }
+        assert!(store.resolve(None, None, Some("Missing"), None).is_err());
+        assert!(store.resolve(None, None, None, None).is_err());
+    }
+}
@@ -0,0 +1,657 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::create_hidden_command;
+
+// Everything ffprobe reports about a file, with the string-encoded numbers already parsed
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaProbe {
+    pub path: String,
+    pub format: FormatInfo,
+    pub streams: Vec<StreamInfo>,
+    pub chapters: Vec<ChapterInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatInfo {
+    pub format_name: String,
+    pub format_long_name: Option<String>,
+    pub duration: Option<f64>,
+    pub start_time: Option<f64>,
+    pub size: Option<u64>,
+    pub bit_rate: Option<u64>,
+    pub probe_score: Option<u32>,
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Attachment,
+    Data,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Disposition {
+    pub default: bool,
+    pub forced: bool,
+    pub dub: bool,
+    pub original: bool,
+    pub comment: bool,
+    pub lyrics: bool,
+    pub karaoke: bool,
+    pub hearing_impaired: bool,
+    pub visual_impaired: bool,
+    pub captions: bool,
+    pub descriptions: bool,
+    // Embedded cover art, reported by ffprobe as a video stream
+    pub attached_pic: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamInfo {
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec_name: Option<String>,
+    pub codec_long_name: Option<String>,
+    pub profile: Option<String>,
+    pub codec_tag: Option<String>,
+    pub bit_rate: Option<u64>,
+    pub duration: Option<f64>,
+    pub start_time: Option<f64>,
+    pub frame_count: Option<u64>,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub disposition: Disposition,
+    pub tags: HashMap<String, String>,
+    pub video: Option<VideoStreamInfo>,
+    pub audio: Option<AudioStreamInfo>,
+    pub attachment: Option<AttachmentInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoStreamInfo {
+    pub width: u32,
+    pub height: u32,
+    pub coded_width: Option<u32>,
+    pub coded_height: Option<u32>,
+    pub pixel_format: Option<String>,
+    pub bits_per_raw_sample: Option<u32>,
+    pub frame_rate: Option<f64>,
+    pub avg_frame_rate: Option<f64>,
+    pub sample_aspect_ratio: Option<String>,
+    pub display_aspect_ratio: Option<String>,
+    pub field_order: Option<String>,
+    pub level: Option<i32>,
+    pub rotation: Option<f64>,
+    pub color: ColorInfo,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColorInfo {
+    pub range: Option<String>,
+    pub space: Option<String>,
+    pub transfer: Option<String>,
+    pub primaries: Option<String>,
+    pub chroma_location: Option<String>,
+    // "HDR10", "HLG" or "Dolby Vision", derived from the transfer function and side data
+    pub hdr_format: Option<String>,
+    pub mastering_display: Option<MasteringDisplay>,
+    pub content_light_level: Option<ContentLightLevel>,
+    pub dolby_vision_profile: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MasteringDisplay {
+    pub min_luminance: Option<f64>,
+    pub max_luminance: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentLightLevel {
+    pub max_content: Option<u32>,
+    pub max_average: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioStreamInfo {
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    pub sample_format: Option<String>,
+    pub bits_per_sample: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentInfo {
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChapterInfo {
+    pub id: i64,
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+    pub tags: HashMap<String, String>,
+}
+
+impl MediaProbe {
+    // Duration of the container, falling back to the longest stream for formats that don't report one
+    pub fn duration(&self) -> Option<f64> {
+        self.format.duration.or_else(|| {
+            self.streams
+                .iter()
+                .filter_map(|stream| stream.duration)
+                .fold(None, |longest: Option<f64>, d| Some(longest.map_or(d, |l| l.max(d))))
+        })
+    }
+
+    // The stream a player would show: cover art is skipped and the default stream wins
+    pub fn primary_video(&self) -> Option<&StreamInfo> {
+        let mut videos = self
+            .streams
+            .iter()
+            .filter(|stream| stream.kind == StreamKind::Video && !stream.disposition.attached_pic);
+        let first = videos.clone().next();
+        videos.find(|stream| stream.disposition.default).or(first)
+    }
+
+    pub fn primary_audio(&self) -> Option<&StreamInfo> {
+        let mut audios = self.streams.iter().filter(|stream| stream.kind == StreamKind::Audio);
+        let first = audios.clone().next();
+        audios.find(|stream| stream.disposition.default).or(first)
+    }
+}
+
+// Raw ffprobe JSON. Most numbers come back as strings, so everything is read loosely here
+// and converted into the typed model above.
+#[derive(Deserialize)]
+struct RawProbe {
+    #[serde(default)]
+    format: Option<RawFormat>,
+    #[serde(default)]
+    streams: Vec<RawStream>,
+    #[serde(default)]
+    chapters: Vec<RawChapter>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawFormat {
+    format_name: Option<String>,
+    format_long_name: Option<String>,
+    duration: Option<String>,
+    start_time: Option<String>,
+    size: Option<String>,
+    bit_rate: Option<String>,
+    probe_score: Option<u32>,
+    tags: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawStream {
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    codec_long_name: Option<String>,
+    profile: Option<serde_json::Value>,
+    codec_tag_string: Option<String>,
+    bit_rate: Option<String>,
+    duration: Option<String>,
+    start_time: Option<String>,
+    nb_frames: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    coded_width: Option<u32>,
+    coded_height: Option<u32>,
+    pix_fmt: Option<String>,
+    bits_per_raw_sample: Option<String>,
+    r_frame_rate: Option<String>,
+    avg_frame_rate: Option<String>,
+    sample_aspect_ratio: Option<String>,
+    display_aspect_ratio: Option<String>,
+    field_order: Option<String>,
+    level: Option<i32>,
+    color_range: Option<String>,
+    color_space: Option<String>,
+    color_transfer: Option<String>,
+    color_primaries: Option<String>,
+    chroma_location: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<u32>,
+    channel_layout: Option<String>,
+    sample_fmt: Option<String>,
+    bits_per_sample: Option<u32>,
+    disposition: HashMap<String, i64>,
+    tags: HashMap<String, serde_json::Value>,
+    side_data_list: Vec<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawChapter {
+    id: i64,
+    start_time: Option<String>,
+    end_time: Option<String>,
+    tags: HashMap<String, serde_json::Value>,
+}
+
+fn parse<T: std::str::FromStr>(value: &Option<String>) -> Option<T> {
+    value.as_deref().and_then(|v| v.trim().parse::<T>().ok())
+}
+
+// Parses ffprobe rationals such as "30000/1001"; "0/0" means unknown
+pub fn parse_rational(value: &str) -> Option<f64> {
+    match value.split_once('/') {
+        Some((num, den)) => {
+            let num: f64 = num.trim().parse().ok()?;
+            let den: f64 = den.trim().parse().ok()?;
+            if den == 0.0 || num == 0.0 {
+                None
+            } else {
+                Some(num / den)
+            }
+        }
+        None => value.trim().parse().ok().filter(|v: &f64| *v > 0.0),
+    }
+}
+
+fn json_number(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => parse_rational(s),
+        _ => None,
+    }
+}
+
+// ffprobe tag keys differ in case between containers ("title" in mkv, "TITLE" in some flac files)
+fn lower_tags(tags: HashMap<String, serde_json::Value>) -> HashMap<String, String> {
+    tags.into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            (key.to_lowercase(), value)
+        })
+        .collect()
+}
+
+fn convert_stream(raw: RawStream) -> StreamInfo {
+    let kind = match raw.codec_type.as_deref() {
+        Some("video") => StreamKind::Video,
+        Some("audio") => StreamKind::Audio,
+        Some("subtitle") => StreamKind::Subtitle,
+        Some("attachment") => StreamKind::Attachment,
+        Some("data") => StreamKind::Data,
+        _ => StreamKind::Unknown,
+    };
+
+    let flag = |name: &str| raw.disposition.get(name).copied().unwrap_or(0) != 0;
+    let disposition = Disposition {
+        default: flag("default"),
+        forced: flag("forced"),
+        dub: flag("dub"),
+        original: flag("original"),
+        comment: flag("comment"),
+        lyrics: flag("lyrics"),
+        karaoke: flag("karaoke"),
+        hearing_impaired: flag("hearing_impaired"),
+        visual_impaired: flag("visual_impaired"),
+        captions: flag("captions"),
+        descriptions: flag("descriptions"),
+        attached_pic: flag("attached_pic"),
+    };
+
+    let tags = lower_tags(raw.tags);
+    let side_data = |kind: &str| {
+        raw.side_data_list
+            .iter()
+            .find(|entry| entry.get("side_data_type").and_then(|t| t.as_str()) == Some(kind))
+    };
+
+    let video = if kind == StreamKind::Video {
+        let mastering_display = side_data("Mastering display metadata").map(|entry| MasteringDisplay {
+            min_luminance: entry.get("min_luminance").and_then(json_number),
+            max_luminance: entry.get("max_luminance").and_then(json_number),
+        });
+        let content_light_level = side_data("Content light level metadata").map(|entry| ContentLightLevel {
+            max_content: entry.get("max_content").and_then(|v| v.as_u64()).map(|v| v as u32),
+            max_average: entry.get("max_average").and_then(|v| v.as_u64()).map(|v| v as u32),
+        });
+        let dolby_vision_profile = side_data("DOVI configuration record")
+            .and_then(|entry| entry.get("dv_profile"))
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32);
+        let rotation = side_data("Display Matrix")
+            .and_then(|entry| entry.get("rotation"))
+            .and_then(json_number)
+            .or_else(|| tags.get("rotate").and_then(|r| r.parse().ok()));
+
+        let hdr_format = if dolby_vision_profile.is_some() {
+            Some("Dolby Vision".to_string())
+        } else {
+            match raw.color_transfer.as_deref() {
+                Some("smpte2084") => Some("HDR10".to_string()),
+                Some("arib-std-b67") => Some("HLG".to_string()),
+                _ => None,
+            }
+        };
+
+        Some(VideoStreamInfo {
+            width: raw.width.unwrap_or(0),
+            height: raw.height.unwrap_or(0),
+            coded_width: raw.coded_width,
+            coded_height: raw.coded_height,
+            pixel_format: raw.pix_fmt,
+            bits_per_raw_sample: parse(&raw.bits_per_raw_sample),
+            frame_rate: raw.r_frame_rate.as_deref().and_then(parse_rational),
+            avg_frame_rate: raw.avg_frame_rate.as_deref().and_then(parse_rational),
+            sample_aspect_ratio: raw.sample_aspect_ratio,
+            display_aspect_ratio: raw.display_aspect_ratio,
+            field_order: raw.field_order,
+            level: raw.level,
+            rotation,
+            color: ColorInfo {
+                range: raw.color_range,
+                space: raw.color_space,
+                transfer: raw.color_transfer,
+                primaries: raw.color_primaries,
+                chroma_location: raw.chroma_location,
+                hdr_format,
+                mastering_display,
+                content_light_level,
+                dolby_vision_profile,
+            },
+        })
+    } else {
+        None
+    };
+
+    let audio = if kind == StreamKind::Audio {
+        Some(AudioStreamInfo {
+            sample_rate: parse(&raw.sample_rate),
+            channels: raw.channels,
+            channel_layout: raw.channel_layout,
+            sample_format: raw.sample_fmt,
+            bits_per_sample: raw.bits_per_sample.filter(|bits| *bits > 0),
+        })
+    } else {
+        None
+    };
+
+    let attachment = if kind == StreamKind::Attachment {
+        Some(AttachmentInfo {
+            filename: tags.get("filename").cloned(),
+            mime_type: tags.get("mimetype").cloned(),
+        })
+    } else {
+        None
+    };
+
+    // Numeric profiles (e.g. for some audio codecs) are reported as numbers
+    let profile = raw.profile.map(|p| match p {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    });
+
+    StreamInfo {
+        index: raw.index,
+        kind,
+        codec_name: raw.codec_name,
+        codec_long_name: raw.codec_long_name,
+        profile,
+        codec_tag: raw.codec_tag_string.filter(|tag| !tag.starts_with("[0]")),
+        bit_rate: parse(&raw.bit_rate),
+        duration: parse(&raw.duration),
+        start_time: parse(&raw.start_time),
+        frame_count: parse(&raw.nb_frames),
+        language: tags.get("language").cloned().filter(|lang| lang != "und"),
+        title: tags.get("title").cloned(),
+        disposition,
+        tags,
+        video,
+        audio,
+        attachment,
+    }
+}
+
+// Runs ffprobe once and returns the typed model for every stream, chapter and container tag
+pub fn probe(file_path: &str) -> Result<MediaProbe, String> {
+    let output = create_hidden_command("ffprobe")
+        .args([
+            "-v", "quiet",
+            "-print_format", "json",
+            "-show_format",
+            "-show_streams",
+            "-show_chapters",
+            file_path
+        ])
+        .output()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                "FFprobe not found. Please install FFmpeg and add it to your PATH.".to_string()
+            } else {
+                format!("Failed to execute ffprobe: {}", e)
+            }
+        })?;
+
+    if !output.status.success() {
+        return Err("ffprobe failed to analyze the file".to_string());
+    }
+    from_json(file_path, &output.stdout)
+}
+
+// Builds the typed model from ffprobe's JSON output for `file_path`
+pub fn from_json(file_path: &str, json: &[u8]) -> Result<MediaProbe, String> {
+    let raw: RawProbe = serde_json::from_slice(json).map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
+
+    let raw_format = raw.format.unwrap_or_default();
+    let format = FormatInfo {
+        format_name: raw_format.format_name.clone().unwrap_or_default(),
+        format_long_name: raw_format.format_long_name.clone(),
+        duration: parse(&raw_format.duration),
+        start_time: parse(&raw_format.start_time),
+        size: parse(&raw_format.size),
+        bit_rate: parse(&raw_format.bit_rate),
+        probe_score: raw_format.probe_score,
+        tags: lower_tags(raw_format.tags),
+    };
+
+    let chapters = raw
+        .chapters
+        .into_iter()
+        .map(|chapter| {
+            let tags = lower_tags(chapter.tags);
+            ChapterInfo {
+                id: chapter.id,
+                start: parse(&chapter.start_time).unwrap_or(0.0),
+                end: parse(&chapter.end_time).unwrap_or(0.0),
+                title: tags.get("title").cloned(),
+                tags,
+            }
+        })
+        .collect();
+
+    Ok(MediaProbe {
+        path: file_path.to_string(),
+        format,
+        streams: raw.streams.into_iter().map(convert_stream).collect(),
+        chapters,
+    })
+}
+
+// Timestamps (seconds) of the keyframes of one stream, in order. Reads packet flags only,
+// so nothing is decoded.
+pub fn keyframes(file_path: &str, stream_index: u32) -> Result<Vec<f64>, String> {
+    let output = create_hidden_command("ffprobe")
+        .args([
+            "-v", "error",
+            "-select_streams", &stream_index.to_string(),
+            "-show_entries", "packet=pts_time,flags",
+            "-of", "csv=p=0",
+            file_path
+        ])
+        .output()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                "FFprobe not found. Please install FFmpeg and add it to your PATH.".to_string()
+            } else {
+                format!("Failed to execute ffprobe: {}", e)
+            }
+        })?;
+
+    if !output.status.success() {
+        return Err("ffprobe failed to read the keyframes".to_string());
+    }
+
+    let mut keyframes: Vec<f64> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (pts, flags) = line.split_once(',')?;
+            flags.contains('K').then(|| pts.trim().parse().ok()).flatten()
+        })
+        .collect();
+    // Packets come in decode order, which differs from presentation order with B-frames
+    keyframes.sort_by(f64::total_cmp);
+    keyframes.dedup();
+    Ok(keyframes)
+}
+
+#[tauri::command]
+pub async fn probe_media(file_path: String) -> Result<MediaProbe, String> {
+    probe(&file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed ffprobe output for an mkv with cover art, two video streams and two audio tracks
+    const FIXTURE: &str = r#"{
+        "streams": [
+            {
+                "index": 0, "codec_type": "video", "codec_name": "mjpeg", "width": 600, "height": 600,
+                "r_frame_rate": "90000/1", "disposition": { "default": 0, "attached_pic": 1 },
+                "tags": { "filename": "cover.jpg", "mimetype": "image/jpeg" }
+            },
+            {
+                "index": 1, "codec_type": "video", "codec_name": "h264", "profile": "High",
+                "width": 1280, "height": 720, "r_frame_rate": "24000/1001", "avg_frame_rate": "0/0",
+                "bit_rate": "2500000", "nb_frames": "1438", "bits_per_raw_sample": "8",
+                "disposition": { "default": 0 }
+            },
+            {
+                "index": 2, "codec_type": "video", "codec_name": "hevc", "width": 3840, "height": 2160,
+                "color_transfer": "smpte2084", "disposition": { "default": 1 },
+                "side_data_list": [
+                    { "side_data_type": "Display Matrix", "rotation": -90 },
+                    { "side_data_type": "Mastering display metadata", "min_luminance": "50/10000", "max_luminance": "10000000/10000" }
+                ]
+            },
+            {
+                "index": 3, "codec_type": "audio", "codec_name": "aac", "profile": 1, "sample_rate": "48000",
+                "channels": 2, "bits_per_sample": 0, "duration": "60.021000",
+                "tags": { "LANGUAGE": "und", "title": "Stereo" }
+            },
+            {
+                "index": 4, "codec_type": "audio", "codec_name": "opus", "sample_rate": "48000",
+                "channels": 6, "disposition": { "default": 1 }, "tags": { "language": "jpn" }
+            }
+        ],
+        "chapters": [
+            { "id": 0, "start_time": "0.000000", "end_time": "30.500000", "tags": { "title": "Opening" } }
+        ],
+        "format": {
+            "format_name": "matroska,webm", "duration": "60.021000", "start_time": "-0.007000",
+            "size": "9876543210", "bit_rate": "5000000000", "probe_score": 100,
+            "tags": { "ENCODER": "Lavf60.3.100", "creation_time": "2024-01-01T00:00:00.000000Z" }
+        }
+    }"#;
+
+    #[test]
+    fn string_encoded_numbers_are_parsed() {
+        let media = from_json("/v/a.mkv", FIXTURE.as_bytes()).unwrap();
+        assert_eq!(media.format.format_name, "matroska,webm");
+        assert_eq!(media.format.duration, Some(60.021));
+        assert_eq!(media.format.start_time, Some(-0.007));
+        assert_eq!(media.format.probe_score, Some(100));
+        assert_eq!(media.format.tags.get("encoder").map(String::as_str), Some("Lavf60.3.100"));
+
+        let h264 = &media.streams[1];
+        assert_eq!(h264.bit_rate, Some(2_500_000));
+        assert_eq!(h264.frame_count, Some(1438));
+        let video = h264.video.as_ref().unwrap();
+        assert!((video.frame_rate.unwrap() - 23.976).abs() < 0.001);
+        assert_eq!(video.avg_frame_rate, None);
+        assert_eq!(video.bits_per_raw_sample, Some(8));
+
+        let aac = &media.streams[3];
+        let audio = aac.audio.as_ref().unwrap();
+        assert_eq!((audio.sample_rate, audio.channels, audio.bits_per_sample), (Some(48000), Some(2), None));
+        // Numeric profiles become strings and "und" means no language
+        assert_eq!(aac.profile.as_deref(), Some("1"));
+        assert_eq!(aac.language, None);
+        assert_eq!(aac.title.as_deref(), Some("Stereo"));
+
+        assert_eq!(media.chapters[0].end, 30.5);
+        assert_eq!(media.chapters[0].title.as_deref(), Some("Opening"));
+    }
+
+    #[test]
+    fn sizes_and_bit_rates_above_u32_fit() {
+        let media = from_json("/v/a.mkv", FIXTURE.as_bytes()).unwrap();
+        assert_eq!(media.format.size, Some(9_876_543_210));
+        assert_eq!(media.format.bit_rate, Some(5_000_000_000));
+    }
+
+    #[test]
+    fn primary_streams_skip_cover_art_and_prefer_the_default() {
+        let media = from_json("/v/a.mkv", FIXTURE.as_bytes()).unwrap();
+        assert_eq!(media.streams.iter().filter(|s| s.kind == StreamKind::Video).count(), 3);
+        assert!(media.streams[0].disposition.attached_pic);
+
+        let video = media.primary_video().unwrap();
+        assert_eq!(video.index, 2);
+        let info = video.video.as_ref().unwrap();
+        assert_eq!((info.width, info.height), (3840, 2160));
+        assert_eq!(info.rotation, Some(-90.0));
+        assert_eq!(info.color.hdr_format.as_deref(), Some("HDR10"));
+        assert_eq!(info.color.mastering_display.as_ref().unwrap().max_luminance, Some(1000.0));
+
+        assert_eq!(media.primary_audio().unwrap().index, 4);
+    }
+
+    #[test]
+    fn first_stream_wins_without_a_default_and_missing_sections_are_empty() {
+        let media = from_json("/v/a.wav", br#"{ "streams": [
+            { "index": 0, "codec_type": "audio", "codec_name": "pcm_s16le" },
+            { "index": 1, "codec_type": "audio", "codec_name": "flac" }
+        ] }"#)
+        .unwrap();
+        assert_eq!(media.primary_audio().unwrap().index, 0);
+        assert!(media.primary_video().is_none());
+        assert_eq!(media.format.format_name, "");
+        assert_eq!(media.duration(), None);
+        assert!(from_json("/v/a.wav", b"not json").is_err());
+    }
+}
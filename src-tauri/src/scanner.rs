@@ -0,0 +1,704 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State, Window};
+
+use crate::library::{MediaLibrary, RescanReport};
+use crate::media_type::{self, MediaType};
+use crate::MediaFile;
+
+// Emitted with a ScanBatch every time `batch_size` files have been found
+pub const BATCH_EVENT: &str = "media-scan-batch";
+// Emitted with the ScanReport when a streaming scan ends, cancelled or not
+pub const FINISHED_EVENT: &str = "media-scan-finished";
+
+// Per-directory ignore file using .gitignore syntax; its rules apply to that directory and below
+pub const IGNORE_FILE: &str = ".yeyoignore";
+
+const DEFAULT_BATCH_SIZE: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    // Don't look at symlinks at all
+    Skip,
+    // Include symlinked files but don't descend into symlinked directories
+    Files,
+    // Follow everything; directories already visited are skipped so loops terminate
+    #[default]
+    Follow,
+}
+
+// The defaults match the original scanner: unlimited depth, hidden files included, symlinks followed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ScanOptions {
+    // Levels of subdirectories to descend into; 0 only lists the directory itself
+    pub max_depth: Option<usize>,
+    pub include_hidden: bool,
+    pub symlinks: SymlinkPolicy,
+    pub batch_size: usize,
+    // Gitignore-style patterns relative to the scanned directory, e.g. "node_modules/" or "*.partial"
+    pub exclude: Vec<String>,
+    // Globs matched against the path relative to the scanned directory; when given, a file has to match one
+    pub include: Vec<String>,
+    // File size bounds in bytes
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+}
+
+impl ScanOptions {
+    // Whether a scan with these options lists every file, so anything missing from it was really removed
+    pub fn sees_everything(&self) -> bool {
+        self.max_depth.is_none()
+            && self.include_hidden
+            && self.symlinks == SymlinkPolicy::Follow
+            && self.exclude.is_empty()
+            && self.include.is_empty()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            max_depth: None,
+            include_hidden: true,
+            symlinks: SymlinkPolicy::Follow,
+            batch_size: DEFAULT_BATCH_SIZE,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            min_size: None,
+            max_size: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanError {
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanReport {
+    pub scan_id: String,
+    pub directory: String,
+    pub files_found: usize,
+    pub directories_scanned: usize,
+    // Directories that could not be read (permissions, vanished mid-scan, broken mounts) and broken ignore files
+    pub errors: Vec<ScanError>,
+    // Symlinked directories skipped because they lead back to somewhere already scanned
+    pub loops_skipped: usize,
+    pub cancelled: bool,
+}
+
+impl ScanReport {
+    // Paths the walk could not list completely, so their absence from the results proves nothing
+    pub fn unread_paths(&self) -> Vec<String> {
+        self.errors.iter().map(|error| error.path.clone()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanBatch {
+    pub scan_id: String,
+    pub files: Vec<MediaFile>,
+}
+
+// Cancellation flags for the streaming scans that are currently running
+#[derive(Default)]
+pub struct ScanRegistry {
+    scans: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl ScanRegistry {
+    fn register(&self, scan_id: &str) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        self.scans.lock().unwrap().insert(scan_id.to_string(), flag.clone());
+        flag
+    }
+
+    fn finish(&self, scan_id: &str) {
+        self.scans.lock().unwrap().remove(scan_id);
+    }
+
+    fn cancel(&self, scan_id: &str) -> Result<(), String> {
+        let scans = self.scans.lock().unwrap();
+        let flag = scans.get(scan_id).ok_or(format!("No running scan with id {}", scan_id))?;
+        flag.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn is_hidden(path: &Path, metadata: &fs::Metadata) -> bool {
+    let dot_file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().starts_with('.'))
+        .unwrap_or(false);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        dot_file || metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = metadata;
+        dot_file
+    }
+}
+
+// The per-scan exclude and include patterns, compiled once up front
+struct ScanFilter {
+    exclude: Gitignore,
+    include: Option<GlobSet>,
+}
+
+impl ScanFilter {
+    fn new(root: &Path, options: &ScanOptions) -> Result<Self, String> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in &options.exclude {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| format!("Invalid exclude pattern \"{}\": {}", pattern, e))?;
+        }
+        let exclude = builder
+            .build()
+            .map_err(|e| format!("Invalid exclude patterns: {}", e))?;
+
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            let mut set = GlobSetBuilder::new();
+            for pattern in &options.include {
+                let glob = Glob::new(pattern).map_err(|e| format!("Invalid include glob \"{}\": {}", pattern, e))?;
+                set.add(glob);
+            }
+            Some(set.build().map_err(|e| format!("Invalid include globs: {}", e))?)
+        };
+
+        Ok(ScanFilter { exclude, include })
+    }
+}
+
+// Reads the IGNORE_FILE in `dir`, if there is one. Lines that fail to parse are reported and the rest still apply.
+fn load_ignore_file(dir: &Path, errors: &mut Vec<ScanError>) -> Option<Gitignore> {
+    let file = dir.join(IGNORE_FILE);
+    if !file.is_file() {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(e) = builder.add(&file) {
+        errors.push(ScanError {
+            path: file.to_string_lossy().to_string(),
+            message: e.to_string(),
+        });
+    }
+    match builder.build() {
+        Ok(rules) if !rules.is_empty() => Some(rules),
+        Ok(_) => None,
+        Err(e) => {
+            errors.push(ScanError {
+                path: file.to_string_lossy().to_string(),
+                message: e.to_string(),
+            });
+            None
+        }
+    }
+}
+
+// Ignore files are checked from the deepest directory up so the closest rules win, like in git.
+// The per-scan excludes come last.
+fn is_excluded(rules: &[Rc<Gitignore>], exclude: &Gitignore, path: &Path, is_dir: bool) -> bool {
+    for rules in rules.iter().rev() {
+        match rules.matched(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    exclude.matched(path, is_dir).is_ignore()
+}
+
+// Whether the IGNORE_FILE rules between `root` and `path` exclude `path` or one of its parent directories.
+// Used for single paths that show up outside of a walk, e.g. from the file watcher.
+pub fn is_ignored(root: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let components: Vec<_> = relative.components().collect();
+    let no_excludes = Gitignore::empty();
+    let mut errors = Vec::new();
+    let mut rules = Vec::new();
+    let mut dir = root.to_path_buf();
+
+    for (index, component) in components.iter().enumerate() {
+        if let Some(loaded) = load_ignore_file(&dir, &mut errors) {
+            rules.push(Rc::new(loaded));
+        }
+        let child = dir.join(component);
+        let is_dir = index + 1 < components.len() || path.is_dir();
+        if is_excluded(&rules, &no_excludes, &child, is_dir) {
+            return true;
+        }
+        dir = child;
+    }
+    false
+}
+
+// Walks `root` depth-first without recursion, handing found files to `on_batch` in groups of
+// `options.batch_size`. Stops early once `cancel` is set. Fails only on invalid patterns in `options`.
+pub fn walk(
+    root: &Path,
+    extensions: &[String],
+    options: &ScanOptions,
+    cancel: &AtomicBool,
+    mut on_batch: impl FnMut(Vec<MediaFile>),
+) -> Result<ScanReport, String> {
+    let batch_size = options.batch_size.max(1);
+    let mut batch = Vec::new();
+    let report = walk_files(root, options, cancel, |path| {
+        let Some(file) = media_file_from_path(path, extensions) else {
+            return false;
+        };
+        batch.push(file);
+        if batch.len() >= batch_size {
+            on_batch(std::mem::take(&mut batch));
+        }
+        true
+    })?;
+
+    if !batch.is_empty() {
+        on_batch(batch);
+    }
+    Ok(report)
+}
+
+// The traversal behind `walk`: hands every file that passes `options` and the IGNORE_FILE rules to
+// `on_file`, which returns whether it counts as found.
+pub fn walk_files(
+    root: &Path,
+    options: &ScanOptions,
+    cancel: &AtomicBool,
+    mut on_file: impl FnMut(&Path) -> bool,
+) -> Result<ScanReport, String> {
+    let filter = ScanFilter::new(root, options)?;
+    let mut report = ScanReport {
+        directory: root.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+
+    // Canonical paths of visited directories, used to break symlink loops
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    if let Ok(canonical) = fs::canonicalize(root) {
+        visited.insert(canonical);
+    }
+
+    // Each directory carries the ignore rules of its parents
+    let mut stack: Vec<(PathBuf, usize, Vec<Rc<Gitignore>>)> = vec![(root.to_path_buf(), 0, Vec::new())];
+    while let Some((dir, depth, mut rules)) = stack.pop() {
+        if cancel.load(Ordering::SeqCst) {
+            report.cancelled = true;
+            break;
+        }
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                report.errors.push(ScanError {
+                    path: dir.to_string_lossy().to_string(),
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        report.directories_scanned += 1;
+        if let Some(loaded) = load_ignore_file(&dir, &mut report.errors) {
+            rules.push(Rc::new(loaded));
+        }
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    report.errors.push(ScanError {
+                        path: dir.to_string_lossy().to_string(),
+                        message: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let path = entry.path();
+
+            let is_symlink = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+            if is_symlink && options.symlinks == SymlinkPolicy::Skip {
+                continue;
+            }
+
+            // fs::metadata follows symlinks; a broken link simply has nothing to scan
+            let Ok(metadata) = fs::metadata(&path) else { continue };
+            if !options.include_hidden && is_hidden(&path, &metadata) {
+                continue;
+            }
+            if is_excluded(&rules, &filter.exclude, &path, metadata.is_dir()) {
+                continue;
+            }
+
+            if metadata.is_dir() {
+                if is_symlink && options.symlinks == SymlinkPolicy::Files {
+                    continue;
+                }
+                if options.max_depth.is_some_and(|max| depth >= max) {
+                    continue;
+                }
+                // Real directories are remembered too, so a link pointing at one of them later is skipped
+                match fs::canonicalize(&path) {
+                    Ok(canonical) => {
+                        if !visited.insert(canonical) {
+                            report.loops_skipped += 1;
+                            continue;
+                        }
+                    }
+                    Err(_) if is_symlink => continue,
+                    Err(_) => {}
+                }
+                stack.push((path, depth + 1, rules.clone()));
+            } else {
+                if options.min_size.is_some_and(|min| metadata.len() < min)
+                    || options.max_size.is_some_and(|max| metadata.len() > max)
+                {
+                    continue;
+                }
+                if let Some(include) = &filter.include {
+                    if !include.is_match(path.strip_prefix(root).unwrap_or(&path)) {
+                        continue;
+                    }
+                }
+                if on_file(&path) {
+                    report.files_found += 1;
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// Collects everything under `dir` with the default options. Fails only when `dir` itself
+// can't be read; unreadable subdirectories are skipped and listed in the report's errors.
+pub fn scan_directory_recursive(dir: &Path, extensions: &[String]) -> Result<(Vec<MediaFile>, ScanReport), String> {
+    let mut files = Vec::new();
+    let report = walk(dir, extensions, &ScanOptions::default(), &AtomicBool::new(false), |mut batch| {
+        files.append(&mut batch)
+    })?;
+
+    match report.errors.iter().find(|error| Path::new(&error.path) == dir) {
+        Some(error) => Err(error.message.clone()),
+        None => Ok((files, report)),
+    }
+}
+
+// Builds the MediaFile for a single path if it is a regular file with one of the wanted extensions.
+// Files without an extension are kept when their content is one of the kinds being scanned for.
+// Runs inside directory walks, so the content is only sniffed, never probed.
+pub fn media_file_from_path(path: &Path, extensions: &[String]) -> Option<MediaFile> {
+    if !path.is_file() {
+        return None;
+    }
+
+    let ext_lower = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if !ext_lower.is_empty() && !extensions.contains(&ext_lower) {
+        return None;
+    }
+
+    let metadata = fs::metadata(path).ok()?;
+    let file_type = media_type::detect_without_probe(path);
+    if ext_lower.is_empty()
+        && (file_type == MediaType::Unknown
+            || !extensions.iter().any(|ext| media_type::type_for_extension(ext) == file_type))
+    {
+        return None;
+    }
+
+    let modified = metadata
+        .modified()
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    Some(MediaFile {
+        name: path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        path: path.to_string_lossy().to_string(),
+        size: metadata.len(),
+        modified,
+        file_type: file_type.as_str().to_string(),
+    })
+}
+
+// Scans like scan_media_files but streams the results as BATCH_EVENT events while walking.
+// Returns the final report, which is also emitted as FINISHED_EVENT.
+#[tauri::command]
+pub async fn scan_media_files_streaming(
+    directory: String,
+    extensions: Vec<String>,
+    options: Option<ScanOptions>,
+    scan_id: Option<String>,
+    window: Window,
+    app: AppHandle,
+) -> Result<ScanReport, String> {
+    let path = Path::new(&directory);
+    if !path.exists() || !path.is_dir() {
+        return Err("Invalid directory path".to_string());
+    }
+
+    let scan_id = scan_id.unwrap_or_else(crate::new_job_id);
+    let options = options.unwrap_or_default();
+    let extensions_lower: Vec<String> = extensions.iter().map(|ext| ext.to_lowercase()).collect();
+
+    // The walk blocks on the filesystem, so keep it off the async runtime
+    tauri::async_runtime::spawn_blocking(move || {
+        let scans = app.state::<ScanRegistry>();
+        let library = app.state::<MediaLibrary>();
+        let path = Path::new(&directory);
+
+        let cancel = scans.register(&scan_id);
+        // Batches go into the library as they arrive; only their paths are kept to find removed files.
+        // A filtered scan leaves the library alone.
+        let sync_library = options.sees_everything();
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut sync = RescanReport::default();
+        let result = walk(path, &extensions_lower, &options, &cancel, |files| {
+            if sync_library {
+                seen.extend(files.iter().map(|file| file.path.clone()));
+                library.merge_files(&files, &mut sync);
+            }
+            let _ = window.emit(
+                BATCH_EVENT,
+                ScanBatch {
+                    scan_id: scan_id.clone(),
+                    files,
+                },
+            );
+        });
+        scans.finish(&scan_id);
+        let mut report = result?;
+        report.scan_id = scan_id;
+
+        // A cancelled scan, or one that couldn't read the directory itself, only saw part of the tree
+        // and can't tell what was removed. Subdirectories that failed keep their entries.
+        if sync_library {
+            let root_failed = report.errors.iter().any(|error| Path::new(&error.path) == path);
+            let complete = !report.cancelled && !root_failed;
+            let synced = library.finish_sync(
+                &directory,
+                &extensions_lower,
+                complete.then_some(&seen),
+                &report.unread_paths(),
+                sync,
+            );
+            if let Err(e) = synced {
+                eprintln!("{}", e);
+            }
+            library.spawn_probe(&app);
+        }
+
+        let _ = window.emit(FINISHED_EVENT, report.clone());
+        Ok(report)
+    })
+    .await
+    .map_err(|e| format!("Scan failed: {}", e))?
+}
+
+#[tauri::command]
+pub fn cancel_scan(scan_id: String, scans: State<'_, ScanRegistry>) -> Result<(), String> {
+    scans.cancel(&scan_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(root: &Path, relative: &str, size: usize) {
+        let path = root.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0u8; size]).unwrap();
+    }
+
+    // Relative paths of the files found, sorted, and the report
+    fn scan(root: &Path, options: &ScanOptions) -> (Vec<String>, ScanReport) {
+        let mut found = Vec::new();
+        let report = walk(root, &["mp4".to_string()], options, &AtomicBool::new(false), |batch| {
+            found.extend(batch.into_iter().map(|file| {
+                let relative = Path::new(&file.path).strip_prefix(root).unwrap().to_path_buf();
+                relative.to_string_lossy().replace('\\', "/")
+            }))
+        })
+        .unwrap();
+        found.sort();
+        (found, report)
+    }
+
+    #[test]
+    fn depth_limit_counts_subdirectory_levels() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "1.mp4", 1);
+        touch(dir.path(), "a/2.mp4", 1);
+        touch(dir.path(), "a/b/3.mp4", 1);
+        touch(dir.path(), "a/notes.txt", 1);
+
+        let options = |max_depth| ScanOptions { max_depth, ..Default::default() };
+        assert_eq!(scan(dir.path(), &options(Some(0))).0, ["1.mp4"]);
+        assert_eq!(scan(dir.path(), &options(Some(1))).0, ["1.mp4", "a/2.mp4"]);
+        let (found, report) = scan(dir.path(), &options(None));
+        assert_eq!(found, ["1.mp4", "a/2.mp4", "a/b/3.mp4"]);
+        assert_eq!(report.directories_scanned, 3);
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn hidden_files_and_directories_can_be_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "shown.mp4", 1);
+        touch(dir.path(), ".hidden.mp4", 1);
+        touch(dir.path(), ".cache/inside.mp4", 1);
+
+        assert_eq!(scan(dir.path(), &ScanOptions::default()).0.len(), 3);
+        let options = ScanOptions { include_hidden: false, ..Default::default() };
+        assert_eq!(scan(dir.path(), &options).0, ["shown.mp4"]);
+    }
+
+    #[test]
+    fn small_batches_and_cancelling_between_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["a", "b", "c"] {
+            touch(dir.path(), &format!("{}/1.mp4", sub), 1);
+            touch(dir.path(), &format!("{}/2.mp4", sub), 1);
+        }
+        let options = ScanOptions { batch_size: 1, ..Default::default() };
+
+        let mut batches = 0;
+        let report = walk(dir.path(), &["mp4".to_string()], &options, &AtomicBool::new(false), |batch| {
+            assert_eq!(batch.len(), 1);
+            batches += 1;
+        })
+        .unwrap();
+        assert_eq!((batches, report.files_found, report.cancelled), (6, 6, false));
+
+        // The flag is checked before each directory, so the one being listed is finished first
+        let cancel = AtomicBool::new(false);
+        let report = walk(dir.path(), &["mp4".to_string()], &options, &cancel, |_| {
+            cancel.store(true, Ordering::SeqCst)
+        })
+        .unwrap();
+        assert!(report.cancelled);
+        assert_eq!(report.files_found, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "a/1.mp4", 1);
+        touch(dir.path(), "b/2.mp4", 1);
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("a/back")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("b"), dir.path().join("b-link")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("a/1.mp4"), dir.path().join("link.mp4")).unwrap();
+
+        // Following links finds each directory once, whichever path reaches it first
+        let (found, report) = scan(dir.path(), &ScanOptions::default());
+        assert_eq!(found.len(), 3);
+        assert!(found.contains(&"link.mp4".to_string()));
+        assert_eq!(report.loops_skipped, 2);
+
+        let options = ScanOptions { symlinks: SymlinkPolicy::Files, ..Default::default() };
+        let (found, report) = scan(dir.path(), &options);
+        assert_eq!(found, ["a/1.mp4", "b/2.mp4", "link.mp4"]);
+        assert_eq!(report.loops_skipped, 0);
+
+        let options = ScanOptions { symlinks: SymlinkPolicy::Skip, ..Default::default() };
+        assert_eq!(scan(dir.path(), &options).0, ["a/1.mp4", "b/2.mp4"]);
+    }
+
+    #[test]
+    fn ignore_files_apply_below_their_directory_and_the_closest_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "keep.mp4", 1);
+        touch(dir.path(), "draft.part.mp4", 1);
+        touch(dir.path(), "renders/out.mp4", 1);
+        touch(dir.path(), "clips/a.mp4", 1);
+        touch(dir.path(), "clips/b.part.mp4", 1);
+        touch(dir.path(), "clips/old/c.mp4", 1);
+        fs::write(dir.path().join(IGNORE_FILE), "*.part.mp4\nrenders/\n").unwrap();
+        fs::write(dir.path().join("clips").join(IGNORE_FILE), "!b.part.mp4\nold/\n").unwrap();
+
+        let (found, report) = scan(dir.path(), &ScanOptions::default());
+        assert_eq!(found, ["clips/a.mp4", "clips/b.part.mp4", "keep.mp4"]);
+        assert!(report.errors.is_empty());
+
+        assert!(is_ignored(dir.path(), &dir.path().join("draft.part.mp4")));
+        assert!(is_ignored(dir.path(), &dir.path().join("renders/out.mp4")));
+        assert!(is_ignored(dir.path(), &dir.path().join("clips/old/c.mp4")));
+        assert!(!is_ignored(dir.path(), &dir.path().join("clips/b.part.mp4")));
+        assert!(!is_ignored(dir.path(), &dir.path().join("clips/a.mp4")));
+    }
+
+    #[test]
+    fn exclude_and_include_patterns_are_relative_to_the_scanned_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "a.mp4", 1);
+        touch(dir.path(), "node_modules/b.mp4", 1);
+        touch(dir.path(), "shows/s01/e01.mp4", 1);
+        touch(dir.path(), "shows/s01/e01.sample.mp4", 1);
+
+        let options = ScanOptions {
+            exclude: vec!["node_modules/".to_string(), "*.sample.mp4".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(scan(dir.path(), &options).0, ["a.mp4", "shows/s01/e01.mp4"]);
+
+        let options = ScanOptions { include: vec!["shows/**".to_string()], ..Default::default() };
+        assert_eq!(scan(dir.path(), &options).0, ["shows/s01/e01.mp4", "shows/s01/e01.sample.mp4"]);
+
+        let options = ScanOptions { include: vec!["{a".to_string()], ..Default::default() };
+        assert!(walk(dir.path(), &["mp4".to_string()], &options, &AtomicBool::new(false), |_| {}).is_err());
+    }
+
+    #[test]
+    fn size_bounds_are_inclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(dir.path(), "small.mp4", 10);
+        touch(dir.path(), "medium.mp4", 100);
+        touch(dir.path(), "large.mp4", 1000);
+
+        let options = ScanOptions { min_size: Some(100), ..Default::default() };
+        assert_eq!(scan(dir.path(), &options).0, ["large.mp4", "medium.mp4"]);
+        let options = ScanOptions { max_size: Some(100), ..Default::default() };
+        assert_eq!(scan(dir.path(), &options).0, ["medium.mp4", "small.mp4"]);
+        let options = ScanOptions { min_size: Some(11), max_size: Some(999), ..Default::default() };
+        assert_eq!(scan(dir.path(), &options).0, ["medium.mp4"]);
+        assert!(!options.sees_everything());
+    }
+}
@@ -0,0 +1,307 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::{State, Window};
+
+use crate::capabilities::{self, CapabilityCache};
+use crate::ffmpeg_progress::{self, Pass};
+use crate::jobs::JobRegistry;
+use crate::presets::PresetStore;
+use crate::probe;
+use crate::ConversionSettings;
+
+// Sizes are in decimal megabytes, which stays under the cap whichever way a service counts
+const BYTES_PER_MB: f64 = 1_000_000.0;
+// Share of the budget set aside for container overhead and rate control overshoot
+const OVERHEAD: f64 = 0.04;
+// Below this the picture falls apart, so audio quality is given up first
+const MIN_VIDEO_KBPS: f64 = 150.0;
+// Audio bitrates tried in turn when the budget is tight
+const AUDIO_STEPS_KBPS: [u32; 4] = [128, 96, 64, 32];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetSizeSettings {
+    pub target_size_mb: f64,
+    #[serde(default = "default_video_codec")]
+    pub video_codec: String,
+    #[serde(default = "default_audio_codec")]
+    pub audio_codec: String,
+    // Starting audio bitrate; lowered automatically if it leaves too little for the video
+    pub audio_bitrate_kbps: Option<u32>,
+    pub preset: Option<String>,
+    // Scale down to at most this height, keeping the aspect ratio
+    pub max_height: Option<u32>,
+}
+
+impl TargetSizeSettings {
+    // The encoder settings of a regular video conversion, aimed at a size instead of a quality
+    pub fn from_conversion(settings: &ConversionSettings, target_size_mb: f64) -> Self {
+        TargetSizeSettings {
+            target_size_mb,
+            video_codec: settings.video_codec.clone(),
+            audio_codec: settings.audio_codec.clone(),
+            audio_bitrate_kbps: None,
+            preset: settings.preset.clone(),
+            max_height: settings.max_height,
+        }
+    }
+}
+
+fn default_video_codec() -> String {
+    "libx264".to_string()
+}
+
+fn default_audio_codec() -> String {
+    "aac".to_string()
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetSizeResult {
+    pub output: String,
+    pub target_bytes: u64,
+    pub actual_bytes: u64,
+    // How far off the target the file landed, in percent; negative means smaller
+    pub deviation_percent: f64,
+    pub within_target: bool,
+    pub video_bitrate_kbps: u32,
+    pub audio_bitrate_kbps: u32,
+    pub duration: f64,
+}
+
+// Splits the size budget into video and audio bitrates (kbit/s)
+fn plan_bitrates(target_bytes: f64, duration: f64, has_audio: bool, audio_kbps: u32) -> Result<(u32, u32), String> {
+    let total_kbps = target_bytes * 8.0 * (1.0 - OVERHEAD) / duration / 1000.0;
+
+    let audio_steps: Vec<u32> = if has_audio {
+        std::iter::once(audio_kbps)
+            .chain(AUDIO_STEPS_KBPS.into_iter().filter(|step| *step < audio_kbps))
+            .collect()
+    } else {
+        vec![0]
+    };
+    for audio in audio_steps {
+        let video = total_kbps - audio as f64;
+        if video >= MIN_VIDEO_KBPS {
+            return Ok((video.floor() as u32, audio));
+        }
+    }
+
+    Err(format!(
+        "{:.1} MB is too small for {:.0} seconds of video; it allows only {:.0} kbit/s in total",
+        target_bytes / BYTES_PER_MB,
+        duration,
+        total_kbps
+    ))
+}
+
+// Encoders pass_args knows how to run in two passes
+pub fn supports_two_pass(codec: &str) -> bool {
+    matches!(codec, "libx264" | "libvpx" | "libvpx-vp9" | "libaom-av1" | "mpeg4" | "libx265")
+}
+
+// Arguments selecting the pass for encoders that support two-pass rate control
+fn pass_args(codec: &str, pass: u8, log_prefix: &Path) -> Result<Vec<String>, String> {
+    let log = log_prefix.to_string_lossy().to_string();
+    match codec {
+        // x265 keeps its own stats file and ignores -pass. Inside -x265-params a backslash is an
+        // escape, so Windows paths get forward slashes, which x265 accepts too; ':' separates options.
+        "libx265" => Ok(vec![
+            "-x265-params".to_string(),
+            format!("pass={}:stats={}.log", pass, log.replace('\\', "/").replace(':', "\\:")),
+        ]),
+        _ if supports_two_pass(codec) => Ok(vec![
+            "-pass".to_string(),
+            pass.to_string(),
+            "-passlogfile".to_string(),
+            log,
+        ]),
+        _ => Err(format!("{} doesn't support two-pass encoding", codec)),
+    }
+}
+
+fn null_output() -> &'static str {
+    if cfg!(windows) {
+        "NUL"
+    } else {
+        "/dev/null"
+    }
+}
+
+// Removes the stats files the encoders leave behind (…-0.log, .mbtree, .cutree, …)
+fn remove_pass_logs(log_prefix: &Path) {
+    let (Some(dir), Some(prefix)) = (log_prefix.parent(), log_prefix.file_name()) else {
+        return;
+    };
+    let prefix = prefix.to_string_lossy().to_string();
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+// Two-pass encode of `input_path` aimed at `settings.target_size_mb`
+pub fn encode_to_size(
+    input_path: &str,
+    output: &Path,
+    settings: &TargetSizeSettings,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<TargetSizeResult, String> {
+    if settings.target_size_mb.is_nan() || settings.target_size_mb <= 0.0 {
+        return Err("Target size must be greater than zero".to_string());
+    }
+    let media = probe::probe(input_path)?;
+    let duration = media.duration().filter(|d| *d > 0.0).ok_or("Could not determine the duration of the input")?;
+    let has_audio = media.primary_audio().is_some();
+
+    let target_bytes = settings.target_size_mb * BYTES_PER_MB;
+    let (video_kbps, audio_kbps) = plan_bitrates(
+        target_bytes,
+        duration,
+        has_audio,
+        settings.audio_bitrate_kbps.unwrap_or(AUDIO_STEPS_KBPS[0]),
+    )?;
+
+    let log_prefix: PathBuf = std::env::temp_dir().join(format!("yeyo-2pass-{}", job_id));
+    let video_args = |pass: u8| -> Result<Vec<String>, String> {
+        let mut args = vec![
+            "-c:v".to_string(),
+            settings.video_codec.clone(),
+            "-b:v".to_string(),
+            format!("{}k", video_kbps),
+        ];
+        let presets = capabilities::encoder_rules(&settings.video_codec).presets;
+        if let Some(preset) = settings.preset.as_ref().filter(|_| !presets.is_empty()) {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+        // Both passes need the same filters, or the first pass analyses a different picture
+        if let Some(max_height) = settings.max_height {
+            args.extend(["-vf".to_string(), format!("scale=-2:'min({},ih)'", max_height)]);
+        }
+        args.extend(pass_args(&settings.video_codec, pass, &log_prefix)?);
+        Ok(args)
+    };
+
+    // Pass 1 only analyses the video; its output is thrown away
+    let mut first = ffmpeg_progress::command();
+    first.args(["-y", "-i", input_path]);
+    first.args(video_args(1)?);
+    first.args(["-an", "-f", "null", null_output()]);
+
+    let mut second = ffmpeg_progress::command();
+    second.args(["-y", "-i", input_path]);
+    second.args(video_args(2)?);
+    if has_audio {
+        second.args(["-c:a", &settings.audio_codec, "-b:a", &format!("{}k", audio_kbps)]);
+    }
+    second.arg(output);
+
+    let result = ffmpeg_progress::run_pass(window, jobs, job_id, &mut first, Vec::new(), Some(duration), Pass::nth(0, 2))
+        .and_then(|_| {
+            let outputs = vec![output.to_path_buf()];
+            ffmpeg_progress::run_pass(window, jobs, job_id, &mut second, outputs, Some(duration), Pass::nth(1, 2))
+        });
+    remove_pass_logs(&log_prefix);
+    result?;
+
+    let actual_bytes = fs::metadata(output).map(|m| m.len()).unwrap_or(0);
+    Ok(TargetSizeResult {
+        output: output.to_string_lossy().to_string(),
+        target_bytes: target_bytes as u64,
+        actual_bytes,
+        deviation_percent: (actual_bytes as f64 / target_bytes - 1.0) * 100.0,
+        within_target: actual_bytes as f64 <= target_bytes,
+        video_bitrate_kbps: video_kbps,
+        audio_bitrate_kbps: audio_kbps,
+        duration,
+    })
+}
+
+// Encodes a video so the file comes out at (just under) a given size, e.g. 25 MB for chat uploads.
+// With a preset, its encoder settings are used and only the target size (unless the preset has
+// one) and the audio bitrate are taken from `settings`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_to_target_size(
+    input_path: String,
+    output_format: Option<String>,
+    output_directory: Option<String>,
+    settings: Option<TargetSizeSettings>,
+    preset: Option<String>,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+    presets: State<'_, PresetStore>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<TargetSizeResult, String> {
+    let job_id = job_id.unwrap_or_else(crate::new_job_id);
+    let _job = jobs.reserve(&job_id)?;
+    let (settings, output_format) = match (settings, preset.as_deref()) {
+        (settings, Some(name)) => {
+            let (conversion, output_format) = presets.video(None, Some(name), output_format)?;
+            let target_size_mb = settings
+                .as_ref()
+                .map(|settings| settings.target_size_mb)
+                .or(conversion.target_size_mb)
+                .ok_or(format!("Preset {} has no target size; give one in the settings", name))?;
+            let mut target = TargetSizeSettings::from_conversion(&conversion, target_size_mb);
+            target.audio_bitrate_kbps = settings.and_then(|settings| settings.audio_bitrate_kbps);
+            (target, output_format)
+        }
+        (Some(settings), None) => (settings, output_format.ok_or("No output format given")?),
+        (None, None) => return Err("No target size settings or preset given".to_string()),
+    };
+    capabilities::validate_target_size(capabilities.current().as_ref(), &settings, &output_format).into_result()?;
+    let input = Path::new(&input_path);
+    let stem = input.file_stem().ok_or("Invalid input file")?.to_string_lossy();
+    let output = crate::output_dir_for(input, output_directory.as_deref())?
+        .join(format!("{}_{}MB.{}", stem, settings.target_size_mb, output_format));
+
+    encode_to_size(&input_path, &output, &settings, &window, &jobs, &job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_is_split_after_overhead() {
+        // 10 MB over a minute is 1280 kbit/s once the overhead is taken off
+        assert_eq!(plan_bitrates(10_000_000.0, 60.0, true, 128), Ok((1152, 128)));
+        assert_eq!(plan_bitrates(10_000_000.0, 60.0, false, 128), Ok((1280, 0)));
+    }
+
+    #[test]
+    fn audio_is_lowered_before_the_video_gets_too_small() {
+        assert_eq!(plan_bitrates(2_000_000.0, 60.0, true, 128), Ok((160, 96)));
+        assert_eq!(plan_bitrates(2_000_000.0, 60.0, true, 320), Ok((160, 96)));
+    }
+
+    #[test]
+    fn too_small_targets_are_refused() {
+        assert!(plan_bitrates(1_000_000.0, 60.0, true, 128).is_err());
+        assert!(plan_bitrates(500_000.0, 60.0, false, 128).is_err());
+    }
+
+    #[test]
+    fn two_pass_arguments() {
+        let log = Path::new("/tmp/log");
+        assert_eq!(pass_args("libx264", 1, log).unwrap(), ["-pass", "1", "-passlogfile", "/tmp/log"]);
+        assert_eq!(pass_args("libx265", 2, log).unwrap(), ["-x265-params", "pass=2:stats=/tmp/log.log"]);
+        assert!(pass_args("h264_nvenc", 1, log).is_err());
+
+        let windows = Path::new(r"C:\Users\me\AppData\Local\Temp\yeyo-2pass-1");
+        assert_eq!(
+            pass_args("libx265", 1, windows).unwrap(),
+            ["-x265-params", r"pass=1:stats=C\:/Users/me/AppData/Local/Temp/yeyo-2pass-1.log"]
+        );
+        assert!(!supports_two_pass("copy"));
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::http::{Request, Response, ResponseBuilder};
+use tauri::{AppHandle, Manager, State};
+
+use crate::create_hidden_command;
+use crate::media_type::{self, MediaType};
+use crate::persist;
+use crate::probe;
+
+// Custom URI scheme the cached thumbnails are served from
+pub const PROTOCOL: &str = "thumbnail";
+
+const CACHE_DIR: &str = "thumbnails";
+const INDEX_FILE: &str = "index.json";
+const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+const DEFAULT_WIDTH: u32 = 320;
+// Frames at least this many percent black are passed over when picking a poster frame
+const BLACK_PERCENT: u32 = 90;
+// Frames the thumbnail filter compares before picking the most representative one
+const CANDIDATE_FRAMES: u32 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    #[default]
+    Jpeg,
+    Webp,
+}
+
+impl ThumbnailFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+
+    fn codec_args(&self) -> &'static [&'static str] {
+        match self {
+            // mjpeg wants full range input
+            ThumbnailFormat::Jpeg => &["-c:v", "mjpeg", "-q:v", "4", "-pix_fmt", "yuvj420p"],
+            ThumbnailFormat::Webp => &["-c:v", "libwebp", "-quality", "80"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheEntry {
+    source: String,
+    modified: u64,
+    size: u64,
+    last_access: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheState {
+    max_bytes: u64,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl Default for CacheState {
+    fn default() -> Self {
+        CacheState {
+            max_bytes: DEFAULT_MAX_BYTES,
+            entries: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thumbnail {
+    pub key: String,
+    // Ready to use as an <img> src
+    pub url: String,
+    pub source: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheInfo {
+    pub entries: usize,
+    pub total_bytes: u64,
+    pub max_bytes: u64,
+}
+
+// Thumbnails on disk in the app cache dir, with an index of what each file was made from
+// and when it was last served, for LRU eviction
+pub struct ThumbnailCache {
+    dir: Option<PathBuf>,
+    state: Mutex<CacheState>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn thumbnail_url(key: &str) -> String {
+    // WebView2 only routes custom protocols through http(s)://<scheme>.localhost
+    if cfg!(windows) {
+        format!("https://{}.localhost/{}", PROTOCOL, key)
+    } else {
+        format!("{}://localhost/{}", PROTOCOL, key)
+    }
+}
+
+impl ThumbnailCache {
+    pub fn load(app: &AppHandle) -> Self {
+        let dir = app.path_resolver().app_cache_dir().map(|dir| dir.join(CACHE_DIR));
+
+        let mut state = dir
+            .as_ref()
+            .and_then(|dir| fs::read_to_string(dir.join(INDEX_FILE)).ok())
+            .and_then(|json| serde_json::from_str::<CacheState>(&json).ok())
+            .unwrap_or_default();
+        if let Some(dir) = &dir {
+            // The cache dir may have been cleaned up by the OS or the user
+            state.entries.retain(|key, _| dir.join(key).is_file());
+        }
+
+        ThumbnailCache {
+            dir,
+            state: Mutex::new(state),
+        }
+    }
+
+    // Nobody waits on the index, so a failed save is only logged; the next change writes it again
+    fn save(&self, state: &CacheState) {
+        let Some(dir) = &self.dir else { return };
+        let saved = serde_json::to_vec(state)
+            .map_err(|e| format!("Failed to serialize the thumbnail index: {}", e))
+            .and_then(|json| persist::write_atomic(&dir.join(INDEX_FILE), &json));
+        if let Err(e) = saved {
+            eprintln!("{}", e);
+        }
+    }
+
+    // File name of the thumbnail for one version of a file; a new mtime gives a new key
+    fn key(source: &str, modified: u64, width: u32, format: ThumbnailFormat) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(source.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(modified.to_le_bytes());
+        let digest = format!("{:x}", hasher.finalize());
+        format!("{}-{}.{}", &digest[..32], width, format.extension())
+    }
+
+    pub fn get_or_create(&self, source: &Path, width: u32, format: ThumbnailFormat) -> Result<Thumbnail, String> {
+        let dir = self.dir.as_ref().ok_or("No cache directory available for thumbnails")?;
+        let metadata = fs::metadata(source).map_err(|e| format!("Cannot read {}: {}", source.display(), e))?;
+        let modified = metadata
+            .modified()
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let source_str = source.to_string_lossy().to_string();
+        let key = Self::key(&source_str, modified, width, format);
+        let file = dir.join(&key);
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(entry) = state.entries.get_mut(&key) {
+                if file.is_file() {
+                    entry.last_access = unix_now();
+                    return Ok(Thumbnail {
+                        key: key.clone(),
+                        url: thumbnail_url(&key),
+                        source: source_str,
+                        size: entry.size,
+                    });
+                }
+            }
+        }
+
+        // Render outside the lock into a file of this request's own; concurrent requests for the
+        // same key each rename a complete thumbnail into place, and the last one wins
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create thumbnail cache: {}", e))?;
+        let partial = dir.join(format!("{}.{}.part", key, uuid::Uuid::new_v4()));
+        let rendered = render(source, &partial, width, format);
+        if let Err(e) = rendered {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        if let Err(e) = fs::rename(&partial, &file) {
+            let _ = fs::remove_file(&partial);
+            // Windows refuses to replace a thumbnail that is being served; the one there is just as good
+            if !file.is_file() {
+                return Err(format!("Failed to store thumbnail: {}", e));
+            }
+        }
+        let size = fs::metadata(&file).map(|m| m.len()).unwrap_or(0);
+
+        let mut state = self.state.lock().unwrap();
+        // Thumbnails of older versions of this file can't be requested any more
+        let stale: Vec<String> = state
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.source == source_str && entry.modified != modified)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for stale_key in stale {
+            state.entries.remove(&stale_key);
+            let _ = fs::remove_file(dir.join(&stale_key));
+        }
+
+        state.entries.insert(
+            key.clone(),
+            CacheEntry {
+                source: source_str.clone(),
+                modified,
+                size,
+                last_access: unix_now(),
+            },
+        );
+        self.evict(&mut state, dir);
+        self.save(&state);
+
+        Ok(Thumbnail {
+            url: thumbnail_url(&key),
+            key,
+            source: source_str,
+            size,
+        })
+    }
+
+    // Drops the least recently served thumbnails until the cache fits its size limit
+    fn evict(&self, state: &mut CacheState, dir: &Path) {
+        let mut total: u64 = state.entries.values().map(|entry| entry.size).sum();
+        if total <= state.max_bytes {
+            return;
+        }
+
+        let mut by_age: Vec<(String, u64, u64)> = state
+            .entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.last_access, entry.size))
+            .collect();
+        by_age.sort_by_key(|(_, last_access, _)| *last_access);
+
+        for (key, _, size) in by_age {
+            if total <= state.max_bytes {
+                break;
+            }
+            state.entries.remove(&key);
+            let _ = fs::remove_file(dir.join(&key));
+            total = total.saturating_sub(size);
+        }
+    }
+
+    // Bytes of a cached thumbnail. Only keys in the index are served, so the URL can't reach other files.
+    fn read(&self, key: &str) -> Option<Vec<u8>> {
+        let dir = self.dir.as_ref()?;
+        let mut state = self.state.lock().unwrap();
+        let entry = state.entries.get_mut(key)?;
+        let bytes = fs::read(dir.join(key)).ok()?;
+        // Only kept in memory; it is written out with the next change to the index
+        entry.last_access = unix_now();
+        Some(bytes)
+    }
+
+    fn info(&self) -> CacheInfo {
+        let state = self.state.lock().unwrap();
+        CacheInfo {
+            entries: state.entries.len(),
+            total_bytes: state.entries.values().map(|entry| entry.size).sum(),
+            max_bytes: state.max_bytes,
+        }
+    }
+}
+
+// Runs one ffmpeg invocation writing a single image to `output`; false if no frame came out
+fn run_ffmpeg(args: &[String], output: &Path, format: ThumbnailFormat) -> Result<bool, String> {
+    let result = create_hidden_command("ffmpeg")
+        .args(["-hide_banner", "-v", "error", "-y"])
+        .args(args)
+        .args(["-frames:v", "1", "-f", "image2", "-update", "1"])
+        .args(format.codec_args())
+        .arg(output)
+        .output()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                "FFmpeg not found. Please install FFmpeg and add it to your PATH.".to_string()
+            } else {
+                format!("Failed to execute ffmpeg: {}", e)
+            }
+        })?;
+
+    Ok(result.status.success() && fs::metadata(output).map(|m| m.len() > 0).unwrap_or(false))
+}
+
+fn render(source: &Path, output: &Path, width: u32, format: ThumbnailFormat) -> Result<(), String> {
+    let input = source.to_string_lossy().to_string();
+    let scale = format!("scale={}:-2", width);
+
+    if media_type::detect(source) == MediaType::Image {
+        let args = vec!["-i".to_string(), input, "-vf".to_string(), scale];
+        return match run_ffmpeg(&args, output, format)? {
+            true => Ok(()),
+            false => Err(format!("Failed to render a thumbnail for {}", source.display())),
+        };
+    }
+
+    let probe = probe::probe(&input)?;
+
+    if let Some(video) = probe.primary_video() {
+        // Start a little way in to get past intros, then let blackframe tag every frame and drop
+        // the mostly black ones before the thumbnail filter picks among the rest
+        let duration = probe.duration().unwrap_or(0.0);
+        let seek = (duration * 0.1).min(60.0);
+        let skip_black = format!(
+            "blackframe=amount=0,metadata=mode=select:key=lavfi.blackframe.pblack:value={}:function=less,thumbnail={},{}",
+            BLACK_PERCENT, CANDIDATE_FRAMES, scale
+        );
+        let any_frame = format!("thumbnail={},{}", CANDIDATE_FRAMES, scale);
+
+        // Retry from the start, then without the black filter for videos that are dark throughout
+        let attempts = [(seek, &skip_black), (0.0, &skip_black), (0.0, &any_frame)];
+        for (start, filters) in attempts {
+            let args = vec![
+                "-ss".to_string(),
+                format!("{:.3}", start),
+                "-i".to_string(),
+                input.clone(),
+                "-map".to_string(),
+                format!("0:{}", video.index),
+                "-vf".to_string(),
+                filters.clone(),
+            ];
+            if run_ffmpeg(&args, output, format)? {
+                return Ok(());
+            }
+        }
+        return Err(format!("Failed to grab a frame from {}", source.display()));
+    }
+
+    // Audio files: embedded cover art shows up as a video stream flagged attached_pic
+    if let Some(cover) = probe.streams.iter().find(|stream| stream.disposition.attached_pic) {
+        let args = vec![
+            "-i".to_string(),
+            input,
+            "-map".to_string(),
+            format!("0:{}", cover.index),
+            "-vf".to_string(),
+            scale,
+        ];
+        if run_ffmpeg(&args, output, format)? {
+            return Ok(());
+        }
+        return Err(format!("Failed to extract the cover art of {}", source.display()));
+    }
+
+    Err(format!("{} has no video frames or cover art", source.display()))
+}
+
+// Handler for the PROTOCOL scheme: thumbnail://localhost/<key>
+pub fn serve(app: &AppHandle, request: &Request) -> Result<Response, Box<dyn Error>> {
+    let cache = app.state::<ThumbnailCache>();
+    let path = request.uri().split(['?', '#']).next().unwrap_or_default();
+    let key = path.rsplit('/').next().unwrap_or_default();
+
+    match cache.read(key) {
+        Some(bytes) => {
+            let mimetype = if key.ends_with(".webp") { "image/webp" } else { "image/jpeg" };
+            ResponseBuilder::new()
+                .mimetype(mimetype)
+                // A key never changes content; a new version of the source gets a new key
+                .header("Cache-Control", "max-age=31536000, immutable")
+                .body(bytes)
+        }
+        None => ResponseBuilder::new().status(404).body(Vec::new()),
+    }
+}
+
+// Returns the cached thumbnail for a file, rendering it first if needed
+#[tauri::command]
+pub async fn get_thumbnail(
+    path: String,
+    width: Option<u32>,
+    format: Option<ThumbnailFormat>,
+    app: AppHandle,
+) -> Result<Thumbnail, String> {
+    let width = width.unwrap_or(DEFAULT_WIDTH).clamp(16, 1920);
+    let format = format.unwrap_or_default();
+    tauri::async_runtime::spawn_blocking(move || {
+        app.state::<ThumbnailCache>()
+            .get_or_create(Path::new(&path), width, format)
+    })
+    .await
+    .map_err(|e| format!("Thumbnail task failed: {}", e))?
+}
+
+#[tauri::command]
+pub fn thumbnail_cache_info(cache: State<'_, ThumbnailCache>) -> CacheInfo {
+    cache.info()
+}
+
+#[tauri::command]
+pub fn set_thumbnail_cache_limit(max_bytes: u64, cache: State<'_, ThumbnailCache>) -> CacheInfo {
+    if let Some(dir) = &cache.dir {
+        let mut state = cache.state.lock().unwrap();
+        state.max_bytes = max_bytes;
+        cache.evict(&mut state, dir);
+        cache.save(&state);
+    }
+    cache.info()
+}
+
+#[tauri::command]
+pub fn clear_thumbnail_cache(cache: State<'_, ThumbnailCache>) -> Result<(), String> {
+    let Some(dir) = &cache.dir else { return Ok(()) };
+    let mut state = cache.state.lock().unwrap();
+    for key in state.entries.keys() {
+        let _ = fs::remove_file(dir.join(key));
+    }
+    state.entries.clear();
+    cache.save(&state);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(dir: &Path, max_bytes: u64) -> ThumbnailCache {
+        ThumbnailCache {
+            dir: Some(dir.to_path_buf()),
+            state: Mutex::new(CacheState { max_bytes, entries: HashMap::new() }),
+        }
+    }
+
+    fn add(cache: &ThumbnailCache, key: &str, size: u64, last_access: u64) {
+        fs::write(cache.dir.as_ref().unwrap().join(key), vec![0u8; size as usize]).unwrap();
+        cache.state.lock().unwrap().entries.insert(
+            key.to_string(),
+            CacheEntry { source: format!("/media/{}", key), modified: 1, size, last_access },
+        );
+    }
+
+    #[test]
+    fn key_changes_with_every_input() {
+        let key = ThumbnailCache::key("/media/a.mp4", 100, 320, ThumbnailFormat::Jpeg);
+        assert_eq!(key, ThumbnailCache::key("/media/a.mp4", 100, 320, ThumbnailFormat::Jpeg));
+        let (digest, rest) = key.split_once('-').unwrap();
+        assert_eq!(digest.len(), 32);
+        assert!(digest.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(rest, "320.jpg");
+
+        assert_ne!(key, ThumbnailCache::key("/media/b.mp4", 100, 320, ThumbnailFormat::Jpeg));
+        assert_ne!(key, ThumbnailCache::key("/media/a.mp4", 101, 320, ThumbnailFormat::Jpeg));
+        assert!(ThumbnailCache::key("/media/a.mp4", 100, 640, ThumbnailFormat::Jpeg).ends_with("-640.jpg"));
+        let webp = ThumbnailCache::key("/media/a.mp4", 100, 320, ThumbnailFormat::Webp);
+        assert_eq!(webp.replace(".webp", ".jpg"), key);
+    }
+
+    #[test]
+    fn eviction_drops_the_least_recently_served_until_the_cap_fits() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 250);
+        add(&cache, "old", 100, 1);
+        add(&cache, "middle", 100, 2);
+        add(&cache, "new", 100, 3);
+
+        let mut state = cache.state.lock().unwrap();
+        cache.evict(&mut state, dir.path());
+        let mut kept: Vec<&str> = state.entries.keys().map(String::as_str).collect();
+        kept.sort();
+        assert_eq!(kept, ["middle", "new"]);
+        assert!(!dir.path().join("old").exists());
+        assert!(dir.path().join("middle").exists());
+
+        // Lowering the limit keeps the most recently served one
+        state.max_bytes = 100;
+        cache.evict(&mut state, dir.path());
+        assert_eq!(state.entries.keys().collect::<Vec<_>>(), ["new"]);
+        drop(state);
+        assert_eq!(cache.info().total_bytes, 100);
+    }
+
+    #[test]
+    fn serving_a_thumbnail_makes_it_recent() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 250);
+        add(&cache, "old", 100, 1);
+        add(&cache, "new", 100, 2);
+        assert!(cache.read("old").is_some());
+        assert!(cache.read("../index.json").is_none());
+
+        add(&cache, "newest", 100, 3);
+        let mut state = cache.state.lock().unwrap();
+        cache.evict(&mut state, dir.path());
+        assert!(state.entries.contains_key("old"));
+        assert!(!state.entries.contains_key("new"));
+    }
+
+    #[test]
+    fn index_is_saved_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(&dir.path().join("thumbnails"), 250);
+        fs::create_dir_all(cache.dir.as_ref().unwrap()).unwrap();
+        add(&cache, "a", 10, 1);
+        cache.save(&cache.state.lock().unwrap());
+
+        let index = dir.path().join("thumbnails").join(INDEX_FILE);
+        let saved: CacheState = serde_json::from_str(&fs::read_to_string(index).unwrap()).unwrap();
+        assert_eq!(saved.entries["a"].size, 10);
+        assert!(!dir.path().join("thumbnails").join("index.json.tmp").exists());
+    }
+}
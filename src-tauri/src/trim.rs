@@ -0,0 +1,542 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::{State, Window};
+
+use crate::capabilities::{self, CapabilityCache, FfmpegCapabilities, ValidationReport};
+use crate::concat::concat_list_entry;
+use crate::ffmpeg_progress::{self, Pass};
+use crate::jobs::JobRegistry;
+use crate::media_type::{self, MediaType};
+use crate::probe::{self, MediaProbe};
+use crate::{AudioConversionSettings, ConversionSettings};
+
+// Ranges shorter than this are dropped; FFmpeg can't produce anything meaningful from them
+const MIN_RANGE: f64 = 0.05;
+// Audio containers that keep cover art as an attached picture
+const COVER_ART_FORMATS: &[&str] = &["mp3", "flac", "m4a", "m4b"];
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrimRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrimOptions {
+    // Re-encode so cuts land on the exact frame instead of the nearest keyframe
+    pub accurate: bool,
+    // Write the kept ranges one after another into a single file instead of one file per range
+    pub join: bool,
+    pub output_directory: Option<String>,
+    // Encoder settings for accurate mode; without a codec FFmpeg picks the container's default
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub crf: Option<u32>,
+    pub preset: Option<String>,
+}
+
+impl Default for TrimOptions {
+    fn default() -> Self {
+        TrimOptions {
+            accurate: false,
+            join: false,
+            output_directory: None,
+            video_codec: None,
+            audio_codec: None,
+            crf: Some(18),
+            preset: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrimSegment {
+    pub requested_start: f64,
+    pub requested_end: f64,
+    // Where the cut really starts; earlier than requested when snapped back to a keyframe
+    pub start: f64,
+    pub end: f64,
+    // The file for this range; None when the ranges were joined
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrimResult {
+    pub outputs: Vec<String>,
+    pub segments: Vec<TrimSegment>,
+    pub reencoded: bool,
+}
+
+// Sorts the ranges, clamps them to the media and merges overlapping ones
+fn normalize_ranges(ranges: &[TrimRange], duration: Option<f64>) -> Result<Vec<TrimRange>, String> {
+    let mut sorted = Vec::new();
+    for range in ranges {
+        if !range.start.is_finite() || !range.end.is_finite() || range.start < 0.0 || range.end <= range.start {
+            return Err(format!("Invalid range {:.3}-{:.3}: the end must come after the start", range.start, range.end));
+        }
+        let end = duration.map_or(range.end, |d| range.end.min(d));
+        if end - range.start >= MIN_RANGE {
+            sorted.push(TrimRange { start: range.start, end });
+        }
+    }
+    if sorted.is_empty() {
+        return Err("No ranges inside the media to keep".to_string());
+    }
+
+    sorted.sort_by(|a, b| a.start.total_cmp(&b.start));
+    let mut merged: Vec<TrimRange> = Vec::new();
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    Ok(merged)
+}
+
+// Latest keyframe at or before `time`. Stream copy can only start on a keyframe, so starting
+// there keeps everything that was asked for.
+fn snap_to_keyframe(keyframes: &[f64], time: f64) -> f64 {
+    let index = keyframes.partition_point(|&keyframe| keyframe <= time + 0.001);
+    if index == 0 {
+        return 0.0;
+    }
+    keyframes[index - 1].max(0.0)
+}
+
+// Pairs each requested range with where its stream copied cut really starts. Snapping moves starts
+// back, so ranges that were apart can overlap again; those are merged so no footage repeats.
+fn snap_ranges(ranges: &[TrimRange], keyframes: &[f64]) -> Vec<(TrimRange, TrimRange)> {
+    let mut snapped: Vec<(TrimRange, TrimRange)> = Vec::new();
+    for range in ranges {
+        let cut = TrimRange {
+            start: snap_to_keyframe(keyframes, range.start),
+            end: range.end,
+        };
+        match snapped.last_mut() {
+            Some((last_requested, last_cut)) if cut.start <= last_cut.end => {
+                last_requested.end = last_requested.end.max(range.end);
+                last_cut.end = last_cut.end.max(cut.end);
+            }
+            _ => snapped.push((*range, cut)),
+        }
+    }
+    snapped
+}
+
+fn output_format(output: &Path) -> String {
+    output.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+fn audio_only(format: &str) -> bool {
+    media_type::type_for_extension(format) == MediaType::Audio
+}
+
+// Stream selection: every video and audio stream, plus subtitles where the container can take them
+// as they are. Subtitles are always copied: re-encoding bitmap ones (PGS, DVD) to mkv's default ASS
+// fails. Audio containers only get the cover art, copied, and only where they can hold it.
+fn map_args(output: &Path) -> Vec<String> {
+    let format = output_format(output);
+    let mut args = Vec::new();
+    if !audio_only(&format) {
+        args.extend(["-map".to_string(), "0:v?".to_string()]);
+    } else if COVER_ART_FORMATS.contains(&format.as_str()) {
+        args.extend(["-map".to_string(), "0:v?".to_string(), "-c:v".to_string(), "copy".to_string()]);
+    }
+    args.extend(["-map".to_string(), "0:a?".to_string()]);
+    if format == "mkv" {
+        args.extend(["-map".to_string(), "0:s?".to_string(), "-c:s".to_string(), "copy".to_string()]);
+    }
+    args
+}
+
+fn encode_args(options: &TrimOptions, output: &Path) -> Vec<String> {
+    let format = output_format(output);
+    let mut args = Vec::new();
+    if !audio_only(&format) {
+        if let Some(codec) = &options.video_codec {
+            args.extend(["-c:v".to_string(), codec.clone()]);
+        }
+        // Only pass the quality options the encoder understands, like loop_media does
+        let encoder = options.video_codec.as_deref().unwrap_or_else(|| capabilities::default_video_encoder(&format));
+        let rules = capabilities::encoder_rules(encoder);
+        if let (Some(crf), Some(_)) = (options.crf, rules.crf) {
+            args.extend(["-crf".to_string(), crf.to_string()]);
+            if rules.zero_bitrate_for_crf {
+                args.extend(["-b:v".to_string(), "0".to_string()]);
+            }
+        }
+        if let Some(preset) = options.preset.as_ref().filter(|_| !rules.presets.is_empty()) {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+    }
+    if let Some(codec) = &options.audio_codec {
+        args.extend(["-c:a".to_string(), codec.clone()]);
+    }
+    args.extend(["-b:a".to_string(), "192k".to_string()]);
+    args
+}
+
+// Checks the accurate mode encoders against the output container. Codecs left to FFmpeg's
+// defaults are only checked where the default is known.
+fn validate_encode(capabilities: Option<&FfmpegCapabilities>, options: &TrimOptions, output: &Path) -> ValidationReport {
+    let format = output_format(output);
+    // "copy" skips the encoder checks
+    let audio_codec = options.audio_codec.clone().unwrap_or_else(|| "copy".to_string());
+    if audio_only(&format) {
+        let settings = AudioConversionSettings {
+            codec: audio_codec,
+            bitrate: None,
+            sample_rate: None,
+            channels: None,
+            compression: None,
+            extract_from_video: None,
+        };
+        return capabilities::validate_audio(capabilities, &settings, &format);
+    }
+    let video_codec = options
+        .video_codec
+        .clone()
+        .or_else(|| Some(capabilities::default_video_encoder(&format).to_string()).filter(|codec| !codec.is_empty()))
+        .unwrap_or_else(|| "copy".to_string());
+    let settings = ConversionSettings {
+        video_codec,
+        audio_codec,
+        crf: options.crf,
+        preset: options.preset.clone(),
+        bitrate: None,
+        fast_mode: None,
+        target_size_mb: None,
+        max_height: None,
+    };
+    capabilities::validate_video(capabilities, &settings, &format)
+}
+
+// One range into its own file, as `pass` of the job
+#[allow(clippy::too_many_arguments)]
+fn cut_segment(
+    input_path: &str,
+    range: TrimRange,
+    output: &Path,
+    options: &TrimOptions,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    pass: Pass,
+) -> Result<(), String> {
+    let output_str = output.to_string_lossy().to_string();
+    // A stream copy seek lands on the last keyframe before the seek point; nudging past the snapped
+    // keyframe keeps rounding from landing on the one before it
+    let seek = if options.accurate { range.start } else { range.start + 0.001 };
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-y", "-ss", &format!("{:.3}", seek), "-i", input_path]);
+    cmd.args(["-t", &format!("{:.3}", range.end - range.start)]);
+    cmd.args(map_args(output));
+    if options.accurate {
+        cmd.args(encode_args(options, output));
+    } else {
+        // Timestamps start at zero in every part, so the parts also concatenate cleanly
+        cmd.args(["-c", "copy", "-avoid_negative_ts", "make_zero"]);
+    }
+    cmd.arg(&output_str);
+
+    let duration = range.end - range.start;
+    ffmpeg_progress::run_pass(window, jobs, job_id, &mut cmd, vec![output.to_path_buf()], Some(duration), pass)
+}
+
+// All ranges into one file in a single re-encoding pass with trim/atrim and the concat filter
+#[allow(clippy::too_many_arguments)]
+fn cut_joined_accurate(
+    input_path: &str,
+    media: &MediaProbe,
+    ranges: &[TrimRange],
+    output: &Path,
+    options: &TrimOptions,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<(), String> {
+    // Audio containers get the audio alone, as with a single cut
+    let video = media.primary_video().map(|stream| stream.index).filter(|_| !audio_only(&output_format(output)));
+    let audio = media.primary_audio().map(|stream| stream.index);
+
+    let mut filter = String::new();
+    let mut inputs = String::new();
+    for (i, range) in ranges.iter().enumerate() {
+        if let Some(index) = video {
+            filter.push_str(&format!(
+                "[0:{}]trim=start={:.3}:end={:.3},setpts=PTS-STARTPTS[v{}];",
+                index, range.start, range.end, i
+            ));
+            inputs.push_str(&format!("[v{}]", i));
+        }
+        if let Some(index) = audio {
+            filter.push_str(&format!(
+                "[0:{}]atrim=start={:.3}:end={:.3},asetpts=PTS-STARTPTS[a{}];",
+                index, range.start, range.end, i
+            ));
+            inputs.push_str(&format!("[a{}]", i));
+        }
+    }
+    filter.push_str(&format!(
+        "{}concat=n={}:v={}:a={}",
+        inputs,
+        ranges.len(),
+        video.is_some() as u8,
+        audio.is_some() as u8
+    ));
+    if video.is_some() {
+        filter.push_str("[v]");
+    }
+    if audio.is_some() {
+        filter.push_str("[a]");
+    }
+
+    let output_str = output.to_string_lossy().to_string();
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-y", "-i", input_path, "-filter_complex", &filter]);
+    if video.is_some() {
+        cmd.args(["-map", "[v]"]);
+    }
+    if audio.is_some() {
+        cmd.args(["-map", "[a]"]);
+    }
+    cmd.args(encode_args(options, output));
+    cmd.arg(&output_str);
+
+    let duration: f64 = ranges.iter().map(|range| range.end - range.start).sum();
+    ffmpeg_progress::run_job(window, jobs, job_id, &mut cmd, vec![output.to_path_buf()], Some(duration))
+}
+
+// Joins stream-copied parts with the concat demuxer, again without re-encoding
+#[allow(clippy::too_many_arguments)]
+fn join_parts(
+    parts: &[PathBuf],
+    list_path: &Path,
+    output: &Path,
+    duration: f64,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+    pass: Pass,
+) -> Result<(), String> {
+    let list: String = parts.iter().map(|part| concat_list_entry(part)).collect();
+    fs::write(list_path, list).map_err(|e| format!("Failed to write concat list: {}", e))?;
+
+    let output_str = output.to_string_lossy().to_string();
+    let mut cmd = ffmpeg_progress::command();
+    cmd.args(["-y", "-f", "concat", "-safe", "0", "-i"]);
+    cmd.arg(list_path);
+    cmd.args(["-map", "0", "-c", "copy", &output_str]);
+
+    ffmpeg_progress::run_pass(window, jobs, job_id, &mut cmd, vec![output.to_path_buf()], Some(duration), pass)
+}
+
+// Keeps the given time ranges of a file. By default the cuts are stream copied from the nearest
+// keyframe at or before each start; `accurate` re-encodes for frame exact cuts instead.
+#[tauri::command]
+pub async fn trim_media(
+    input_path: String,
+    ranges: Vec<TrimRange>,
+    options: Option<TrimOptions>,
+    job_id: Option<String>,
+    window: Window,
+    jobs: State<'_, JobRegistry>,
+    capabilities: State<'_, CapabilityCache>,
+) -> Result<TrimResult, String> {
+    let job_id = job_id.unwrap_or_else(crate::new_job_id);
+    let _job = jobs.reserve(&job_id)?;
+    let options = options.unwrap_or_default();
+
+    let input = Path::new(&input_path);
+    let stem = input.file_stem().ok_or("Invalid input file")?.to_string_lossy().to_string();
+    let ext = input.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or("mkv".to_string());
+    let output_dir = crate::output_dir_for(input, options.output_directory.as_deref())?;
+    if options.accurate {
+        // Every output keeps the input's extension
+        let sample = output_dir.join(format!("{}_trimmed.{}", stem, ext));
+        validate_encode(capabilities.current().as_ref(), &options, &sample).into_result()?;
+    }
+
+    let media = probe::probe(&input_path)?;
+    let ranges = normalize_ranges(&ranges, media.duration())?;
+
+    // Snap stream copied cuts back to keyframes; audio-only files can be cut anywhere
+    let snapped: Vec<(TrimRange, TrimRange)> = match (options.accurate, media.primary_video()) {
+        (false, Some(video)) => {
+            // Packet timestamps are absolute, while the ranges count from the start of the file
+            // (.ts and many captures don't start at 0)
+            let offset = media.format.start_time.unwrap_or(0.0);
+            let keyframes: Vec<f64> = probe::keyframes(&input_path, video.index)?
+                .into_iter()
+                .map(|keyframe| keyframe - offset)
+                .collect();
+            snap_ranges(&ranges, &keyframes)
+        }
+        _ => ranges.iter().map(|range| (*range, *range)).collect(),
+    };
+    let cut_ranges: Vec<TrimRange> = snapped.iter().map(|(_, cut)| *cut).collect();
+
+    let mut segments: Vec<TrimSegment> = snapped
+        .iter()
+        .map(|(requested, cut)| TrimSegment {
+            requested_start: requested.start,
+            requested_end: requested.end,
+            start: cut.start,
+            end: cut.end,
+            output: None,
+        })
+        .collect();
+
+    if options.join && cut_ranges.len() > 1 {
+        let output = output_dir.join(format!("{}_trimmed.{}", stem, ext));
+        if options.accurate {
+            cut_joined_accurate(&input_path, &media, &cut_ranges, &output, &options, &window, &jobs, &job_id)?;
+        } else {
+            // Stream copy: cut each part to a temporary file, then concatenate them
+            let parts: Vec<PathBuf> = (0..cut_ranges.len())
+                .map(|i| output_dir.join(format!(".{}_trim_{}_{}.{}", stem, job_id, i, ext)))
+                .collect();
+            let list_path = output_dir.join(format!(".{}_trim_{}.txt", stem, job_id));
+            // One pass per part, then the join, which copies all of them again
+            let duration: f64 = cut_ranges.iter().map(|range| range.end - range.start).sum();
+            let weights: Vec<f64> =
+                cut_ranges.iter().map(|range| range.end - range.start).chain(std::iter::once(duration)).collect();
+
+            let result = cut_ranges
+                .iter()
+                .zip(&parts)
+                .enumerate()
+                .try_for_each(|(i, (range, part))| {
+                    let pass = Pass::weighted(&weights, i);
+                    cut_segment(&input_path, *range, part, &options, &window, &jobs, &job_id, pass)
+                })
+                .and_then(|_| {
+                    let pass = Pass::weighted(&weights, cut_ranges.len());
+                    join_parts(&parts, &list_path, &output, duration, &window, &jobs, &job_id, pass)
+                });
+
+            for temp in parts.iter().chain(std::iter::once(&list_path)) {
+                let _ = fs::remove_file(temp);
+            }
+            result?;
+        }
+
+        return Ok(TrimResult {
+            outputs: vec![output.to_string_lossy().to_string()],
+            segments,
+            reencoded: options.accurate,
+        });
+    }
+
+    let weights: Vec<f64> = cut_ranges.iter().map(|range| range.end - range.start).collect();
+    let mut outputs = Vec::new();
+    for (i, (range, segment)) in cut_ranges.iter().zip(segments.iter_mut()).enumerate() {
+        let name = if cut_ranges.len() == 1 {
+            format!("{}_trimmed.{}", stem, ext)
+        } else {
+            format!("{}_part{}.{}", stem, i + 1, ext)
+        };
+        let output = output_dir.join(name);
+        cut_segment(&input_path, *range, &output, &options, &window, &jobs, &job_id, Pass::weighted(&weights, i))?;
+
+        let output_str = output.to_string_lossy().to_string();
+        segment.output = Some(output_str.clone());
+        outputs.push(output_str);
+    }
+
+    Ok(TrimResult {
+        outputs,
+        segments,
+        reencoded: options.accurate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: f64, end: f64) -> TrimRange {
+        TrimRange { start, end }
+    }
+
+    fn bounds(ranges: &[TrimRange]) -> Vec<(f64, f64)> {
+        ranges.iter().map(|range| (range.start, range.end)).collect()
+    }
+
+    #[test]
+    fn ranges_are_sorted_clamped_and_merged() {
+        let ranges = [range(30.0, 40.0), range(0.0, 10.0), range(5.0, 12.0), range(55.0, 90.0)];
+        let normalized = normalize_ranges(&ranges, Some(60.0)).unwrap();
+        assert_eq!(bounds(&normalized), [(0.0, 12.0), (30.0, 40.0), (55.0, 60.0)]);
+    }
+
+    #[test]
+    fn touching_ranges_are_merged_and_tiny_ones_dropped() {
+        let ranges = [range(0.0, 5.0), range(5.0, 8.0), range(59.99, 70.0)];
+        assert_eq!(bounds(&normalize_ranges(&ranges, Some(60.0)).unwrap()), [(0.0, 8.0)]);
+    }
+
+    #[test]
+    fn invalid_ranges_are_refused() {
+        assert!(normalize_ranges(&[range(5.0, 5.0)], None).is_err());
+        assert!(normalize_ranges(&[range(-1.0, 5.0)], None).is_err());
+        assert!(normalize_ranges(&[range(0.0, f64::NAN)], None).is_err());
+        assert!(normalize_ranges(&[range(70.0, 80.0)], Some(60.0)).is_err());
+    }
+
+    #[test]
+    fn cuts_snap_back_to_the_previous_keyframe() {
+        let keyframes = [0.5, 2.0, 4.0, 6.0];
+        assert_eq!(snap_to_keyframe(&keyframes, 3.9), 2.0);
+        assert_eq!(snap_to_keyframe(&keyframes, 4.0), 4.0);
+        // Before the first keyframe, or on one shifted below zero by the start time
+        assert_eq!(snap_to_keyframe(&keyframes, 0.2), 0.0);
+        assert_eq!(snap_to_keyframe(&[-0.04, 2.0], 1.0), 0.0);
+    }
+
+    #[test]
+    fn snapped_ranges_stay_apart_when_they_dont_overlap() {
+        let snapped = snap_ranges(&[range(2.0, 8.0), range(12.0, 15.0), range(21.0, 25.0)], &[0.0, 10.0, 20.0]);
+        let cut: Vec<TrimRange> = snapped.iter().map(|(_, cut)| *cut).collect();
+        assert_eq!(bounds(&cut), [(0.0, 8.0), (10.0, 15.0), (20.0, 25.0)]);
+    }
+
+    #[test]
+    fn ranges_overlapping_after_snapping_are_merged() {
+        // 14-18 snaps back to 10, inside the first range, so the footage would repeat
+        let snapped = snap_ranges(&[range(2.0, 12.0), range(14.0, 18.0)], &[0.0, 10.0, 20.0]);
+        let requested: Vec<TrimRange> = snapped.iter().map(|(requested, _)| *requested).collect();
+        let cut: Vec<TrimRange> = snapped.iter().map(|(_, cut)| *cut).collect();
+        assert_eq!(bounds(&requested), [(2.0, 18.0)]);
+        assert_eq!(bounds(&cut), [(0.0, 18.0)]);
+    }
+
+    #[test]
+    fn accurate_encodes_follow_the_output_container() {
+        let options = TrimOptions { preset: Some("slow".to_string()), ..Default::default() };
+        assert_eq!(encode_args(&options, Path::new("a.mp4")), ["-crf", "18", "-preset", "slow", "-b:a", "192k"]);
+        assert_eq!(encode_args(&options, Path::new("a.webm")), ["-crf", "18", "-b:v", "0", "-b:a", "192k"]);
+        assert_eq!(encode_args(&options, Path::new("a.mp3")), ["-b:a", "192k"]);
+
+        assert_eq!(map_args(Path::new("a.wav")), ["-map", "0:a?"]);
+        assert_eq!(map_args(Path::new("a.mp3")), ["-map", "0:v?", "-c:v", "copy", "-map", "0:a?"]);
+        assert_eq!(map_args(Path::new("a.mkv")), ["-map", "0:v?", "-map", "0:a?", "-map", "0:s?", "-c:s", "copy"]);
+    }
+
+    #[test]
+    fn mismatched_accurate_codecs_are_refused() {
+        assert!(validate_encode(None, &TrimOptions::default(), Path::new("a.webm")).valid);
+        assert!(validate_encode(None, &TrimOptions::default(), Path::new("a.flac")).valid);
+
+        let aac = TrimOptions { audio_codec: Some("aac".to_string()), ..Default::default() };
+        assert!(!validate_encode(None, &aac, Path::new("a.webm")).valid);
+        assert!(!validate_encode(None, &aac, Path::new("a.ogg")).valid);
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use notify_debouncer_full::notify::event::{ModifyKind, RenameMode};
+use notify_debouncer_full::notify::{Event, EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, RecommendedCache};
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::library::MediaLibrary;
+use crate::MediaFile;
+
+// Emitted with a batch of LibraryChange values after the filesystem settles
+pub const CHANGE_EVENT: &str = "media-library-changed";
+
+// How long a path has to stay quiet before its events are delivered. Large copies keep
+// writing for a while, so this also keeps them from flooding the UI with modify events.
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Renamed,
+    Modified,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryChange {
+    pub kind: ChangeKind,
+    pub path: String,
+    // Previous path for renames
+    pub from: Option<String>,
+    pub file: Option<MediaFile>,
+}
+
+// Watches the library roots registered through scan_media_files and keeps the index up to date
+#[derive(Default)]
+pub struct LibraryWatcher {
+    debouncer: Mutex<Option<Debouncer<RecommendedWatcher, RecommendedCache>>>,
+    watched: Mutex<HashSet<String>>,
+}
+
+impl LibraryWatcher {
+    pub fn watch(&self, app: &AppHandle, directory: &str) -> Result<(), String> {
+        let mut watched = self.watched.lock().unwrap();
+        if watched.contains(directory) {
+            return Ok(());
+        }
+
+        let mut debouncer = self.debouncer.lock().unwrap();
+        if debouncer.is_none() {
+            let app = app.clone();
+            let created = new_debouncer(DEBOUNCE_TIMEOUT, None, move |result: DebounceEventResult| {
+                if let Ok(events) = result {
+                    handle_events(&app, events.into_iter().map(|event| event.event).collect());
+                }
+            })
+            .map_err(|e| format!("Failed to start file watcher: {}", e))?;
+            *debouncer = Some(created);
+        }
+
+        debouncer
+            .as_mut()
+            .expect("debouncer was just created")
+            .watch(Path::new(directory), RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", directory, e))?;
+        watched.insert(directory.to_string());
+        Ok(())
+    }
+
+    pub fn unwatch(&self, directory: &str) {
+        if self.watched.lock().unwrap().remove(directory) {
+            if let Some(debouncer) = self.debouncer.lock().unwrap().as_mut() {
+                let _ = debouncer.unwatch(Path::new(directory));
+            }
+        }
+    }
+}
+
+// Re-reads the path from disk and records it in the index, returning the change to report.
+// A path that no longer exists is removed.
+fn refresh_path(library: &MediaLibrary, path: &Path) -> Vec<LibraryChange> {
+    if !path.exists() {
+        return remove_path(library, path);
+    }
+    let Some(root) = library.root_for(path) else {
+        return Vec::new();
+    };
+    if crate::scanner::is_ignored(Path::new(&root.directory), path) {
+        return Vec::new();
+    }
+
+    if path.is_dir() {
+        // A directory was copied or moved in; its files may not get events of their own
+        let files = crate::scanner::scan_directory_recursive(path, &root.extensions)
+            .map(|(files, _)| files)
+            .unwrap_or_default();
+        return files
+            .into_iter()
+            .filter(|file| library.upsert_file(file))
+            .map(|file| LibraryChange {
+                kind: ChangeKind::Added,
+                path: file.path.clone(),
+                from: None,
+                file: Some(file),
+            })
+            .collect();
+    }
+
+    match crate::scanner::media_file_from_path(path, &root.extensions) {
+        Some(file) => {
+            let existed = library.contains(&file.path);
+            if !library.upsert_file(&file) {
+                return Vec::new();
+            }
+            vec![LibraryChange {
+                kind: if existed { ChangeKind::Modified } else { ChangeKind::Added },
+                path: file.path.clone(),
+                from: None,
+                file: Some(file),
+            }]
+        }
+        None => Vec::new(),
+    }
+}
+
+fn remove_path(library: &MediaLibrary, path: &Path) -> Vec<LibraryChange> {
+    if library.remove_path(path) == 0 {
+        return Vec::new();
+    }
+    vec![LibraryChange {
+        kind: ChangeKind::Removed,
+        path: path.to_string_lossy().to_string(),
+        from: None,
+        file: None,
+    }]
+}
+
+fn handle_events(app: &AppHandle, events: Vec<Event>) {
+    let library = app.state::<MediaLibrary>();
+    let changes = apply_events(&library, events);
+    if changes.is_empty() {
+        return;
+    }
+    if changes.iter().any(|change| change.file.is_some()) {
+        library.spawn_probe(app);
+    }
+    let _ = app.emit_all(CHANGE_EVENT, changes);
+}
+
+// Records a batch of filesystem events in the index and returns the changes to report
+fn apply_events(library: &MediaLibrary, events: Vec<Event>) -> Vec<LibraryChange> {
+    let mut changes = Vec::new();
+    // The debouncer can report several events for the same path in one batch, e.g. a remove and a
+    // create for an atomic save. Each path is refreshed once from disk after the batch, in order,
+    // so it ends up as it is now rather than as its first event said.
+    let mut touched: Vec<PathBuf> = Vec::new();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+
+    for event in events {
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                let (from, to) = (&event.paths[0], &event.paths[1]);
+
+                // Only keep the entry when the new name still qualifies: renamed to a.txt or to an ignored
+                // name means the file left the library
+                let root = library
+                    .root_for(to)
+                    .filter(|root| !crate::scanner::is_ignored(Path::new(&root.directory), to));
+                let file = root.as_ref().and_then(|root| crate::scanner::media_file_from_path(to, &root.extensions));
+                let tracked = root.is_some() && (file.is_some() || to.is_dir());
+                if tracked && library.rename_path(from, to, file.as_ref()) > 0 {
+                    changes.push(LibraryChange {
+                        kind: ChangeKind::Renamed,
+                        path: to.to_string_lossy().to_string(),
+                        from: Some(from.to_string_lossy().to_string()),
+                        file,
+                    });
+                } else if tracked {
+                    // Renamed from a non-media name (e.g. "video.mp4.part") into something we track
+                    changes.extend(refresh_path(library, to));
+                } else {
+                    changes.extend(remove_path(library, from));
+                }
+            }
+            EventKind::Remove(_) | EventKind::Create(_) | EventKind::Modify(_) => {
+                for path in event.paths {
+                    if seen.insert(path.clone()) {
+                        touched.push(path);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for path in touched {
+        changes.extend(refresh_path(library, &path));
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify_debouncer_full::notify::event::{CreateKind, RemoveKind};
+    use std::fs;
+
+    // Bytes without a known signature, so the type comes from the extension
+    const CONTENT: [u8; 64] = [0x80; 64];
+
+    fn library(root: &Path) -> MediaLibrary {
+        let library = MediaLibrary::in_memory();
+        let extensions = ["mp4".to_string(), "mp3".to_string()];
+        library.sync_directory(&root.to_string_lossy(), &extensions, &[], &[]).unwrap();
+        library
+    }
+
+    fn kinds(changes: &[LibraryChange]) -> Vec<ChangeKind> {
+        changes.iter().map(|change| change.kind).collect()
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> Event {
+        paths.iter().fold(Event::new(kind), |event, path| event.add_path(path.to_path_buf()))
+    }
+
+    #[test]
+    fn refresh_path_follows_the_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = library(dir.path());
+        let clip = dir.path().join("clip.mp4");
+
+        fs::write(&clip, CONTENT).unwrap();
+        assert_eq!(kinds(&refresh_path(&library, &clip)), [ChangeKind::Added]);
+        // Nothing changed on disk
+        assert!(refresh_path(&library, &clip).is_empty());
+
+        fs::write(&clip, [CONTENT, CONTENT].concat()).unwrap();
+        assert_eq!(kinds(&refresh_path(&library, &clip)), [ChangeKind::Modified]);
+
+        fs::remove_file(&clip).unwrap();
+        assert_eq!(kinds(&refresh_path(&library, &clip)), [ChangeKind::Removed]);
+        assert!(!library.contains(&clip.to_string_lossy()));
+
+        // Other extensions, ignored files and paths outside the roots stay out
+        let text = dir.path().join("notes.txt");
+        fs::write(&text, "notes").unwrap();
+        assert!(refresh_path(&library, &text).is_empty());
+        fs::write(dir.path().join(crate::scanner::IGNORE_FILE), "skip.mp4\n").unwrap();
+        let skipped = dir.path().join("skip.mp4");
+        fs::write(&skipped, CONTENT).unwrap();
+        assert!(refresh_path(&library, &skipped).is_empty());
+        let outside = tempfile::tempdir().unwrap();
+        let stray = outside.path().join("stray.mp4");
+        fs::write(&stray, CONTENT).unwrap();
+        assert!(refresh_path(&library, &stray).is_empty());
+    }
+
+    #[test]
+    fn refresh_path_adds_the_files_of_a_directory_moved_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = library(dir.path());
+        let album = dir.path().join("album");
+        fs::create_dir_all(album.join("disc 2")).unwrap();
+        fs::write(album.join("a.mp3"), CONTENT).unwrap();
+        fs::write(album.join("disc 2").join("b.mp3"), CONTENT).unwrap();
+
+        let changes = refresh_path(&library, &album);
+        assert_eq!(kinds(&changes), [ChangeKind::Added, ChangeKind::Added]);
+        assert!(library.contains(&album.join("disc 2").join("b.mp3").to_string_lossy()));
+    }
+
+    #[test]
+    fn remove_and_create_in_one_batch_keeps_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = library(dir.path());
+        let clip = dir.path().join("clip.mp4");
+        fs::write(&clip, CONTENT).unwrap();
+        apply_events(&library, vec![event(EventKind::Create(CreateKind::File), &[&clip])]);
+
+        // An atomic save: the old file is removed and the new one created under the same name
+        fs::write(&clip, [CONTENT, CONTENT].concat()).unwrap();
+        let changes = apply_events(
+            &library,
+            vec![
+                event(EventKind::Remove(RemoveKind::File), &[&clip]),
+                event(EventKind::Create(CreateKind::File), &[&clip]),
+            ],
+        );
+        assert_eq!(kinds(&changes), [ChangeKind::Modified]);
+        assert!(library.contains(&clip.to_string_lossy()));
+    }
+
+    #[test]
+    fn renames_move_the_entry_and_retype_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = library(dir.path());
+        let rename = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        let video = dir.path().join("clip.mp4");
+        let audio = dir.path().join("clip.mp3");
+        fs::write(&video, CONTENT).unwrap();
+        apply_events(&library, vec![event(EventKind::Create(CreateKind::File), &[&video])]);
+
+        fs::rename(&video, &audio).unwrap();
+        let changes = apply_events(&library, vec![event(rename, &[&video, &audio])]);
+        assert_eq!(kinds(&changes), [ChangeKind::Renamed]);
+        assert_eq!(changes[0].from.as_deref(), Some(&*video.to_string_lossy()));
+        let entries = library.query(&Default::default());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, audio.to_string_lossy());
+        assert_eq!((entries[0].extension.as_str(), entries[0].file_type.as_str()), ("mp3", "audio"));
+
+        // Renamed to something the root doesn't track, the file leaves the library
+        let text = dir.path().join("clip.txt");
+        fs::rename(&audio, &text).unwrap();
+        assert_eq!(kinds(&apply_events(&library, vec![event(rename, &[&audio, &text])])), [ChangeKind::Removed]);
+
+        // A finished download is renamed from a name nobody tracked
+        let partial = dir.path().join("clip.mp4.part");
+        fs::write(&partial, CONTENT).unwrap();
+        fs::rename(&partial, &video).unwrap();
+        assert_eq!(kinds(&apply_events(&library, vec![event(rename, &[&partial, &video])])), [ChangeKind::Added]);
+    }
+}
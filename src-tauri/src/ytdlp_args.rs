@@ -0,0 +1,290 @@
+use serde::Serialize;
+
+// A single custom argument together with where it started in the original string,
+// so errors can point at the exact token
+#[derive(Debug, Clone, Serialize)]
+pub struct ArgToken {
+    pub value: String,
+    // Zero-based index among the tokens
+    pub index: usize,
+    // Character offset of the token in the input
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArgWarning {
+    pub token: String,
+    pub index: usize,
+    pub position: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CustomArgsReport {
+    pub args: Vec<String>,
+    pub warnings: Vec<ArgWarning>,
+}
+
+// Options that make yt-dlp run other programs or load code, with the reason shown to the user
+const BLOCKED_OPTIONS: &[(&str, &str)] = &[
+    ("--exec", "runs arbitrary shell commands"),
+    ("--exec-before-download", "runs arbitrary shell commands"),
+    ("--netrc-cmd", "runs an arbitrary shell command to get credentials"),
+    ("--downloader", "runs an external program as the downloader"),
+    ("--external-downloader", "runs an external program as the downloader"),
+    ("--ffmpeg-location", "replaces the ffmpeg binary yt-dlp executes"),
+    ("--use-postprocessor", "loads and runs post-processors, including Exec"),
+    ("--plugin-dirs", "loads Python plugins from arbitrary directories"),
+    ("--config-location", "loads options from a file, which may contain --exec"),
+    ("--config-locations", "loads options from a file, which may contain --exec"),
+    ("--alias", "defines option aliases that can expand to blocked options"),
+    ("--update", "replaces the installed yt-dlp binary"),
+    ("--update-to", "replaces the installed yt-dlp binary"),
+    ("-U", "replaces the installed yt-dlp binary"),
+];
+
+// Options that are allowed but usually conflict with what the app sets up itself
+const WARNED_OPTIONS: &[(&str, &str)] = &[
+    ("-o", "overrides the output template chosen by the app"),
+    ("--output", "overrides the output template chosen by the app"),
+    ("-P", "overrides the output directory chosen by the app"),
+    ("--paths", "overrides the output directory chosen by the app"),
+    ("-a", "reads additional URLs from a file"),
+    ("--batch-file", "reads additional URLs from a file"),
+    ("--load-info-json", "loads video information from a local file"),
+    ("--cookies-from-browser", "reads cookies from your browser profile"),
+    ("--postprocessor-args", "passes raw arguments to ffmpeg"),
+    ("--ppa", "passes raw arguments to ffmpeg"),
+    ("--downloader-args", "passes raw arguments to an external downloader"),
+    ("--external-downloader-args", "passes raw arguments to an external downloader"),
+    ("--no-check-certificates", "disables HTTPS certificate validation"),
+    ("--print-to-file", "writes to an arbitrary file path"),
+    ("--progress-template", "changes the output the progress events are parsed from"),
+    ("--quiet", "hides the output the progress events are parsed from"),
+    ("-q", "hides the output the progress events are parsed from"),
+];
+
+// Short options that take a value; in a group like "-xfbest" everything after one of these is its value
+const SHORT_OPTIONS_WITH_VALUE: &[char] = &['a', 'f', 'I', 'N', 'o', 'p', 'P', 'r', 'R', 'S', 't', 'u', '2'];
+
+// Splits a string into arguments the way a POSIX shell would: whitespace separates arguments,
+// single quotes keep everything literally, double quotes allow \" and \\ escapes, and a
+// backslash outside quotes escapes the next character. No variable or glob expansion happens.
+pub fn tokenize(input: &str) -> Result<Vec<ArgToken>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().enumerate();
+
+    let mut current = String::new();
+    let mut start: Option<usize> = None;
+
+    while let Some((position, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(start) = start.take() {
+                    tokens.push(ArgToken {
+                        value: std::mem::take(&mut current),
+                        index: tokens.len(),
+                        position: start,
+                    });
+                }
+            }
+            '\'' => {
+                start.get_or_insert(position);
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => current.push(c),
+                        None => return Err(format!("Unterminated single quote starting at character {}", position + 1)),
+                    }
+                }
+            }
+            '"' => {
+                start.get_or_insert(position);
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped @ ('"' | '\\' | '$' | '`'))) => current.push(escaped),
+                            Some((_, other)) => {
+                                current.push('\\');
+                                current.push(other);
+                            }
+                            None => return Err(format!("Unterminated double quote starting at character {}", position + 1)),
+                        },
+                        Some((_, c)) => current.push(c),
+                        None => return Err(format!("Unterminated double quote starting at character {}", position + 1)),
+                    }
+                }
+            }
+            '\\' => {
+                start.get_or_insert(position);
+                match chars.next() {
+                    Some((_, escaped)) => current.push(escaped),
+                    None => return Err(format!("Trailing backslash at character {}", position + 1)),
+                }
+            }
+            c => {
+                start.get_or_insert(position);
+                current.push(c);
+            }
+        }
+    }
+
+    if let Some(start) = start {
+        tokens.push(ArgToken {
+            value: current,
+            index: tokens.len(),
+            position: start,
+        });
+    }
+
+    Ok(tokens)
+}
+
+// Looks an option token up in a table. Long options may be written as "--opt=value" and,
+// like yt-dlp itself accepts, as an unambiguous prefix ("--exe" for "--exec").
+fn lookup<'a>(token: &str, table: &'a [(&'a str, &'a str)]) -> Option<(&'a str, &'a str)> {
+    if let Some(long) = token.strip_prefix("--") {
+        let name = long.split('=').next().unwrap_or(long);
+        if name.is_empty() {
+            return None;
+        }
+        let exact = table.iter().find(|(option, _)| option.strip_prefix("--") == Some(name));
+        if exact.is_some() {
+            return exact.copied();
+        }
+        // Abbreviations shorter than three letters are ambiguous for yt-dlp anyway
+        if name.len() >= 3 {
+            return table
+                .iter()
+                .find(|(option, _)| option.strip_prefix("--").is_some_and(|option| option.starts_with(name)))
+                .copied();
+        }
+        return None;
+    }
+
+    // Short options are matched exactly; groups are split up by short_options() first
+    if token.starts_with('-') && token.len() == 2 {
+        return table.iter().find(|(option, _)| *option == token).copied();
+    }
+
+    None
+}
+
+// The options a token stands for. Short options can be grouped like optparse allows ("-xU" is
+// "-x -U"), and one taking a value swallows the rest of the group ("-ofile").
+fn short_options(token: &str) -> Vec<String> {
+    let Some(group) = token.strip_prefix('-').filter(|group| !group.is_empty() && !group.starts_with('-')) else {
+        return vec![token.to_string()];
+    };
+    let mut options = Vec::new();
+    for c in group.chars() {
+        options.push(format!("-{}", c));
+        if SHORT_OPTIONS_WITH_VALUE.contains(&c) {
+            break;
+        }
+    }
+    options
+}
+
+// Tokenises and checks custom arguments. Blocked options are refused with an error naming
+// the offending token; risky ones are let through with a warning.
+pub fn parse_custom_args(input: &str) -> Result<CustomArgsReport, String> {
+    let tokens = tokenize(input)?;
+    let mut warnings = Vec::new();
+
+    // Every token starting with a dash is checked, even when it could be the value of the previous
+    // option, so something like "-f --exec ..." can't sneak through
+    for token in &tokens {
+        for name in short_options(&token.value) {
+            if let Some((option, reason)) = lookup(&name, BLOCKED_OPTIONS) {
+                return Err(format!(
+                    "Custom argument #{} \"{}\" (at character {}) is not allowed: {} {}",
+                    token.index + 1,
+                    token.value,
+                    token.position + 1,
+                    option,
+                    reason
+                ));
+            }
+
+            if let Some((option, reason)) = lookup(&name, WARNED_OPTIONS) {
+                warnings.push(ArgWarning {
+                    token: token.value.clone(),
+                    index: token.index,
+                    position: token.position,
+                    message: format!("{} {}", option, reason),
+                });
+            }
+        }
+    }
+
+    Ok(CustomArgsReport {
+        args: tokens.into_iter().map(|token| token.value).collect(),
+        warnings,
+    })
+}
+
+// Lets the frontend check custom arguments while the user types them
+#[tauri::command]
+pub fn ytdlp_validate_args(custom_args: String) -> Result<CustomArgsReport, String> {
+    parse_custom_args(&custom_args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(input: &str) -> Vec<String> {
+        tokenize(input).unwrap().into_iter().map(|token| token.value).collect()
+    }
+
+    #[test]
+    fn tokenizes_like_a_shell() {
+        assert_eq!(values("  -f  best "), ["-f", "best"]);
+        assert_eq!(values(r#"--user-agent "Mozilla 5.0" -o 'a b'"#), ["--user-agent", "Mozilla 5.0", "-o", "a b"]);
+        assert_eq!(values(r#""say \"hi\"" 'no \escape' a\ b"#), [r#"say "hi""#, r"no \escape", "a b"]);
+        assert_eq!(values(r#"pre"quoted"post ''"#), ["prequotedpost", ""]);
+    }
+
+    #[test]
+    fn token_positions() {
+        let tokens = tokenize("-x  'two words'").unwrap();
+        assert_eq!((tokens[1].index, tokens[1].position), (1, 4));
+    }
+
+    #[test]
+    fn unterminated_input_is_an_error() {
+        assert!(tokenize("'open").is_err());
+        assert!(tokenize("\"open").is_err());
+        assert!(tokenize("trailing\\").is_err());
+    }
+
+    #[test]
+    fn blocked_options_are_refused() {
+        for input in ["--exec 'rm -rf ~'", "--exec=echo", "--exe echo", "-U", "-f best --netrc-cmd x"] {
+            assert!(parse_custom_args(input).is_err(), "{} was let through", input);
+        }
+        assert!(parse_custom_args("--ex echo").is_ok());
+    }
+
+    #[test]
+    fn grouped_short_options_are_checked() {
+        assert!(parse_custom_args("-xU").is_err());
+        assert!(parse_custom_args("-iwU").is_err());
+        // Everything after -o is its value
+        assert!(parse_custom_args("-oUpload.mp4").is_ok());
+        let report = parse_custom_args("-xo out.mp4").unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].message.starts_with("-o "));
+    }
+
+    #[test]
+    fn risky_options_warn() {
+        let report = parse_custom_args("--print-to-file title /etc/x --no-check-certificates").unwrap();
+        assert_eq!(report.args.len(), 4);
+        let messages: Vec<&str> = report.warnings.iter().map(|warning| warning.message.as_str()).collect();
+        assert!(messages[0].starts_with("--print-to-file"));
+        assert!(messages[1].starts_with("--no-check-certificates"));
+    }
+}
@@ -0,0 +1,379 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Serialize;
+use tauri::Window;
+
+use crate::jobs::{self, JobRegistry, ProcessOutput};
+
+// Event emitted to the window for every parsed yt-dlp progress update
+pub const PROGRESS_EVENT: &str = "ytdlp-progress";
+
+// Prefix of the machine readable lines produced by PROGRESS_TEMPLATE
+const TEMPLATE_PREFIX: &str = "yeyo-progress|";
+
+// Progress template passed to yt-dlp so we don't have to scrape the human readable
+// "[download]  42.0% of 10.00MiB at 1.00MiB/s ETA 00:05" line. Missing fields are printed as "NA".
+pub const PROGRESS_TEMPLATE: &str = "download:yeyo-progress|%(progress.downloaded_bytes)s|%(progress.total_bytes)s|%(progress.total_bytes_estimate)s|%(progress.speed)s|%(progress.eta)s|%(progress.fragment_index)s|%(progress.fragment_count)s|%(info.playlist_index)s|%(info.n_entries)s";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadPhase {
+    Starting,
+    Downloading,
+    Merging,
+    Postprocessing,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadProgress {
+    pub job_id: String,
+    pub phase: DownloadPhase,
+    pub percent: Option<f64>,
+    pub downloaded_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    // Bytes per second
+    pub speed: Option<f64>,
+    // Seconds remaining for the current file
+    pub eta: Option<u64>,
+    pub fragment_index: Option<u32>,
+    pub fragment_count: Option<u32>,
+    pub playlist_index: Option<u32>,
+    pub playlist_count: Option<u32>,
+    pub filename: Option<String>,
+    // Post-processor name ("Merger", "ExtractAudio", ...) or final error text
+    pub message: Option<String>,
+}
+
+impl DownloadProgress {
+    fn new(job_id: &str, phase: DownloadPhase) -> Self {
+        DownloadProgress {
+            job_id: job_id.to_string(),
+            phase,
+            percent: None,
+            downloaded_bytes: None,
+            total_bytes: None,
+            speed: None,
+            eta: None,
+            fragment_index: None,
+            fragment_count: None,
+            playlist_index: None,
+            playlist_count: None,
+            filename: None,
+            message: None,
+        }
+    }
+}
+
+// Keeps the state that yt-dlp only prints once (current playlist entry, destination file)
+// so every emitted event carries the full picture.
+pub struct ProgressParser {
+    job_id: String,
+    playlist_index: Option<u32>,
+    playlist_count: Option<u32>,
+    filename: Option<String>,
+    // Files of the current entry that are incomplete until yt-dlp exits (fragments, .part files, merge target)
+    partial_files: Vec<PathBuf>,
+}
+
+impl ProgressParser {
+    pub fn new(job_id: &str) -> Self {
+        ProgressParser {
+            job_id: job_id.to_string(),
+            playlist_index: None,
+            playlist_count: None,
+            filename: None,
+            partial_files: Vec::new(),
+        }
+    }
+
+    fn track_file(&mut self, path: &str) {
+        let path = path.trim().trim_matches('"');
+        for suffix in ["", ".part", ".ytdl"] {
+            self.partial_files.push(PathBuf::from(format!("{}{}", path, suffix)));
+        }
+    }
+
+    pub fn event(&self, phase: DownloadPhase) -> DownloadProgress {
+        let mut progress = DownloadProgress::new(&self.job_id, phase);
+        progress.playlist_index = self.playlist_index;
+        progress.playlist_count = self.playlist_count;
+        progress.filename = self.filename.clone();
+        progress
+    }
+
+    pub fn parse_line(&mut self, line: &str) -> Option<DownloadProgress> {
+        let line = line.trim();
+
+        if let Some(rest) = line.strip_prefix(TEMPLATE_PREFIX) {
+            return self.parse_template_line(rest);
+        }
+
+        if let Some(rest) = line.strip_prefix("[download]") {
+            return self.parse_download_line(rest.trim());
+        }
+
+        // Post-processor lines look like "[Merger] Merging formats into ..." or "[ExtractAudio] Destination: ..."
+        if line.starts_with('[') {
+            let end = line.find(']')?;
+            let name = &line[1..end];
+            let detail = line[end + 1..].trim();
+            if let Some(target) = detail
+                .strip_prefix("Merging formats into ")
+                .or_else(|| detail.strip_prefix("Destination:"))
+            {
+                self.track_file(target);
+            }
+            let phase = match name {
+                "Merger" => DownloadPhase::Merging,
+                "ExtractAudio" | "EmbedSubtitle" | "EmbedThumbnail" | "Metadata" | "VideoConvertor"
+                | "VideoRemuxer" | "ModifyChapters" | "SponsorBlock" | "SplitChapters" | "FixupM3u8"
+                | "FixupM4a" | "FixupStretched" | "FixupDuplicateMoov" | "FixupTimestamp" | "MoveFiles"
+                | "ThumbnailsConvertor" | "SubtitlesConvertor" | "ffmpeg" => DownloadPhase::Postprocessing,
+                _ => return None,
+            };
+            let mut progress = self.event(phase);
+            progress.message = Some(name.to_string());
+            return Some(progress);
+        }
+
+        None
+    }
+
+    fn parse_template_line(&mut self, rest: &str) -> Option<DownloadProgress> {
+        let fields: Vec<&str> = rest.split('|').collect();
+        if fields.len() < 9 {
+            return None;
+        }
+
+        let downloaded = parse_number::<u64>(fields[0]);
+        let total = parse_number::<u64>(fields[1]).or_else(|| parse_number::<f64>(fields[2]).map(|t| t as u64));
+
+        if let Some(index) = parse_number::<u32>(fields[7]) {
+            self.playlist_index = Some(index);
+        }
+        if let Some(count) = parse_number::<u32>(fields[8]) {
+            self.playlist_count = Some(count);
+        }
+
+        let mut progress = self.event(DownloadPhase::Downloading);
+        progress.downloaded_bytes = downloaded;
+        progress.total_bytes = total;
+        progress.speed = parse_number::<f64>(fields[3]);
+        progress.eta = parse_number::<f64>(fields[4]).map(|eta| eta as u64);
+        progress.fragment_index = parse_number::<u32>(fields[5]);
+        progress.fragment_count = parse_number::<u32>(fields[6]);
+        progress.percent = match (downloaded, total) {
+            (Some(done), Some(total)) if total > 0 => Some((done as f64 / total as f64 * 100.0).min(100.0)),
+            _ => None,
+        };
+
+        Some(progress)
+    }
+
+    fn parse_download_line(&mut self, rest: &str) -> Option<DownloadProgress> {
+        // "Downloading item 3 of 10" (older releases say "Downloading video 3 of 10")
+        if let Some(entry) = rest
+            .strip_prefix("Downloading item ")
+            .or_else(|| rest.strip_prefix("Downloading video "))
+        {
+            if let Some((index, count)) = entry.split_once(" of ") {
+                if let (Some(index), Some(count)) = (parse_number::<u32>(index), parse_number::<u32>(count)) {
+                    self.playlist_index = Some(index);
+                    self.playlist_count = Some(count);
+                    self.filename = None;
+                    // Earlier entries are complete, only the new one is partial from here on
+                    self.partial_files.clear();
+                    return Some(self.event(DownloadPhase::Starting));
+                }
+            }
+            return None;
+        }
+
+        if let Some(destination) = rest.strip_prefix("Destination:") {
+            self.filename = Some(destination.trim().to_string());
+            self.track_file(destination);
+            return Some(self.event(DownloadPhase::Downloading));
+        }
+
+        if rest.ends_with("has already been downloaded") {
+            let mut progress = self.event(DownloadPhase::Downloading);
+            progress.percent = Some(100.0);
+            return Some(progress);
+        }
+
+        // Fallback for the default human readable line, e.g. when custom args override the template
+        if let Some((percent, _)) = rest.split_once('%') {
+            if let Some(percent) = parse_number::<f64>(percent) {
+                let mut progress = self.event(DownloadPhase::Downloading);
+                progress.percent = Some(percent);
+                if let Some((_, eta)) = rest.split_once("ETA ") {
+                    progress.eta = parse_clock(eta.split_whitespace().next().unwrap_or(""));
+                }
+                if let Some((_, frag)) = rest.split_once("(frag ") {
+                    if let Some((index, count)) = frag.trim_end_matches(')').split_once('/') {
+                        progress.fragment_index = parse_number::<u32>(index);
+                        progress.fragment_count = parse_number::<u32>(count);
+                    }
+                }
+                return Some(progress);
+            }
+        }
+
+        None
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Option<T> {
+    let value = value.trim();
+    if value.is_empty() || value == "NA" || value == "None" {
+        return None;
+    }
+    value.parse::<T>().ok()
+}
+
+// Parses "MM:SS" or "HH:MM:SS" into seconds
+fn parse_clock(value: &str) -> Option<u64> {
+    let mut seconds = 0u64;
+    for part in value.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(seconds)
+}
+
+// Runs yt-dlp as a registered job, emitting a progress event for every recognised stdout line
+pub fn run_with_progress(
+    cmd: &mut Command,
+    window: &Window,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> std::io::Result<ProcessOutput> {
+    cmd.args(["--newline", "--progress-template", PROGRESS_TEMPLATE]);
+
+    let mut parser = ProgressParser::new(job_id);
+    let _ = window.emit(PROGRESS_EVENT, parser.event(DownloadPhase::Starting));
+
+    let mut output = jobs.run(job_id, cmd, Vec::new(), |line| {
+        let tracked = parser.partial_files.len();
+        if let Some(progress) = parser.parse_line(line) {
+            let _ = window.emit(PROGRESS_EVENT, progress);
+        }
+        if parser.partial_files.len() != tracked {
+            jobs.set_outputs(job_id, parser.partial_files.clone());
+        }
+    })?;
+
+    // Keep the returned output readable by leaving out the per-tick template lines
+    output.stdout = output
+        .stdout
+        .lines()
+        .filter(|line| !line.starts_with(TEMPLATE_PREFIX))
+        .map(|line| format!("{}\n", line))
+        .collect();
+
+    Ok(output)
+}
+
+// Emits the terminal event once the caller has decided the outcome (a failed first attempt may still be retried)
+pub fn emit_result(window: &Window, job_id: &str, result: &Result<String, String>) {
+    let parser = ProgressParser::new(job_id);
+    let progress = match result {
+        Ok(_) => {
+            let mut progress = parser.event(DownloadPhase::Finished);
+            progress.percent = Some(100.0);
+            progress
+        }
+        Err(e) if *e == jobs::cancelled_error(job_id) => parser.event(DownloadPhase::Cancelled),
+        Err(e) => {
+            let mut progress = parser.event(DownloadPhase::Failed);
+            progress.message = Some(
+                e.lines()
+                    .rev()
+                    .find(|l| l.starts_with("ERROR"))
+                    .unwrap_or(e.as_str())
+                    .to_string(),
+            );
+            progress
+        }
+    };
+    let _ = window.emit(PROGRESS_EVENT, progress);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_line_gives_bytes_and_percent() {
+        let mut parser = ProgressParser::new("job");
+        let progress = parser
+            .parse_line("yeyo-progress|500|1000|NA|2048.5|12|3|10|2|5")
+            .unwrap();
+        assert_eq!(progress.phase, DownloadPhase::Downloading);
+        assert_eq!(progress.downloaded_bytes, Some(500));
+        assert_eq!(progress.total_bytes, Some(1000));
+        assert_eq!(progress.percent, Some(50.0));
+        assert_eq!(progress.speed, Some(2048.5));
+        assert_eq!(progress.eta, Some(12));
+        assert_eq!((progress.fragment_index, progress.fragment_count), (Some(3), Some(10)));
+        assert_eq!((progress.playlist_index, progress.playlist_count), (Some(2), Some(5)));
+    }
+
+    #[test]
+    fn template_line_falls_back_to_the_estimate() {
+        let mut parser = ProgressParser::new("job");
+        let progress = parser.parse_line("yeyo-progress|250|NA|1000.0|NA|NA|NA|NA|NA|NA").unwrap();
+        assert_eq!(progress.total_bytes, Some(1000));
+        assert_eq!(progress.percent, Some(25.0));
+        assert_eq!(progress.speed, None);
+    }
+
+    #[test]
+    fn playlist_entry_is_kept_for_later_lines() {
+        let mut parser = ProgressParser::new("job");
+        let starting = parser.parse_line("[download] Downloading item 3 of 10").unwrap();
+        assert_eq!(starting.phase, DownloadPhase::Starting);
+        parser.parse_line("[download] Destination: /tmp/clip.mp4").unwrap();
+
+        let progress = parser.parse_line("yeyo-progress|1|2|NA|NA|NA|NA|NA|NA|NA").unwrap();
+        assert_eq!((progress.playlist_index, progress.playlist_count), (Some(3), Some(10)));
+        assert_eq!(progress.filename.as_deref(), Some("/tmp/clip.mp4"));
+        assert!(parser.partial_files.contains(&PathBuf::from("/tmp/clip.mp4.part")));
+    }
+
+    #[test]
+    fn human_readable_line_is_parsed() {
+        let mut parser = ProgressParser::new("job");
+        let progress = parser
+            .parse_line("[download]  42.0% of ~10.00MiB at 1.00MiB/s ETA 01:05 (frag 4/20)")
+            .unwrap();
+        assert_eq!(progress.percent, Some(42.0));
+        assert_eq!(progress.eta, Some(65));
+        assert_eq!((progress.fragment_index, progress.fragment_count), (Some(4), Some(20)));
+    }
+
+    #[test]
+    fn postprocessor_lines_set_the_phase() {
+        let mut parser = ProgressParser::new("job");
+        let merging = parser.parse_line("[Merger] Merging formats into \"/tmp/out.mkv\"").unwrap();
+        assert_eq!(merging.phase, DownloadPhase::Merging);
+        assert!(parser.partial_files.contains(&PathBuf::from("/tmp/out.mkv")));
+
+        let extract = parser.parse_line("[ExtractAudio] Destination: /tmp/out.mp3").unwrap();
+        assert_eq!(extract.phase, DownloadPhase::Postprocessing);
+        assert_eq!(extract.message.as_deref(), Some("ExtractAudio"));
+        assert!(parser.parse_line("[youtube] abc: Downloading webpage").is_none());
+    }
+
+    #[test]
+    fn clock_values() {
+        assert_eq!(parse_clock("05"), Some(5));
+        assert_eq!(parse_clock("01:05"), Some(65));
+        assert_eq!(parse_clock("1:00:00"), Some(3600));
+        assert_eq!(parse_clock("Unknown"), None);
+    }
+}
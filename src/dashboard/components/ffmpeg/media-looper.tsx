@@ -243,7 +243,7 @@ export function MediaLooper() {
 
       // Call loop_media with selected output directory
       const effectiveTarget = getEffectiveTargetDuration();
-      const outputPath = await invoke<string>('loop_media', {
+      const { output: outputPath } = await invoke<{ output: string; loops: number }>('loop_media', {
         inputPath: selectedFile.path,
         outputDirectory: outputDirectory, // Use selected directory or empty for same directory as input
         targetDuration: effectiveTarget